@@ -0,0 +1,61 @@
+//! Write an email, as a client would receive it, to an `.eml` file without sending it.
+//!
+//! Usage: `preview <email ID> <client ID> [output.eml]`. The message is written to stdout
+//! when no output file is given, or when it is `-`.
+
+use std::io::Write;
+
+use color_eyre::eyre::{bail, ContextCompat, Result};
+use tracing_error::ErrorLayer;
+use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+
+use sequoia::{
+    client::Client,
+    db::DB,
+    email::{Email, TemplateStore},
+};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    init()?;
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (email_id, client_id, output) = match args.as_slice() {
+        [email_id, client_id] => (email_id, client_id, None),
+        [email_id, client_id, output] => (email_id, client_id, Some(output)),
+        _ => bail!("Usage: preview <email ID> <client ID> [output.eml]"),
+    };
+
+    let db = DB::connect().await?;
+
+    let email = Email::get_one(email_id, &db)
+        .await?
+        .with_context(|| format!("Email {email_id} doesn't exist"))?;
+    let client = Client::get_one(client_id.clone(), &db)
+        .await?
+        .with_context(|| format!("Client {client_id} doesn't exist"))?;
+
+    let message = email.render_for(&client, &TemplateStore::from_env())?;
+
+    match output.map(String::as_str) {
+        None | Some("-") => std::io::stdout().write_all(&message.formatted())?,
+        Some(path) => std::fs::write(path, message.formatted())?,
+    }
+
+    Ok(())
+}
+
+fn init() -> Result<()> {
+    color_eyre::install()?;
+    // The configuration can come from the environment only
+    dotenvy::dotenv().ok();
+
+    // Logs go to stderr, so that stdout only holds the message
+    tracing_subscriber::registry()
+        .with(fmt::layer().with_writer(std::io::stderr))
+        .with(ErrorLayer::default())
+        .with(EnvFilter::from_default_env())
+        .init();
+
+    Ok(())
+}
@@ -0,0 +1,61 @@
+//! Sync the email definitions of a directory into the database.
+//!
+//! Usage: `sync <directory> [--diff]`. With `--diff`, the changes are only printed.
+
+use color_eyre::eyre::{bail, Result};
+use tracing_error::ErrorLayer;
+use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+
+use sequoia::{db::DB, email::EmailDefinition};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    init()?;
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (dir, dry_run) = match args.as_slice() {
+        [dir] => (dir, false),
+        [dir, flag] if flag == "--diff" => (dir, true),
+        _ => bail!("Usage: sync <directory> [--diff]"),
+    };
+
+    let definitions = EmailDefinition::load_dir(dir)?;
+    let db = DB::connect().await?;
+
+    let mut changed = 0;
+    for definition in &definitions {
+        let diff = if dry_run {
+            definition.diff(&db).await?
+        } else {
+            definition.sync(&db).await?
+        };
+
+        if !diff.is_unchanged() {
+            println!("{diff}");
+            changed += 1;
+        }
+    }
+
+    let verb = if dry_run { "would change" } else { "changed" };
+    println!(
+        "{changed} of {} email definitions {verb}",
+        definitions.len()
+    );
+
+    Ok(())
+}
+
+fn init() -> Result<()> {
+    color_eyre::install()?;
+    // The configuration can come from the environment only
+    dotenvy::dotenv().ok();
+
+    // Logs go to stderr, so that stdout only holds the changes
+    tracing_subscriber::registry()
+        .with(fmt::layer().with_writer(std::io::stderr))
+        .with(ErrorLayer::default())
+        .with(EnvFilter::from_default_env())
+        .init();
+
+    Ok(())
+}
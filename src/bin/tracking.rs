@@ -0,0 +1,41 @@
+//! Serve the click and open tracking endpoints.
+//!
+//! Usage: `tracking <address>`, such as `tracking 127.0.0.1:8080`.
+
+use color_eyre::eyre::{bail, Result};
+use tokio::net::TcpListener;
+use tracing::info;
+use tracing_error::ErrorLayer;
+use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+
+use sequoia::{db::DB, tracking};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    init()?;
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [address] = args.as_slice() else {
+        bail!("Usage: tracking <address>");
+    };
+
+    let db: &'static DB = Box::leak(Box::new(DB::connect().await?));
+    let listener = TcpListener::bind(address).await?;
+    info!("Listening on {}", listener.local_addr()?);
+
+    tracking::serve(listener, db).await
+}
+
+fn init() -> Result<()> {
+    color_eyre::install()?;
+    // The configuration can come from the environment only
+    dotenvy::dotenv().ok();
+
+    tracing_subscriber::registry()
+        .with(fmt::layer())
+        .with(ErrorLayer::default())
+        .with(EnvFilter::from_default_env())
+        .init();
+
+    Ok(())
+}
@@ -8,16 +8,25 @@ use serde_derive::{Deserialize, Serialize};
 mod client_ref;
 mod group;
 
-pub use group::Group;
+pub use client_ref::ClientRef;
+pub use group::{Group, GroupMembers};
 use serde_rusqlite::{columns_from_statement, from_row_with_columns, to_params_named};
 
-use crate::{db::DB, email::Email};
+use crate::{
+    db::DB,
+    email::{normalize_locale, Email},
+};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Client {
     #[serde(rename(deserialize = "ID"))]
     id: String,
     adresse: EmailAddress,
+    /// Locale the client reads emails in, such as `fr` or `en-gb`
+    locale: Option<String>,
+    /// Whether the client opted out of open and click tracking. Every query must select
+    /// it: a missing column is an error, so that an opt-out is never ignored.
+    do_not_track: bool,
     #[serde(skip)]
     received_emails: Option<Vec<Email>>,
 }
@@ -26,16 +35,20 @@ impl Client {
     pub(crate) const CREATE_TABLES: &'static str = r#"
         CREATE TABLE IF NOT EXISTS Client (
             ID       TEXT PRIMARY KEY,
-            adresse  TEXT NOT NULL
+            adresse  TEXT NOT NULL,
+            locale   TEXT,
+            do_not_track  INTEGER NOT NULL DEFAULT 0 CHECK(do_not_track IN (0, 1))
             ) STRICT;
     "#;
 
-    fn new(adresse: &str) -> Result<Self> {
+    pub(crate) fn new(adresse: &str) -> Result<Self> {
         let adresse = EmailAddress::from_str(adresse)?;
 
         Ok(Self {
             id: create_id(),
             adresse,
+            locale: None,
+            do_not_track: false,
             received_emails: None,
         })
     }
@@ -44,8 +57,9 @@ impl Client {
         let this = Self::new(adresse)?;
 
         db.connection(|conn| {
-            let mut stmt =
-                conn.prepare_cached("INSERT INTO Client (ID, adresse) VALUES (:id, :adresse)")?;
+            let mut stmt = conn.prepare_cached(
+                "INSERT INTO Client (ID, adresse, locale, do_not_track) VALUES (:id, :adresse, :locale, :do_not_track)",
+            )?;
 
             stmt.execute(to_params_named(&this)?.to_slice().as_slice())?;
 
@@ -64,6 +78,51 @@ impl Client {
         self.adresse.as_ref()
     }
 
+    pub fn locale(&self) -> Option<&str> {
+        self.locale.as_deref()
+    }
+
+    /// Set the locale the client reads emails in, `None` when unknown.
+    pub async fn set_locale(&mut self, locale: Option<&str>, db: &DB) -> Result<()> {
+        let locale = locale.map(normalize_locale).transpose()?;
+
+        db.connection(|conn| {
+            conn.execute(
+                "UPDATE Client SET locale = ? WHERE ID = ?",
+                (&locale, &self.id),
+            )?;
+
+            Ok(())
+        })
+        .await?;
+
+        self.locale = locale;
+
+        Ok(())
+    }
+
+    /// Whether the client opted out of tracking. Emails sent to the client are neither
+    /// tracked when opened nor when their links are clicked.
+    pub fn do_not_track(&self) -> bool {
+        self.do_not_track
+    }
+
+    pub async fn set_do_not_track(&mut self, do_not_track: bool, db: &DB) -> Result<()> {
+        db.connection(|conn| {
+            conn.execute(
+                "UPDATE Client SET do_not_track = ? WHERE ID = ?",
+                (do_not_track, &self.id),
+            )?;
+
+            Ok(())
+        })
+        .await?;
+
+        self.do_not_track = do_not_track;
+
+        Ok(())
+    }
+
     pub async fn get_one(id: String, db: &DB) -> Result<Option<Self>> {
         db.connection(|conn| {
             let mut stmt = conn.prepare_cached("SELECT * FROM Client WHERE ID = ?")?;
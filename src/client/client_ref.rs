@@ -1,15 +1,51 @@
+use color_eyre::eyre::{eyre, Result};
+use tokio::sync::OnceCell;
+
+use crate::db::DB;
+
 use super::Client;
 
-pub struct ClientRef(Client);
+/// A reference to a client by ID. The client itself is only loaded from the database on the
+/// first call to [`ClientRef::resolve`], and then cached.
+#[derive(Debug)]
+pub struct ClientRef {
+    id: String,
+    client: OnceCell<Client>,
+}
 
 impl ClientRef {
-    pub const fn as_ref(&self) -> &Client {
-        &self.0
+    pub fn new(id: String) -> Self {
+        Self {
+            id,
+            client: OnceCell::new(),
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The client, if it has already been resolved.
+    pub fn get(&self) -> Option<&Client> {
+        self.client.get()
+    }
+
+    pub async fn resolve(&self, db: &DB) -> Result<&Client> {
+        self.client
+            .get_or_try_init(|| async {
+                Client::get_one(self.id.clone(), db)
+                    .await?
+                    .ok_or_else(|| eyre!("Client {} doesn't exist", self.id))
+            })
+            .await
     }
 }
 
 impl From<Client> for ClientRef {
     fn from(client: Client) -> Self {
-        Self(client)
+        Self {
+            id: client.id().to_owned(),
+            client: OnceCell::new_with(Some(client)),
+        }
     }
 }
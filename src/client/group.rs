@@ -1,3 +1,5 @@
+use std::collections::BTreeSet;
+
 use color_eyre::eyre::Result;
 use cuid2::create_id;
 use serde_derive::{Deserialize, Serialize};
@@ -26,6 +28,7 @@ impl Group {
         CREATE TABLE IF NOT EXISTS MM_ClientGroupClient (
             client_group_ID  TEXT,
             client_ID        TEXT,
+            UNIQUE(client_group_ID, client_ID),
             FOREIGN KEY(client_group_ID)  REFERENCES ClientGroup(ID)
                 ON UPDATE CASCADE
                 ON DELETE CASCADE,
@@ -52,33 +55,39 @@ impl Group {
     }
 
     pub async fn query_clients(&self, db: &DB) -> Result<Vec<ClientRef>> {
+        let ids = self.query_client_ids(db).await?;
+
+        Ok(ids.into_iter().map(ClientRef::new).collect())
+    }
+
+    pub async fn query_client_ids(&self, db: &DB) -> Result<BTreeSet<String>> {
         db.connection(|conn| {
             let mut stmt = conn.prepare_cached(
-                r"
-                SELECT Client.ID, Client.adresse FROM Client 
-                    JOIN MM_ClientGroupClient ON MM_ClientGroupClient.client_ID = Client.ID
-                    WHERE MM_ClientGroupClient.client_group_ID = ?",
+                "SELECT client_ID FROM MM_ClientGroupClient WHERE client_group_ID = ?",
             )?;
 
-            let columns = columns_from_statement(&stmt);
+            let ids = Result::from_iter(stmt.query_map([self.id.clone()], |row| row.get(0))?)?;
 
-            let query = stmt.query_and_then([self.id.clone()], |row| -> Result<ClientRef> {
-                let client = from_row_with_columns::<Client>(row, &columns)?;
-                Ok(ClientRef::from(client))
-            })?;
-
-            let clients = Result::from_iter(query)?;
-
-            Ok(clients)
+            Ok(ids)
         })
         .await
     }
 
+    /// Iterate over the members of the group, `page_size` clients at a time.
+    pub fn members(&self, page_size: usize) -> GroupMembers<'_> {
+        GroupMembers {
+            group: self,
+            page_size,
+            last_id: None,
+            exhausted: false,
+        }
+    }
+
     pub async fn fetch_clients(&mut self, db: &DB) -> Result<()> {
         db.connection(|conn| {
             let mut stmt = conn.prepare_cached(
                 r"
-                SELECT Client.ID, Client.adresse FROM Client 
+                SELECT Client.ID, Client.adresse, Client.locale, Client.do_not_track FROM Client 
                     JOIN MM_ClientGroupClient ON MM_ClientGroupClient.client_ID = Client.ID
                     WHERE MM_ClientGroupClient.client_group_ID = ?",
             )?;
@@ -116,24 +125,25 @@ impl Group {
         &self.id
     }
 
-    // pub fn get(id: String, db: &DB) -> Result<Option<Self>> {
-    //     let mut stmt = db
-    //         .connection()
-    //         .prepare_cached("SELECT * FROM ClientGroup WHERE ID = ?")?;
+    pub async fn get_one(id: &str, db: &DB) -> Result<Option<Self>> {
+        db.connection(|conn| {
+            let mut stmt = conn.prepare_cached("SELECT * FROM ClientGroup WHERE ID = ?")?;
 
-    //     let columns = columns_from_statement(&stmt);
+            let columns = columns_from_statement(&stmt);
 
-    //     let mut rows = stmt
-    //         .query_and_then([id], |row| from_row_with_columns::<Self>(row, &columns))
-    //         .unwrap();
+            let mut rows =
+                stmt.query_and_then([id], |row| from_row_with_columns::<Self>(row, &columns))?;
 
-    //     Ok(rows.next().transpose()?)
-    // }
+            Ok(rows.next().transpose()?)
+        })
+        .await
+    }
 
+    /// Add the client `id` to the group, unless it is already a member.
     pub async fn add_client(&mut self, id: String, db: &DB) -> Result<()> {
         db.connection(|conn| {
             let mut stmt = conn.prepare_cached(
-                "INSERT INTO MM_ClientGroupClient (client_group_ID, client_ID) VALUES (?, ?)",
+                "INSERT OR IGNORE INTO MM_ClientGroupClient (client_group_ID, client_ID) VALUES (?, ?)",
             )?;
 
             stmt.execute((self.id.clone(), id))?;
@@ -143,10 +153,11 @@ impl Group {
         .await
     }
 
+    /// Add the clients `ids` to the group, skipping those already members.
     pub async fn add_clients(&mut self, ids: &[String], db: &DB) -> Result<()> {
         db.connection(|conn| {
             let mut stmt = conn.prepare_cached(
-                "INSERT INTO MM_ClientGroupClient (client_group_ID, client_ID) VALUES (?, ?)",
+                "INSERT OR IGNORE INTO MM_ClientGroupClient (client_group_ID, client_ID) VALUES (?, ?)",
             )?;
 
             for id in ids {
@@ -186,3 +197,59 @@ impl Group {
         .await
     }
 }
+
+/// Pages of the members of a [`Group`], loaded on demand and ordered by client ID.
+pub struct GroupMembers<'g> {
+    group: &'g Group,
+    page_size: usize,
+    last_id: Option<String>,
+    exhausted: bool,
+}
+
+impl GroupMembers<'_> {
+    pub async fn next_page(&mut self, db: &DB) -> Result<Option<Vec<Client>>> {
+        if self.exhausted {
+            return Ok(None);
+        }
+
+        let clients = db
+            .connection(|conn| {
+                let mut stmt = conn.prepare_cached(
+                    r"
+                    SELECT Client.ID, Client.adresse, Client.locale, Client.do_not_track FROM Client
+                        JOIN MM_ClientGroupClient ON MM_ClientGroupClient.client_ID = Client.ID
+                        WHERE MM_ClientGroupClient.client_group_ID = ? AND Client.ID > ?
+                        ORDER BY Client.ID
+                        LIMIT ?",
+                )?;
+
+                let columns = columns_from_statement(&stmt);
+
+                let params = (
+                    self.group.id.clone(),
+                    self.last_id.clone().unwrap_or_default(),
+                    self.page_size,
+                );
+
+                let clients: Vec<Client> =
+                    Result::from_iter(stmt.query_and_then(params, |row| {
+                        from_row_with_columns::<Client>(row, &columns)
+                    })?)?;
+
+                Ok(clients)
+            })
+            .await?;
+
+        if clients.len() < self.page_size {
+            self.exhausted = true;
+        }
+
+        self.last_id = clients.last().map(|client| client.id().to_owned());
+
+        Ok(if clients.is_empty() {
+            None
+        } else {
+            Some(clients)
+        })
+    }
+}
@@ -1,11 +1,20 @@
-use color_eyre::eyre::Result;
+use color_eyre::eyre::{bail, Result};
 use rusqlite::{config::DbConfig, Connection};
 use tokio::sync::Mutex;
 use tracing::{debug, info, instrument};
 
 use crate::client::{Client, Group};
-use crate::email::{Email, PlainEmail, TemplateEmail};
-use crate::mailer::Mailer;
+use crate::email::{
+    Attachment, CustomHeader, Email, EmailRevision, EmailVariant, MarkdownEmail, PlainEmail, Tag,
+    TemplateEmail,
+};
+use crate::mailer::{AbTest, Mailer};
+use crate::sender::Sender;
+use crate::tracking::Delivery;
+
+mod migrations;
+
+use migrations::MIGRATIONS;
 
 pub struct DB {
     path: String,
@@ -13,11 +22,15 @@ pub struct DB {
 }
 
 impl DB {
-    #[instrument(skip_all)]
+    /// Connect to the database at the path in the `DB_PATH` environment variable.
     pub async fn connect() -> Result<Self> {
-        let path = dotenvy::var("DB_PATH")?;
+        Self::connect_to(&dotenvy::var("DB_PATH")?).await
+    }
 
-        let connection = Connection::open(&path)?;
+    /// Connect to the database at `path`, `:memory:` for an in-memory database.
+    #[instrument(skip_all)]
+    pub async fn connect_to(path: &str) -> Result<Self> {
+        let connection = Connection::open(path)?;
         info!("Connected to {}", path);
 
         // Enable foreign keys
@@ -40,16 +53,78 @@ impl DB {
         let create_tables = [
             Client::CREATE_TABLES,
             Group::CREATE_TABLES,
+            Sender::CREATE_TABLES,
             PlainEmail::CREATE_TABLES,
             TemplateEmail::CREATE_TABLES,
+            MarkdownEmail::CREATE_TABLES,
             Email::CREATE_TABLES,
+            EmailRevision::CREATE_TABLES,
+            EmailVariant::CREATE_TABLES,
+            Tag::CREATE_TABLES,
+            CustomHeader::CREATE_TABLES,
+            Attachment::CREATE_TABLES,
             Mailer::CREATE_TABLES,
+            AbTest::CREATE_TABLES,
+            Delivery::CREATE_TABLES,
         ]
         .join("\n");
 
         debug!(create_tables);
 
-        self.connection.lock().await.execute_batch(&create_tables)?;
+        let conn = self.connection.lock().await;
+
+        let is_new: bool = conn.query_row(
+            "SELECT NOT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'Email')",
+            [],
+            |row| row.get(0),
+        )?;
+
+        conn.execute_batch(&create_tables)?;
+
+        if is_new {
+            conn.pragma_update(None, "user_version", MIGRATIONS.len())?;
+        } else {
+            Self::migrate(&conn)?;
+        }
+
+        Ok(())
+    }
+
+    /// Apply the migrations that haven't been applied to the database yet.
+    fn migrate(conn: &Connection) -> Result<()> {
+        let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+
+        if version >= MIGRATIONS.len() {
+            return Ok(());
+        }
+
+        // Dropping a table to rebuild it would delete the rows referencing it otherwise
+        conn.set_db_config(DbConfig::SQLITE_DBCONFIG_ENABLE_FKEY, false)?;
+        let migrated = Self::apply_migrations(conn, version);
+        conn.set_db_config(DbConfig::SQLITE_DBCONFIG_ENABLE_FKEY, true)?;
+
+        migrated
+    }
+
+    fn apply_migrations(conn: &Connection, version: usize) -> Result<()> {
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            info!("Apply migration {}", i + 1);
+
+            let tx = conn.unchecked_transaction()?;
+            migration(&tx)?;
+
+            let violation: bool = tx.query_row(
+                "SELECT EXISTS (SELECT 1 FROM pragma_foreign_key_check)",
+                [],
+                |row| row.get(0),
+            )?;
+            if violation {
+                bail!("Migration {} breaks foreign key constraints", i + 1);
+            }
+
+            tx.pragma_update(None, "user_version", i + 1)?;
+            tx.commit()?;
+        }
 
         Ok(())
     }
@@ -66,14 +141,31 @@ impl DB {
     pub async fn clean(&self) -> Result<()> {
         self.connection.lock().await.execute_batch(
             r"
+            DELETE FROM Open WHERE 0=0;
+            DELETE FROM Click WHERE 0=0;
+            DELETE FROM TrackedLink WHERE 0=0;
+            DELETE FROM Delivery WHERE 0=0;
+            DELETE FROM AbTestAssignment WHERE 0=0;
+            DELETE FROM AbTestVariant WHERE 0=0;
+            DELETE FROM AbTest WHERE 0=0;
             DELETE FROM MM_ClientGroupClient WHERE 0=0;
             DELETE FROM Client WHERE 0=0;
             DELETE FROM ClientGroup WHERE 0=0;
             DELETE FROM MM_EmailClient WHERE 0=0;
             DELETE FROM MM_EmailClientGroup WHERE 0=0;
+            DELETE FROM EmailSending WHERE 0=0;
+            DELETE FROM EmailHeader WHERE 0=0;
+            DELETE FROM Attachment WHERE 0=0;
+            DELETE FROM EmailRevisionVariant WHERE 0=0;
+            DELETE FROM EmailRevision WHERE 0=0;
+            DELETE FROM EmailVariant WHERE 0=0;
+            DELETE FROM MM_EmailTag WHERE 0=0;
+            DELETE FROM Tag WHERE 0=0;
             DELETE FROM Email WHERE 0=0;
+            DELETE FROM Sender WHERE 0=0;
             DELETE FROM PlainEmail WHERE 0=0;
             DELETE FROM TemplateEmail WHERE 0=0;
+            DELETE FROM MarkdownEmail WHERE 0=0;
         ",
         )?;
 
@@ -0,0 +1,322 @@
+use color_eyre::eyre::Result;
+use rusqlite::Connection;
+use tracing::warn;
+
+use crate::email::html_to_text;
+
+/// A schema change for databases created before it.
+pub(super) type Migration = fn(&Connection) -> Result<()>;
+
+/// Migrations, in the order they were introduced.
+///
+/// The `CREATE_TABLES` of each entity always describe the latest schema, so a new database
+/// doesn't run any migration. `PRAGMA user_version` holds how many migrations were applied.
+/// Append to this list, never reorder or remove an entry.
+///
+/// Tables missing from an old database are created with their latest schema before the
+/// migrations run, so use [`add_column`] rather than a bare `ALTER TABLE`, and check
+/// [`has_column`] before reading or dropping a column that was removed.
+///
+/// Foreign keys aren't enforced while migrations run, so that a table can be rebuilt without
+/// deleting the rows that reference it. They are checked before each migration is committed.
+pub(super) const MIGRATIONS: &[Migration] = &[
+    text_bodies,
+    inline_attachments,
+    email_headers,
+    email_revisions,
+    normalized_tags,
+    markdown_emails,
+    locales,
+    senders,
+    definition_keys,
+    open_tracking,
+    unique_group_members,
+    variant_revisions,
+    smtp_password_envs,
+];
+
+/// Add `column` to `table`, unless the table already has it.
+fn add_column(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<()> {
+    if !has_column(conn, table, column)? {
+        conn.execute_batch(&format!(
+            "ALTER TABLE {table} ADD COLUMN {column} {definition};"
+        ))?;
+    }
+
+    Ok(())
+}
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> Result<bool> {
+    Ok(conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM pragma_table_info(?) WHERE name = ?)",
+        (table, column),
+        |row| row.get(0),
+    )?)
+}
+
+/// Add the plain-text alternative of plain and template emails.
+fn text_bodies(conn: &Connection) -> Result<()> {
+    add_column(conn, "PlainEmail", "text_body", "TEXT")?;
+    add_column(conn, "TemplateEmail", "text_body", "TEXT")?;
+
+    let mut select = conn.prepare("SELECT ID, body FROM PlainEmail")?;
+    let mut update = conn.prepare("UPDATE PlainEmail SET text_body = ? WHERE ID = ?")?;
+
+    let rows = select.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?))
+    })?;
+
+    for row in rows {
+        let (id, body) = row?;
+        update.execute((html_to_text(&body.unwrap_or_default())?, id))?;
+    }
+
+    Ok(())
+}
+
+/// Attachments with a Content-ID are inline images.
+fn inline_attachments(conn: &Connection) -> Result<()> {
+    add_column(conn, "Attachment", "content_id", "TEXT")
+}
+
+/// Add the Reply-To, CC and BCC of emails.
+fn email_headers(conn: &Connection) -> Result<()> {
+    add_column(conn, "Email", "reply_to", "TEXT")?;
+    add_column(conn, "Email", "cc", "TEXT")?;
+    add_column(conn, "Email", "bcc", "TEXT")
+}
+
+/// Add the status and revision of emails. Existing emails are published, and their current
+/// content becomes their first revision.
+fn email_revisions(conn: &Connection) -> Result<()> {
+    add_column(
+        conn,
+        "Email",
+        "status",
+        "INTEGER NOT NULL DEFAULT 1 CHECK(status IN (0, 1))",
+    )?;
+    add_column(conn, "Email", "revision", "INTEGER NOT NULL DEFAULT 1")?;
+    add_column(conn, "EmailSending", "revision", "INTEGER")?;
+    add_column(conn, "MM_EmailClient", "revision", "INTEGER")?;
+    add_column(conn, "MM_EmailClientGroup", "revision", "INTEGER")?;
+
+    conn.execute_batch(
+        r"
+        INSERT OR IGNORE INTO EmailRevision (email_ID, revision, email_discriminant, subject, body, text_body, source_path, timestamp)
+            SELECT em.ID, em.revision, em.email_discriminant,
+                    COALESCE(pe.subject, te.subject, ''), COALESCE(pe.body, te.body, ''),
+                    COALESCE(pe.text_body, te.text_body), te.source_path, CAST(strftime('%s', 'now') AS INTEGER)
+                FROM Email em
+                LEFT JOIN PlainEmail pe ON em.plain_email_ID = pe.ID
+                LEFT JOIN TemplateEmail te ON em.template_email_ID = te.ID;
+        ",
+    )?;
+
+    Ok(())
+}
+
+/// Move the `$`-separated tags of `Email.tags` to the `Tag` and `MM_EmailTag` tables.
+fn normalized_tags(conn: &Connection) -> Result<()> {
+    if !has_column(conn, "Email", "tags")? {
+        return Ok(());
+    }
+
+    let mut select = conn.prepare("SELECT ID, tags FROM Email WHERE tags IS NOT NULL")?;
+    let mut insert_tag = conn.prepare("INSERT OR IGNORE INTO Tag (name) VALUES (?)")?;
+    let mut insert_email_tag = conn.prepare(
+        "INSERT OR IGNORE INTO MM_EmailTag (email_ID, tag_ID) SELECT ?, ID FROM Tag WHERE name = ?",
+    )?;
+
+    let rows = select.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    })?;
+
+    for row in rows {
+        let (id, tags) = row?;
+
+        for tag in tags.split('$').filter(|tag| !tag.is_empty()) {
+            insert_tag.execute([tag])?;
+            insert_email_tag.execute((&id, tag))?;
+        }
+    }
+
+    conn.execute_batch("ALTER TABLE Email DROP COLUMN tags;")?;
+
+    Ok(())
+}
+
+/// Add Markdown emails. SQLite can't change the `CHECK` constraint of a column, so the
+/// `Email` table is rebuilt.
+fn markdown_emails(conn: &Connection) -> Result<()> {
+    if has_column(conn, "Email", "markdown_email_ID")? {
+        return Ok(());
+    }
+
+    conn.execute_batch(
+        r"
+        CREATE TABLE Email_new (
+            ID                  TEXT PRIMARY KEY,
+            status              INTEGER NOT NULL DEFAULT 1 CHECK(status IN (0, 1)),
+            revision            INTEGER NOT NULL DEFAULT 1,
+            sender_adresse      TEXT,
+            email_discriminant  INTEGER CHECK(email_discriminant IN (0, 1, 2)),
+            plain_email_ID      TEXT,
+            template_email_ID   TEXT,
+            markdown_email_ID   TEXT,
+            reply_to            TEXT,
+            cc                  TEXT,
+            bcc                 TEXT,
+            FOREIGN KEY (plain_email_ID)     REFERENCES PlainEmail(ID)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE,
+            FOREIGN KEY (template_email_ID)  REFERENCES TemplateEmail(ID)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE,
+            FOREIGN KEY (markdown_email_ID)  REFERENCES MarkdownEmail(ID)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE
+        ) STRICT;
+
+        INSERT INTO Email_new (ID, status, revision, sender_adresse, email_discriminant, plain_email_ID, template_email_ID, reply_to, cc, bcc)
+            SELECT ID, status, revision, sender_adresse, email_discriminant, plain_email_ID, template_email_ID, reply_to, cc, bcc
+                FROM Email
+                ORDER BY rowid;
+
+        DROP TABLE Email;
+        ALTER TABLE Email_new RENAME TO Email;
+    ",
+    )?;
+
+    Ok(())
+}
+
+/// Add the locale of clients and of the default content of emails.
+fn locales(conn: &Connection) -> Result<()> {
+    add_column(conn, "Client", "locale", "TEXT")?;
+    add_column(conn, "Email", "locale", "TEXT")
+}
+
+/// Let emails reference the sender they are sent as.
+fn senders(conn: &Connection) -> Result<()> {
+    add_column(
+        conn,
+        "Email",
+        "sender_ID",
+        "TEXT REFERENCES Sender(ID) ON UPDATE CASCADE ON DELETE SET NULL",
+    )
+}
+
+/// Add the stable key of the emails synced from definition files.
+fn definition_keys(conn: &Connection) -> Result<()> {
+    // A column added to an existing table can't be `UNIQUE`, the index enforces it instead
+    add_column(conn, "Email", "definition_key", "TEXT")?;
+    conn.execute_batch(
+        "CREATE UNIQUE INDEX IF NOT EXISTS Email_definition_key ON Email (definition_key);",
+    )?;
+
+    Ok(())
+}
+
+/// Let emails opt in to open tracking and clients opt out of tracking.
+fn open_tracking(conn: &Connection) -> Result<()> {
+    add_column(
+        conn,
+        "Email",
+        "track_opens",
+        "INTEGER NOT NULL DEFAULT 0 CHECK(track_opens IN (0, 1))",
+    )?;
+    add_column(
+        conn,
+        "Client",
+        "do_not_track",
+        "INTEGER NOT NULL DEFAULT 0 CHECK(do_not_track IN (0, 1))",
+    )?;
+    add_column(
+        conn,
+        "Delivery",
+        "track_opens",
+        "INTEGER NOT NULL DEFAULT 0 CHECK(track_opens IN (0, 1))",
+    )
+}
+
+/// Make a client a member of a group at most once, so that it isn't emailed twice.
+fn unique_group_members(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        r"
+        DELETE FROM MM_ClientGroupClient
+            WHERE rowid NOT IN (
+                SELECT MIN(rowid) FROM MM_ClientGroupClient GROUP BY client_group_ID, client_ID
+            );
+
+        CREATE UNIQUE INDEX IF NOT EXISTS MM_ClientGroupClient_member
+            ON MM_ClientGroupClient (client_group_ID, client_ID);
+    ",
+    )?;
+
+    Ok(())
+}
+
+/// Record the variants of emails in their revisions. The current variants of existing emails
+/// are recorded in their current revision.
+fn variant_revisions(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        r"
+        INSERT OR IGNORE INTO EmailRevisionVariant (email_ID, revision, locale, email_discriminant, subject, body, text_body, source_path)
+            SELECT ev.email_ID, em.revision, ev.locale, ev.email_discriminant,
+                    COALESCE(pe.subject, te.subject, me.subject, ''),
+                    COALESCE(pe.body, te.body, me.source, ''),
+                    COALESCE(pe.text_body, te.text_body), te.source_path
+                FROM EmailVariant ev
+                JOIN Email em ON ev.email_ID = em.ID
+                JOIN EmailRevision er ON er.email_ID = em.ID AND er.revision = em.revision
+                LEFT JOIN PlainEmail pe ON ev.plain_email_ID = pe.ID
+                LEFT JOIN TemplateEmail te ON ev.template_email_ID = te.ID
+                LEFT JOIN MarkdownEmail me ON ev.markdown_email_ID = me.ID;
+        ",
+    )?;
+
+    Ok(())
+}
+
+/// Read the SMTP passwords of senders from the environment instead of the database. The
+/// passwords are dropped, each sender reading its password from `SMTP_PASSWORD_<ID>` until
+/// its login is set again. The `Sender` table is rebuilt.
+fn smtp_password_envs(conn: &Connection) -> Result<()> {
+    if !has_column(conn, "Sender", "smtp_password")? {
+        return Ok(());
+    }
+
+    let mut select = conn.prepare("SELECT ID FROM Sender WHERE smtp_username IS NOT NULL")?;
+    let ids = select.query_map([], |row| row.get::<_, String>(0))?;
+    for id in ids {
+        let id = id?;
+        warn!("The SMTP password of sender {id} is now read from SMTP_PASSWORD_{id}");
+    }
+    drop(select);
+
+    conn.execute_batch(
+        r"
+        CREATE TABLE Sender_new (
+            ID                 TEXT PRIMARY KEY,
+            name               TEXT,
+            adresse            TEXT NOT NULL,
+            reply_to           TEXT,
+            signature          TEXT,
+            smtp_username      TEXT,
+            smtp_password_env  TEXT,
+            CHECK ((smtp_username IS NULL) = (smtp_password_env IS NULL))
+        ) STRICT;
+
+        INSERT INTO Sender_new (ID, name, adresse, reply_to, signature, smtp_username, smtp_password_env)
+            SELECT ID, name, adresse, reply_to, signature, smtp_username,
+                    CASE WHEN smtp_username IS NOT NULL THEN 'SMTP_PASSWORD_' || ID END
+                FROM Sender
+                ORDER BY rowid;
+
+        DROP TABLE Sender;
+        ALTER TABLE Sender_new RENAME TO Sender;
+    ",
+    )?;
+
+    Ok(())
+}
@@ -1,55 +1,139 @@
-use color_eyre::eyre::{Context, Result};
+use std::collections::BTreeMap;
+
+use color_eyre::eyre::{bail, Context, ContextCompat, Result};
 use cuid2::create_id;
 use email_address::EmailAddress;
 
+mod attachment;
 mod builder;
+mod definition;
+mod headers;
+pub(crate) mod html;
+mod lint;
+mod markdown_email;
+mod message;
 mod plain_email;
+mod render;
+mod revision;
 mod tags;
 mod template_email;
-
-pub use builder::EmailBuilder;
+mod template_store;
+mod text;
+mod variant;
+
+pub use attachment::Attachment;
+pub use builder::{
+    Body, EmailBuilder, HasSender, MarkdownBody, NoBody, NoSender, PlainBody, TemplateBody,
+};
+pub use definition::{EmailChange, EmailDefinition, EmailDiff, FieldChange};
+pub use headers::{CustomHeader, Headers};
+pub use lint::{Lint, LintKind, LintReport, Severity, GMAIL_CLIP_SIZE};
+pub use markdown_email::{MarkdownEmail, DEFAULT_STYLESHEET};
 pub use plain_email::PlainEmail;
-use rusqlite::types::Null;
+pub use render::{RenderContext, RenderedEmail, Renderer};
+use revision::TemplateSources;
+pub use revision::{EmailRevision, EmailRevisionVariant};
+use rusqlite::{params_from_iter, Connection};
 use serde_derive::{Deserialize, Serialize};
 use serde_rusqlite::{columns_from_statement, from_row_with_columns};
 use tags::Tags;
+pub use tags::{Tag, TagMatch};
 pub use template_email::TemplateEmail;
-use tracing::{error, info, instrument};
+pub use template_store::TemplateStore;
+pub(crate) use text::html_to_text;
+pub(crate) use variant::normalize_locale;
+pub use variant::EmailVariant;
 
+use crate::client::Client;
 use crate::db::DB;
+use crate::mailer::Receiver;
+use crate::sender::Sender;
+
+/// Number of clients loaded from the database at once when looking for missing translations.
+const MISSING_TRANSLATIONS_PAGE_SIZE: usize = 500;
+
+/// Number of emails whose senders, tags, variants, headers and attachments are loaded in one
+/// query each.
+const QUERY_BATCH_SIZE: usize = 500;
 
 #[derive(Debug)]
 pub struct Email {
     id: String,
+    status: EmailStatus,
+    /// Number of the current revision of the content, starting at 1
+    revision: u32,
     sender_adresse: EmailAddress,
+    /// Sender the email is sent as. Its adresse is also the `sender_adresse` of the email.
+    sender: Option<Sender>,
+    /// Stable key of the [`EmailDefinition`] the email is synced from
+    key: Option<String>,
     tags: Tags,
+    /// Locale of the default content, sent to the clients without a matching variant
+    locale: Option<String>,
+    /// Whether the email embeds a tracking pixel recording when it is opened
+    track_opens: bool,
     email: EmailModel,
+    /// Content in other locales
+    variants: Vec<EmailVariant>,
+    headers: Headers,
+    attachments: Vec<Attachment>,
 }
 
 impl Email {
     pub(crate) const CREATE_TABLES: &'static str = r#"
         CREATE TABLE IF NOT EXISTS Email (
             ID                  TEXT PRIMARY KEY,
+            status              INTEGER NOT NULL DEFAULT 1 CHECK(status IN (0, 1)),
+            revision            INTEGER NOT NULL DEFAULT 1,
             sender_adresse      TEXT,
-            tags                TEXT,
-            email_discriminant  INTEGER CHECK(email_discriminant IN (0, 1)),
+            email_discriminant  INTEGER CHECK(email_discriminant IN (0, 1, 2)),
             plain_email_ID      TEXT,
             template_email_ID   TEXT,
+            markdown_email_ID   TEXT,
+            reply_to            TEXT,
+            cc                  TEXT,
+            bcc                 TEXT,
+            locale              TEXT,
+            sender_ID           TEXT,
+            definition_key      TEXT UNIQUE,
+            track_opens         INTEGER NOT NULL DEFAULT 0 CHECK(track_opens IN (0, 1)),
+            FOREIGN KEY (sender_ID)          REFERENCES Sender(ID)
+                ON UPDATE CASCADE
+                ON DELETE SET NULL,
             FOREIGN KEY (plain_email_ID)     REFERENCES PlainEmail(ID)
                 ON UPDATE CASCADE
                 ON DELETE CASCADE,
             FOREIGN KEY (template_email_ID)  REFERENCES TemplateEmail(ID)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE,
+            FOREIGN KEY (markdown_email_ID)  REFERENCES MarkdownEmail(ID)
                 ON UPDATE CASCADE
                 ON DELETE CASCADE
         ) STRICT;
         "#;
 
-    fn new(sender_adresse: EmailAddress, email: EmailModel, tags: Tags) -> Self {
+    fn new(
+        status: EmailStatus,
+        sender_adresse: EmailAddress,
+        email: EmailModel,
+        tags: Tags,
+        headers: Headers,
+        attachments: Vec<Attachment>,
+    ) -> Self {
         Self {
             id: create_id(),
+            status,
+            revision: 1,
             sender_adresse,
+            sender: None,
+            key: None,
             tags,
+            locale: None,
+            track_opens: false,
             email,
+            variants: Vec::new(),
+            headers,
+            attachments,
         }
     }
 
@@ -57,100 +141,626 @@ impl Email {
         EmailBuilder::new()
     }
 
-    pub async fn create(
-        sender_adresse: EmailAddress,
-        email: EmailModel,
-        tags: Vec<String>,
-        db: &DB,
-    ) -> Result<Self> {
-        let this = Self::new(sender_adresse, email, tags.try_into()?);
+    /// Save an email built with the [`EmailBuilder`].
+    pub async fn create(&self, db: &DB) -> Result<()> {
+        db.connection(|conn| {
+            let tx = conn.unchecked_transaction()?;
 
-        this.email.write(db).await?;
+            self.email.write(&tx)?;
+            for variant in &self.variants {
+                variant.email.write(&tx)?;
+            }
+
+            let (plain_email_id, template_email_id, markdown_email_id) = self.email.ids();
+
+            tx.execute(r"
+                    INSERT INTO Email (ID, status, revision, sender_adresse, sender_ID, definition_key, email_discriminant, plain_email_ID, template_email_ID, markdown_email_ID, reply_to, cc, bcc, locale, track_opens)
+                    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                ", (
+                &self.id,
+                self.status as u8,
+                self.revision,
+                self.sender_adresse.to_string(),
+                self.sender.as_ref().map(Sender::id),
+                &self.key,
+                self.email.discriminant(),
+                plain_email_id,
+                template_email_id,
+                markdown_email_id,
+                self.headers.reply_to.as_ref().map(ToString::to_string),
+                Headers::mailboxes_to_sql(&self.headers.cc),
+                Headers::mailboxes_to_sql(&self.headers.bcc),
+                &self.locale,
+                self.track_opens,
+            ))?;
+
+            EmailRevision::write(&self.id, self.revision, &self.email, &self.variants, &TemplateSources::default(), &tx)?;
+            self.tags.write(&self.id, &tx)?;
+
+            for variant in &self.variants {
+                variant.write(&self.id, &tx)?;
+            }
+
+            for header in &self.headers.custom {
+                header.write(&self.id, &tx)?;
+            }
+
+            for attachment in &self.attachments {
+                attachment.write(&self.id, &tx)?;
+            }
+
+            tx.commit()?;
+
+            Ok(())
+        }).await
+    }
+
+    /// Add the content of a draft in `locale`, replacing the variant of that locale if any.
+    /// The previous variants are kept as a revision.
+    pub async fn set_variant(&mut self, locale: &str, email: EmailModel, db: &DB) -> Result<()> {
+        if self.status != EmailStatus::Draft {
+            bail!("Email {} is published and can't be updated", self.id);
+        }
+
+        let variant = EmailVariant::new(locale, email)?;
+        if self.locale.as_deref() == Some(variant.locale()) {
+            bail!(
+                "Locale {} is the locale of the default content, update it instead",
+                variant.locale()
+            );
+        }
+
+        let previous = self
+            .variants
+            .iter()
+            .position(|previous| previous.locale() == variant.locale());
+        let revision = self.revision + 1;
 
         db.connection(|conn| {
-            let mut stmt = conn.prepare(r"
-                    INSERT INTO Email (ID, sender_adresse, tags, email_discriminant, plain_email_ID, template_email_ID) 
-                    VALUES (?, ?, ?, ?, ?, ?)
-                ")?;
-
-            match &this.email {
-                EmailModel::Plain(plain_email) => {
-                    stmt.execute((
-                        &this.id,
-                        this.sender_adresse.to_string(),
-                        &this.tags.to_string(),
-                        EmailModel::PLAIN_DISCRIMINANT,
-                        plain_email.id(),
-                        Null,
-                    ))?;
-                }
-                EmailModel::Template(template_email) => {
-                    stmt.execute((
-                        &this.id,
-                        this.sender_adresse.to_string(),
-                        EmailModel::TEMPLATE_DISCRIMINANT,
-                        Null,
-                        template_email.id(),
-                    ))?;
-                }
+            let tx = conn.unchecked_transaction()?;
+
+            if let Some(previous) = previous {
+                self.variants[previous].delete(&self.id, &tx)?;
             }
+            variant.email.write(&tx)?;
+            variant.write(&self.id, &tx)?;
+
+            let variants = self
+                .variants
+                .iter()
+                .filter(|other| other.locale() != variant.locale())
+                .chain([&variant]);
+            self.write_revision(revision, variants, &tx)?;
+
+            tx.commit()?;
+
+            Ok(())
+        })
+        .await?;
+
+        match previous {
+            Some(previous) => self.variants[previous] = variant,
+            None => self.variants.push(variant),
+        }
+        self.revision = revision;
+
+        Ok(())
+    }
+
+    /// Remove the content of a draft in `locale`. Return whether there was such a variant.
+    /// The previous variants are kept as a revision.
+    pub async fn remove_variant(&mut self, locale: &str, db: &DB) -> Result<bool> {
+        if self.status != EmailStatus::Draft {
+            bail!("Email {} is published and can't be updated", self.id);
+        }
+
+        let locale = normalize_locale(locale)?;
+        let Some(position) = self
+            .variants
+            .iter()
+            .position(|variant| variant.locale() == locale)
+        else {
+            return Ok(false);
+        };
+
+        let revision = self.revision + 1;
+
+        db.connection(|conn| {
+            let tx = conn.unchecked_transaction()?;
+
+            self.variants[position].delete(&self.id, &tx)?;
+
+            let variants = self
+                .variants
+                .iter()
+                .filter(|variant| variant.locale() != locale);
+            self.write_revision(revision, variants, &tx)?;
+
+            tx.commit()?;
 
             Ok(())
-        }).await?;
+        })
+        .await?;
+        self.variants.remove(position);
+        self.revision = revision;
+
+        Ok(true)
+    }
+
+    /// Make `revision` the current revision, with the default content unchanged and
+    /// `variants`.
+    fn write_revision<'a>(
+        &self,
+        revision: u32,
+        variants: impl IntoIterator<Item = &'a EmailVariant>,
+        conn: &Connection,
+    ) -> Result<()> {
+        conn.execute(
+            "UPDATE Email SET revision = ? WHERE ID = ?",
+            (revision, &self.id),
+        )?;
+
+        EmailRevision::write(
+            &self.id,
+            revision,
+            &self.email,
+            variants,
+            &TemplateSources::default(),
+            conn,
+        )
+    }
+
+    /// Replace the content of a draft. The previous content is kept as a revision.
+    pub async fn update(&mut self, email: EmailModel, db: &DB) -> Result<()> {
+        if self.status != EmailStatus::Draft {
+            bail!("Email {} is published and can't be updated", self.id);
+        }
 
-        Ok(this)
+        self.replace_content(email, db).await
     }
 
-    pub(crate) fn id(&self) -> &str {
+    /// Replace the content of the email, whatever its status, and add a revision.
+    async fn replace_content(&mut self, email: EmailModel, db: &DB) -> Result<()> {
+        let revision = self.revision + 1;
+
+        db.connection(|conn| {
+            let tx = conn.unchecked_transaction()?;
+
+            self.write_content(revision, &email, &tx)?;
+
+            tx.commit()?;
+
+            Ok(())
+        })
+        .await?;
+
+        self.email = email;
+        self.revision = revision;
+
+        Ok(())
+    }
+
+    /// Write `email` as the content of the revision `revision`, deleting the current one.
+    /// The email itself is left to update once the write is committed.
+    fn write_content(&self, revision: u32, email: &EmailModel, conn: &Connection) -> Result<()> {
+        email.write(conn)?;
+
+        let (plain_email_id, template_email_id, markdown_email_id) = email.ids();
+
+        conn.execute(
+            r"
+            UPDATE Email SET revision = ?, email_discriminant = ?, plain_email_ID = ?, template_email_ID = ?, markdown_email_ID = ?
+                WHERE ID = ?
+        ",
+            (
+                revision,
+                email.discriminant(),
+                plain_email_id,
+                template_email_id,
+                markdown_email_id,
+                &self.id,
+            ),
+        )?;
+
+        EmailRevision::write(
+            &self.id,
+            revision,
+            email,
+            &self.variants,
+            &TemplateSources::default(),
+            conn,
+        )?;
+
+        // Only once the email doesn't reference it anymore, or it would be deleted too
+        self.email.delete(conn)
+    }
+
+    /// Make the email sendable. Its content can't be updated anymore.
+    pub async fn publish(&mut self, db: &DB) -> Result<()> {
+        db.connection(|conn| {
+            conn.execute(
+                "UPDATE Email SET status = ? WHERE ID = ?",
+                (EmailStatus::Published as u8, &self.id),
+            )?;
+
+            Ok(())
+        })
+        .await?;
+
+        self.status = EmailStatus::Published;
+
+        Ok(())
+    }
+
+    /// Every version of the content of the email, from the first one to the current one.
+    pub async fn revisions(&self, db: &DB) -> Result<Vec<EmailRevision>> {
+        db.connection(|conn| EmailRevision::get_for_email(&self.id, conn))
+            .await
+    }
+
+    pub fn id(&self) -> &str {
         &self.id
     }
 
+    pub fn status(&self) -> EmailStatus {
+        self.status
+    }
+
+    pub fn revision(&self) -> u32 {
+        self.revision
+    }
+
     pub fn sender_adresse(&self) -> &str {
         self.sender_adresse.as_ref()
     }
 
+    /// Stable key of the [`EmailDefinition`] the email is synced from, if any.
+    pub fn key(&self) -> Option<&str> {
+        self.key.as_deref()
+    }
+
+    pub fn sender(&self) -> Option<&Sender> {
+        self.sender.as_ref()
+    }
+
+    /// Send the email as `sender`, or only from its current adresse when `None`.
+    pub async fn set_sender(&mut self, sender: Option<&Sender>, db: &DB) -> Result<()> {
+        let sender_adresse = match sender {
+            Some(sender) => sender.adresse().parse()?,
+            None => self.sender_adresse.clone(),
+        };
+
+        db.connection(|conn| {
+            conn.execute(
+                "UPDATE Email SET sender_adresse = ?, sender_ID = ? WHERE ID = ?",
+                (sender_adresse.as_str(), sender.map(Sender::id), &self.id),
+            )?;
+
+            Ok(())
+        })
+        .await?;
+
+        self.sender_adresse = sender_adresse;
+        self.sender = sender.cloned();
+
+        Ok(())
+    }
+
+    /// Subject of the email. For template emails, this is the unrendered template.
     pub fn subject(&self) -> &str {
-        match &self.email {
-            EmailModel::Plain(plain_email) => plain_email.subject(),
-            EmailModel::Template(_) => unimplemented!("Template email aren't yet supported"),
+        self.email.subject()
+    }
+
+    /// Body of the email. For template emails, this is the unrendered template, and for
+    /// Markdown emails the HTML compiled from the source.
+    pub fn body(&self) -> &str {
+        self.email.body()
+    }
+
+    /// Plain-text alternative of the body, when it isn't derived from the rendered body.
+    /// For template emails, this is the unrendered template.
+    pub fn text_body(&self) -> Option<&str> {
+        self.email.text_body()
+    }
+
+    pub fn tags(&self) -> &[String] {
+        self.tags.as_slice()
+    }
+
+    /// Locale of the default content, if known.
+    pub fn locale(&self) -> Option<&str> {
+        self.locale.as_deref()
+    }
+
+    /// Whether the email embeds a tracking pixel recording when it is opened, for the
+    /// clients who didn't opt out of tracking.
+    pub fn tracks_opens(&self) -> bool {
+        self.track_opens
+    }
+
+    /// Embed a tracking pixel in the email, once sent by a [`Mailer`](crate::mailer::Mailer)
+    /// tracking opens.
+    pub async fn set_track_opens(&mut self, track_opens: bool, db: &DB) -> Result<()> {
+        db.connection(|conn| {
+            conn.execute(
+                "UPDATE Email SET track_opens = ? WHERE ID = ?",
+                (track_opens, &self.id),
+            )?;
+
+            Ok(())
+        })
+        .await?;
+
+        self.track_opens = track_opens;
+
+        Ok(())
+    }
+
+    /// Content of the email in other locales than the default one.
+    pub fn variants(&self) -> &[EmailVariant] {
+        &self.variants
+    }
+
+    /// The variant sent to a client with `locale`: the variant of that locale, or else of its
+    /// language. `None` stands for the default content.
+    pub fn variant_for(&self, locale: Option<&str>) -> Option<&EmailVariant> {
+        self.variant_index(locale)
+            .map(|index| &self.variants[index])
+    }
+
+    pub(super) fn variant_index(&self, locale: Option<&str>) -> Option<usize> {
+        let locale = normalize_locale(locale?).ok()?;
+
+        if self.locale.as_deref() == Some(locale.as_str()) {
+            return None;
+        }
+
+        if let Some(index) = self
+            .variants
+            .iter()
+            .position(|variant| variant.locale() == locale)
+        {
+            return Some(index);
         }
+
+        let language = variant::language(&locale);
+
+        if self.locale.as_deref().map(variant::language) == Some(language) {
+            return None;
+        }
+
+        self.variants
+            .iter()
+            .position(|variant| variant::language(variant.locale()) == language)
+    }
+
+    /// Whether a client with `locale` gets the email in its language, rather than the
+    /// default content in another one.
+    pub fn is_translated_for(&self, locale: &str) -> bool {
+        let Ok(locale) = normalize_locale(locale) else {
+            return false;
+        };
+
+        self.variant_for(Some(&locale)).is_some()
+            || self
+                .locale
+                .as_deref()
+                .is_some_and(|default| variant::language(default) == variant::language(&locale))
     }
 
-    pub fn body(&self) -> String {
-        match &self.email {
-            EmailModel::Plain(plain_email) => plain_email.body().to_owned(),
-            EmailModel::Template(_) => unimplemented!("Template email aren't yet supported"),
+    /// Whether the email has content in several locales, or its locale is known.
+    pub fn is_localized(&self) -> bool {
+        self.locale.is_some() || !self.variants.is_empty()
+    }
+
+    /// Locales of the recipients of `receiver` for which the email has no translation, with
+    /// the number of recipients in each. Those recipients get the default content.
+    ///
+    /// An email that isn't localized has no missing translation.
+    pub async fn missing_translations(
+        &self,
+        receiver: &Receiver,
+        db: &DB,
+    ) -> Result<BTreeMap<String, usize>> {
+        let mut missing = BTreeMap::new();
+
+        if !self.is_localized() {
+            return Ok(missing);
+        }
+
+        let mut recipients = receiver
+            .recipients(MISSING_TRANSLATIONS_PAGE_SIZE, db)
+            .await?;
+
+        while let Some(clients) = recipients.next_page(db).await? {
+            for locale in clients.iter().filter_map(Client::locale) {
+                if !self.is_translated_for(locale) {
+                    *missing.entry(locale.to_owned()).or_insert(0) += 1;
+                }
+            }
         }
+
+        Ok(missing)
+    }
+
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    /// Files attached to the email, inline images excluded.
+    pub fn attachments(&self) -> impl Iterator<Item = &Attachment> {
+        self.attachments
+            .iter()
+            .filter(|attachment| !attachment.is_inline())
+    }
+
+    /// Images referenced by the HTML body through their Content-ID.
+    pub fn inline_images(&self) -> impl Iterator<Item = &Attachment> {
+        self.attachments
+            .iter()
+            .filter(|attachment| attachment.is_inline())
+    }
+
+    /// Prepare the email to be rendered for each of its recipients. Template bodies are
+    /// loaded from `templates` when the email has a source path.
+    pub fn renderer(&self, templates: &TemplateStore) -> Result<Renderer<'_>> {
+        Renderer::new(self, templates)
+    }
+
+    /// The revision to record a sending rendered by `renderer` with. The template files the
+    /// renderer loaded are recorded in it, in a new revision if they changed since the
+    /// current one was sent.
+    pub(crate) async fn sending_revision(&self, renderer: &Renderer<'_>, db: &DB) -> Result<u32> {
+        let sources = renderer.sources();
+
+        db.connection(|conn| {
+            EmailRevision::for_sending(&self.id, &self.email, &self.variants, &sources, conn)
+        })
+        .await
     }
 
     pub async fn get_one(id: &str, db: &DB) -> Result<Option<Self>> {
         db.connection(|conn| {
-            let mut stmt = conn.prepare_cached(
-                r"
-                SELECT em.ID, em.tags, em.sender_adresse, em.email_discriminant,
-                  pe.ID as plain_email_id, pe.subject as plain_subject, pe.body as plain_body,
-                  te.ID as template_email_id, te.subject as template_subject, te.body as template_body, te.source_path as template_source_path
-                    FROM Email em
-                    LEFT JOIN PlainEmail pe ON em.plain_email_ID = pe.ID
-                    LEFT JOIN TemplateEmail te ON em.template_email_ID = te.ID
-                    WHERE em.ID = ?
-            ",
-            )?;
+            let mut emails = Self::query(conn, "WHERE em.ID = ?", [id])?;
 
-            let columns = columns_from_statement(&stmt);
+            Ok(emails.pop())
+        })
+        .await
+    }
 
-            info!("{columns:?}");
+    /// The email synced from the [`EmailDefinition`] with `key`.
+    pub async fn get_by_key(key: &str, db: &DB) -> Result<Option<Self>> {
+        db.connection(|conn| {
+            let mut emails = Self::query(conn, "WHERE em.definition_key = ?", [key])?;
 
-            let mut rows =
-                stmt.query_and_then([id], |row| from_row_with_columns::<SQLEmail>(row, &columns))?;
+            Ok(emails.pop())
+        })
+        .await
+    }
 
-            rows.next().transpose()?.map(Email::try_from).transpose()
-        }).await
+    /// Every email, in creation order.
+    pub async fn list(db: &DB) -> Result<Vec<Self>> {
+        db.connection(|conn| Self::query(conn, "ORDER BY em.rowid", []))
+            .await
     }
 
-    #[cfg(debug_assertions)]
-    pub fn id_(&self) -> &str {
-        &self.id
+    /// Emails selected by the `filter` clauses, read into [`SQLEmail`]s.
+    fn query(conn: &Connection, filter: &str, params: impl rusqlite::Params) -> Result<Vec<Self>> {
+        let mut stmt = conn.prepare_cached(&format!(
+            r"
+            SELECT em.ID, em.status, em.revision, em.sender_adresse, em.sender_ID, em.definition_key, em.locale, em.track_opens, em.reply_to, em.cc, em.bcc,
+              {}
+                FROM Email em
+                {}
+                {filter}
+        ",
+            EmailModel::SQL_COLUMNS,
+            EmailModel::SQL_JOINS,
+        ))?;
+
+        let columns = columns_from_statement(&stmt);
+
+        let rows = stmt.query_and_then(params, |row| {
+            from_row_with_columns::<SQLEmail>(row, &columns)
+        })?;
+
+        let mut emails = Vec::new();
+        let mut sender_ids = Vec::new();
+        for row in rows {
+            let row = row?;
+            sender_ids.push(row.sender_ID.clone());
+            emails.push(Email::try_from(row)?);
+        }
+
+        // The related rows are loaded for a batch of emails at once rather than per email
+        for (emails, sender_ids) in emails
+            .chunks_mut(QUERY_BATCH_SIZE)
+            .zip(sender_ids.chunks(QUERY_BATCH_SIZE))
+        {
+            let ids: Vec<String> = emails.iter().map(|email| email.id.clone()).collect();
+            let ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+            let mut unique_sender_ids: Vec<&str> =
+                sender_ids.iter().flatten().map(String::as_str).collect();
+            unique_sender_ids.sort_unstable();
+            unique_sender_ids.dedup();
+
+            let senders = Sender::query_many(&unique_sender_ids, conn)?;
+            let mut tags = Tags::get_for_emails(&ids, conn)?;
+            let mut variants = EmailVariant::get_for_emails(&ids, conn)?;
+            let mut headers = CustomHeader::get_for_emails(&ids, conn)?;
+            let mut attachments = Attachment::get_for_emails(&ids, conn)?;
+
+            for (email, sender_id) in emails.iter_mut().zip(sender_ids) {
+                email.sender = sender_id
+                    .as_ref()
+                    .and_then(|sender_id| senders.get(sender_id).cloned());
+                email.tags = tags.remove(&email.id).unwrap_or_default();
+                email.variants = variants.remove(&email.id).unwrap_or_default();
+                email.headers.custom = headers.remove(&email.id).unwrap_or_default();
+                email.attachments = attachments.remove(&email.id).unwrap_or_default();
+            }
+        }
+
+        Ok(emails)
+    }
+
+    /// Delete the email along with its content, revisions, attachments and send history.
+    pub async fn delete(self, db: &DB) -> Result<()> {
+        db.connection(|conn| {
+            let tx = conn.unchecked_transaction()?;
+
+            for variant in &self.variants {
+                variant.delete(&self.id, &tx)?;
+            }
+            tx.execute("DELETE FROM Email WHERE ID = ?", [&self.id])?;
+            self.email.delete(&tx)?;
+
+            tx.commit()?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// Emails tagged with any or all of `tags`, in creation order.
+    pub async fn list_by_tags(tags: &[&str], matching: TagMatch, db: &DB) -> Result<Vec<Self>> {
+        db.connection(|conn| {
+            let ids = Tags::query_email_ids(tags, matching, conn)?;
+
+            // The IDs are in creation order, so the batches are too
+            let mut emails = Vec::with_capacity(ids.len());
+            for ids in ids.chunks(QUERY_BATCH_SIZE) {
+                let placeholders = vec!["?"; ids.len()].join(", ");
+                emails.extend(Self::query(
+                    conn,
+                    &format!("WHERE em.ID IN ({placeholders}) ORDER BY em.rowid"),
+                    params_from_iter(ids),
+                )?);
+            }
+
+            Ok(emails)
+        })
+        .await
+    }
+}
+
+/// Only drafts can be updated, and only published emails can be sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum EmailStatus {
+    Draft = 0,
+    Published = 1,
+}
+
+impl TryFrom<u8> for EmailStatus {
+    type Error = color_eyre::eyre::Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(Self::Draft),
+            1 => Ok(Self::Published),
+            _ => bail!("Unknown email status {value}"),
+        }
     }
 }
 
@@ -158,73 +768,228 @@ impl Email {
 pub enum EmailModel {
     Plain(PlainEmail),
     Template(TemplateEmail),
+    Markdown(MarkdownEmail),
 }
 
 impl EmailModel {
     const PLAIN_DISCRIMINANT: u8 = 0;
     const TEMPLATE_DISCRIMINANT: u8 = 1;
+    const MARKDOWN_DISCRIMINANT: u8 = 2;
+
+    /// Columns read into a [`SQLEmailModel`], from a table aliased `em` that references the
+    /// models, joined by [`EmailModel::SQL_JOINS`].
+    const SQL_COLUMNS: &'static str = r"
+        em.email_discriminant,
+        pe.ID as plain_email_id, pe.subject as plain_subject, pe.body as plain_body, pe.text_body as plain_text_body,
+        te.ID as template_email_id, te.subject as template_subject, te.body as template_body, te.source_path as template_source_path, te.text_body as template_text_body,
+        md.ID as markdown_email_id, md.subject as markdown_subject, md.source as markdown_source, md.stylesheet as markdown_stylesheet
+    ";
+
+    const SQL_JOINS: &'static str = r"
+        LEFT JOIN PlainEmail pe ON em.plain_email_ID = pe.ID
+        LEFT JOIN TemplateEmail te ON em.template_email_ID = te.ID
+        LEFT JOIN MarkdownEmail md ON em.markdown_email_ID = md.ID
+    ";
+
+    /// The plain-text alternative is derived from `body` when `text_body` is `None`.
+    pub fn plain(subject: &str, body: &str, text_body: Option<&str>) -> Result<Self> {
+        Ok(Self::Plain(PlainEmail::new(
+            subject.to_owned(),
+            body.to_owned(),
+            text_body.map(str::to_owned),
+        )?))
+    }
+
+    pub fn template(subject: &str, body: &str, source_path: &str, text_body: Option<&str>) -> Self {
+        Self::Template(TemplateEmail::new(
+            subject.to_owned(),
+            body.to_owned(),
+            source_path.to_owned(),
+            text_body.map(str::to_owned),
+        ))
+    }
+
+    /// [`DEFAULT_STYLESHEET`] styles the HTML body when `stylesheet` is `None`.
+    pub fn markdown(subject: &str, source: &str, stylesheet: Option<&str>) -> Self {
+        Self::Markdown(MarkdownEmail::new(
+            subject.to_owned(),
+            source.to_owned(),
+            stylesheet.map(str::to_owned),
+        ))
+    }
+
+    fn discriminant(&self) -> u8 {
+        match self {
+            Self::Plain(_) => Self::PLAIN_DISCRIMINANT,
+            Self::Template(_) => Self::TEMPLATE_DISCRIMINANT,
+            Self::Markdown(_) => Self::MARKDOWN_DISCRIMINANT,
+        }
+    }
+
+    /// IDs of the plain, template and Markdown emails, as referenced by the `Email` table.
+    fn ids(&self) -> (Option<&str>, Option<&str>, Option<&str>) {
+        match self {
+            Self::Plain(plain_email) => (Some(plain_email.id()), None, None),
+            Self::Template(template_email) => (None, Some(template_email.id()), None),
+            Self::Markdown(markdown_email) => (None, None, Some(markdown_email.id())),
+        }
+    }
+
+    fn subject(&self) -> &str {
+        match self {
+            Self::Plain(plain_email) => plain_email.subject(),
+            Self::Template(template_email) => template_email.subject(),
+            Self::Markdown(markdown_email) => markdown_email.subject(),
+        }
+    }
+
+    fn body(&self) -> &str {
+        match self {
+            Self::Plain(plain_email) => plain_email.body(),
+            Self::Template(template_email) => template_email.body(),
+            Self::Markdown(markdown_email) => markdown_email.html(),
+        }
+    }
 
-    async fn write(&self, db: &DB) -> Result<()> {
+    fn text_body(&self) -> Option<&str> {
         match self {
-            Self::Plain(plain_email) => plain_email.write(db).await?,
-            Self::Template(template_email) => template_email.write(db).await?,
+            Self::Plain(plain_email) => Some(plain_email.text_body()),
+            Self::Template(template_email) => template_email.text_body(),
+            Self::Markdown(markdown_email) => Some(markdown_email.source()),
         }
+    }
+
+    fn delete(&self, conn: &Connection) -> Result<()> {
+        match self {
+            Self::Plain(plain_email) => {
+                conn.execute("DELETE FROM PlainEmail WHERE ID = ?", [plain_email.id()])?
+            }
+            Self::Template(template_email) => conn.execute(
+                "DELETE FROM TemplateEmail WHERE ID = ?",
+                [template_email.id()],
+            )?,
+            Self::Markdown(markdown_email) => conn.execute(
+                "DELETE FROM MarkdownEmail WHERE ID = ?",
+                [markdown_email.id()],
+            )?,
+        };
 
         Ok(())
     }
+
+    fn write(&self, conn: &Connection) -> Result<()> {
+        match self {
+            Self::Plain(plain_email) => plain_email.write(conn),
+            Self::Template(template_email) => template_email.write(conn),
+            Self::Markdown(markdown_email) => markdown_email.write(conn),
+        }
+    }
 }
 
 #[allow(non_snake_case)]
 #[derive(Deserialize, Serialize, Debug)]
 struct SQLEmail {
     ID: String,
+    status: u8,
+    revision: u32,
     sender_adresse: String,
-    tags: String,
+    sender_ID: Option<String>,
+    definition_key: Option<String>,
+    locale: Option<String>,
+    track_opens: bool,
+    reply_to: Option<String>,
+    cc: Option<String>,
+    bcc: Option<String>,
+    #[serde(flatten)]
+    email: SQLEmailModel,
+}
+
+impl TryFrom<SQLEmail> for Email {
+    type Error = color_eyre::eyre::Error;
+
+    fn try_from(value: SQLEmail) -> Result<Self> {
+        let id = value.ID;
+
+        Ok(Self {
+            status: value.status.try_into()?,
+            revision: value.revision,
+            tags: Tags::default(),
+            sender_adresse: value
+                .sender_adresse
+                .parse()
+                .with_context(|| format!("Parsing sender adresse of email {id}"))?,
+            sender: None,
+            key: value.definition_key,
+            locale: value.locale,
+            track_opens: value.track_opens,
+            email: EmailModel::try_from(value.email)
+                .with_context(|| format!("Reading content of email {id}"))?,
+            variants: Vec::new(),
+            headers: Headers {
+                reply_to: value
+                    .reply_to
+                    .map(|reply_to| reply_to.parse())
+                    .transpose()?,
+                cc: Headers::mailboxes_from_sql(value.cc)?,
+                bcc: Headers::mailboxes_from_sql(value.bcc)?,
+                custom: Vec::new(),
+            },
+            attachments: Vec::new(),
+            id,
+        })
+    }
+}
+
+/// Columns of [`EmailModel::SQL_COLUMNS`], for an email or one of its variants.
+#[derive(Deserialize, Serialize, Debug)]
+struct SQLEmailModel {
     email_discriminant: u8,
     plain_email_id: Option<String>,
     plain_subject: Option<String>,
     plain_body: Option<String>,
+    plain_text_body: Option<String>,
     template_email_id: Option<String>,
     template_subject: Option<String>,
     template_body: Option<String>,
     template_source_path: Option<String>,
+    template_text_body: Option<String>,
+    markdown_email_id: Option<String>,
+    markdown_subject: Option<String>,
+    markdown_source: Option<String>,
+    markdown_stylesheet: Option<String>,
 }
 
-impl TryFrom<SQLEmail> for Email {
+impl TryFrom<SQLEmailModel> for EmailModel {
     type Error = color_eyre::eyre::Error;
 
-    #[instrument]
-    fn try_from(value: SQLEmail) -> Result<Self> {
-        // TODO: Gérer les erreurs correctement
-
+    fn try_from(value: SQLEmailModel) -> Result<Self> {
         let email_model = match value.email_discriminant {
-            EmailModel::PLAIN_DISCRIMINANT => {
-                let plain_email = PlainEmail::from_sql(
-                    value.plain_email_id.unwrap(),
-                    value.plain_subject.unwrap(),
-                    value.plain_body.unwrap(),
-                );
-                EmailModel::Plain(plain_email)
-            }
-            EmailModel::TEMPLATE_DISCRIMINANT => {
-                unimplemented!("Template email are not yet implemented")
-            }
-            _ => {
-                error!("Unknown email model discriminant.");
-                panic!();
-            }
+            EmailModel::PLAIN_DISCRIMINANT => EmailModel::Plain(PlainEmail::from_sql(
+                value.plain_email_id.context("Plain email is missing")?,
+                value.plain_subject.unwrap_or_default(),
+                value.plain_body.unwrap_or_default(),
+                value.plain_text_body.unwrap_or_default(),
+            )),
+            EmailModel::TEMPLATE_DISCRIMINANT => EmailModel::Template(TemplateEmail::from_sql(
+                value
+                    .template_email_id
+                    .context("Template email is missing")?,
+                value.template_subject.unwrap_or_default(),
+                value.template_body.unwrap_or_default(),
+                value.template_source_path.unwrap_or_default(),
+                value.template_text_body,
+            )),
+            EmailModel::MARKDOWN_DISCRIMINANT => EmailModel::Markdown(MarkdownEmail::from_sql(
+                value
+                    .markdown_email_id
+                    .context("Markdown email is missing")?,
+                value.markdown_subject.unwrap_or_default(),
+                value.markdown_source.unwrap_or_default(),
+                value.markdown_stylesheet,
+            )),
+            discriminant => bail!("Unknown email model discriminant {discriminant}"),
         };
 
-        info!("");
-
-        Ok(Self {
-            id: value.ID,
-            tags: Tags::from(value.tags),
-            sender_adresse: dbg!(value.sender_adresse)
-                .parse()
-                .context("Parsing sender_adresse adresse from database")
-                .unwrap(),
-            email: email_model,
-        })
+        Ok(email_model)
     }
 }
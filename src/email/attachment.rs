@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use color_eyre::eyre::{bail, Context, ContextCompat, Result};
+use cuid2::create_id;
+use lettre::message::header::ContentType;
+use rusqlite::{params_from_iter, Connection};
+
+/// A file attached to an email.
+///
+/// Attachments with a Content-ID are inline images, displayed where the HTML body
+/// references them as `<img src="cid:...">`.
+#[derive(Debug)]
+pub struct Attachment {
+    id: String,
+    filename: String,
+    content_type: String,
+    data: Vec<u8>,
+    content_id: Option<String>,
+}
+
+impl Attachment {
+    pub(crate) const CREATE_TABLES: &'static str = r#"
+        CREATE TABLE IF NOT EXISTS Attachment (
+            ID            TEXT PRIMARY KEY,
+            email_ID      TEXT NOT NULL,
+            filename      TEXT NOT NULL,
+            content_type  TEXT NOT NULL,
+            data          BLOB NOT NULL,
+            content_id    TEXT,
+            FOREIGN KEY (email_ID)  REFERENCES Email(ID)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE
+        ) STRICT;
+    "#;
+
+    /// Total size of the attachments of an email, unless configured otherwise in the builder.
+    pub const DEFAULT_MAX_TOTAL_SIZE: usize = 20 * 1024 * 1024;
+
+    pub fn new(filename: &str, content_type: &str, data: Vec<u8>) -> Result<Self> {
+        ContentType::parse(content_type)
+            .with_context(|| format!("Invalid content type {content_type:?}"))?;
+
+        Ok(Self {
+            id: create_id(),
+            filename: filename.to_owned(),
+            content_type: content_type.to_owned(),
+            data,
+            content_id: None,
+        })
+    }
+
+    /// An image displayed in the HTML body, where it is referenced as `cid:<content_id>`.
+    pub fn new_inline(content_id: &str, content_type: &str, data: Vec<u8>) -> Result<Self> {
+        Self::check_content_id(content_id)?;
+
+        let mut this = Self::new(content_id, content_type, data)?;
+        this.content_id = Some(content_id.to_owned());
+
+        Ok(this)
+    }
+
+    /// Read an inline image from a file. Its MIME type is guessed from the extension.
+    pub fn inline_from_path(content_id: &str, path: impl AsRef<Path>) -> Result<Self> {
+        Self::check_content_id(content_id)?;
+
+        let mut this = Self::from_path(path)?;
+        this.content_id = Some(content_id.to_owned());
+
+        Ok(this)
+    }
+
+    fn check_content_id(content_id: &str) -> Result<()> {
+        let valid = !content_id.is_empty()
+            && content_id
+                .chars()
+                .all(|c| c.is_ascii_graphic() && !matches!(c, '<' | '>' | '"'));
+
+        if !valid {
+            bail!("Invalid Content-ID {content_id:?}");
+        }
+
+        Ok(())
+    }
+
+    /// Read an attachment from a file. Its MIME type is guessed from the extension.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+
+        let filename = path
+            .file_name()
+            .and_then(|filename| filename.to_str())
+            .with_context(|| format!("Invalid attachment path {}", path.display()))?;
+
+        let content_type = mime_guess::from_path(path).first_or_octet_stream();
+
+        let data = std::fs::read(path)
+            .with_context(|| format!("Reading attachment {}", path.display()))?;
+
+        Self::new(filename, content_type.essence_str(), data)
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    pub fn content_type(&self) -> &str {
+        &self.content_type
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn content_id(&self) -> Option<&str> {
+        self.content_id.as_deref()
+    }
+
+    pub fn is_inline(&self) -> bool {
+        self.content_id.is_some()
+    }
+
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Fail when the attachments weigh more than `max_size` bytes in total.
+    pub(super) fn check_total_size(attachments: &[Attachment], max_size: usize) -> Result<()> {
+        let size = attachments.iter().map(Attachment::size).sum::<usize>();
+
+        if size > max_size {
+            bail!("Attachments weigh {size} bytes, more than the limit of {max_size} bytes");
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn write(&self, email_id: &str, conn: &Connection) -> Result<()> {
+        let mut stmt = conn.prepare_cached(
+            r"
+            INSERT INTO Attachment (ID, email_ID, filename, content_type, data, content_id)
+            VALUES (?, ?, ?, ?, ?, ?)
+        ",
+        )?;
+
+        stmt.execute((
+            &self.id,
+            email_id,
+            &self.filename,
+            &self.content_type,
+            &self.data,
+            &self.content_id,
+        ))?;
+
+        Ok(())
+    }
+
+    /// Attachments of each of the emails `email_ids`, by email ID.
+    pub(super) fn get_for_emails(
+        email_ids: &[&str],
+        conn: &Connection,
+    ) -> Result<HashMap<String, Vec<Self>>> {
+        let placeholders = vec!["?"; email_ids.len()].join(", ");
+        let mut stmt = conn.prepare(&format!(
+            "SELECT email_ID, ID, filename, content_type, data, content_id FROM Attachment WHERE email_ID IN ({placeholders}) ORDER BY rowid",
+        ))?;
+
+        let rows = stmt.query_map(params_from_iter(email_ids), |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                Self {
+                    id: row.get(1)?,
+                    filename: row.get(2)?,
+                    content_type: row.get(3)?,
+                    data: row.get(4)?,
+                    content_id: row.get(5)?,
+                },
+            ))
+        })?;
+
+        let mut attachments: HashMap<String, Vec<Self>> = HashMap::new();
+        for row in rows {
+            let (email_id, attachment) = row?;
+            attachments.entry(email_id).or_default().push(attachment);
+        }
+
+        Ok(attachments)
+    }
+
+    /// The attachment as a MIME part, displayed inline with its Content-ID if it has one.
+    pub(crate) fn to_part(&self) -> Result<lettre::message::SinglePart> {
+        let content_type = ContentType::parse(&self.content_type)
+            .with_context(|| format!("Invalid content type {:?}", self.content_type))?;
+
+        let attachment = match &self.content_id {
+            Some(content_id) => lettre::message::Attachment::new_inline(content_id.clone()),
+            None => lettre::message::Attachment::new(self.filename.clone()),
+        };
+
+        Ok(attachment.body(self.data.clone(), content_type))
+    }
+}
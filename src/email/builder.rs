@@ -1,93 +1,519 @@
-use color_eyre::eyre::Result;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{bail, Context, Result};
+use cuid2::create_id;
 use email_address::EmailAddress;
-use tracing::error;
 
-use crate::{db::DB, email::TemplateEmail};
+use crate::{db::DB, email::TemplateEmail, sender::Sender};
 
-use super::{tags::Tags, Email, EmailModel, PlainEmail};
+use super::{
+    html::{is_local_url, rewrite_img_src, unescape_attribute},
+    normalize_locale,
+    tags::Tags,
+    Attachment, CustomHeader, Email, EmailModel, EmailStatus, EmailVariant, Headers, MarkdownEmail,
+    PlainEmail,
+};
 
-#[derive(Default)]
-pub struct EmailBuilder {
-    sender_adresse: Option<EmailAddress>,
+/// Builder of an [`Email`].
+///
+/// The sender and the body are part of the type of the builder, so that an email can only
+/// be built once both are set, and with a single body:
+///
+/// ```ignore
+/// let email = EmailBuilder::new()
+///     .sender_adresse("Tarak <tarak@example.com>")?
+///     .subject("Greeting")
+///     .plain_body("Hello from <strong>Sequoia</strong>")
+///     .build()?;
+/// ```
+pub struct EmailBuilder<S = NoSender, B = NoBody> {
+    sender_adresse: S,
+    body: B,
     tags: Option<Tags>,
     subject: Option<String>,
-    plain_body: Option<String>,
-    template_body: Option<String>,
-    source_path: Option<String>,
+    attachments: Vec<Attachment>,
+    max_attachments_size: Option<usize>,
+    headers: Headers,
+    locale: Option<String>,
+    variants: Vec<EmailVariant>,
+    draft: bool,
+    track_opens: bool,
+}
+
+/// State of an [`EmailBuilder`] whose sender isn't set yet.
+pub struct NoSender;
+
+/// State of an [`EmailBuilder`] whose sender is set.
+pub struct HasSender {
+    adresse: EmailAddress,
+    sender: Option<Sender>,
+}
+
+/// State of an [`EmailBuilder`] whose body isn't set yet.
+pub struct NoBody;
+
+/// State of an [`EmailBuilder`] with an HTML body.
+pub struct PlainBody {
+    body: String,
+    text_body: Option<String>,
+    images_root: Option<PathBuf>,
+}
+
+/// State of an [`EmailBuilder`] with a template body.
+pub struct TemplateBody {
+    body: String,
+    source_path: String,
+    text_body: Option<String>,
+}
+
+/// State of an [`EmailBuilder`] with a Markdown body.
+pub struct MarkdownBody {
+    source: String,
+    stylesheet: Option<String>,
+}
+
+/// The body of an email being built.
+pub trait Body: sealed::Sealed {
+    #[doc(hidden)]
+    fn into_model(self, subject: String, attachments: &mut Vec<Attachment>) -> Result<EmailModel>;
+}
+
+mod sealed {
+    pub trait Sealed {}
+
+    impl Sealed for super::PlainBody {}
+    impl Sealed for super::TemplateBody {}
+    impl Sealed for super::MarkdownBody {}
+}
+
+impl Body for PlainBody {
+    fn into_model(self, subject: String, attachments: &mut Vec<Attachment>) -> Result<EmailModel> {
+        let body = match &self.images_root {
+            Some(root) => embed_local_images(&self.body, root, attachments)?,
+            None => self.body,
+        };
+
+        Ok(EmailModel::Plain(PlainEmail::new(
+            subject,
+            body,
+            self.text_body,
+        )?))
+    }
+}
+
+impl Body for TemplateBody {
+    fn into_model(self, subject: String, _: &mut Vec<Attachment>) -> Result<EmailModel> {
+        Ok(EmailModel::Template(TemplateEmail::new(
+            subject,
+            self.body,
+            self.source_path,
+            self.text_body,
+        )))
+    }
+}
+
+impl Body for MarkdownBody {
+    fn into_model(self, subject: String, _: &mut Vec<Attachment>) -> Result<EmailModel> {
+        Ok(EmailModel::Markdown(MarkdownEmail::new(
+            subject,
+            self.source,
+            self.stylesheet,
+        )))
+    }
 }
 
 impl EmailBuilder {
     pub fn new() -> Self {
-        Self::default()
+        Self {
+            sender_adresse: NoSender,
+            body: NoBody,
+            tags: None,
+            subject: None,
+            attachments: Vec::new(),
+            max_attachments_size: None,
+            headers: Headers::default(),
+            locale: None,
+            variants: Vec::new(),
+            draft: false,
+            track_opens: false,
+        }
     }
+}
+
+impl Default for EmailBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
+impl<B> EmailBuilder<NoSender, B> {
+    pub fn sender_adresse(self, sender_adresse: &str) -> Result<EmailBuilder<HasSender, B>> {
+        Ok(self.with_sender(HasSender {
+            adresse: sender_adresse.parse()?,
+            sender: None,
+        }))
+    }
+
+    /// Send the email as `sender`, with its name, reply-to, signature and credentials.
+    pub fn sender(self, sender: &Sender) -> Result<EmailBuilder<HasSender, B>> {
+        Ok(self.with_sender(HasSender {
+            adresse: sender.adresse().parse()?,
+            sender: Some(sender.clone()),
+        }))
+    }
+}
+
+impl<S> EmailBuilder<S, NoBody> {
+    pub fn plain_body(self, body: &str) -> EmailBuilder<S, PlainBody> {
+        self.with_body(PlainBody {
+            body: body.to_owned(),
+            text_body: None,
+            images_root: None,
+        })
+    }
+
+    /// Template body, rendered for each recipient.
+    pub fn template_body(self, body: &str) -> EmailBuilder<S, TemplateBody> {
+        self.with_body(TemplateBody {
+            body: body.to_owned(),
+            source_path: String::new(),
+            text_body: None,
+        })
+    }
+
+    /// Template body read from the file at `source_path` in the [`TemplateStore`] when the
+    /// email is sent.
+    ///
+    /// [`TemplateStore`]: super::TemplateStore
+    pub fn template_path(self, source_path: &str) -> EmailBuilder<S, TemplateBody> {
+        self.with_body(TemplateBody {
+            body: String::new(),
+            source_path: source_path.to_owned(),
+            text_body: None,
+        })
+    }
+
+    /// Body written in Markdown. It is compiled to HTML, and sent as is as the plain-text
+    /// alternative.
+    pub fn markdown_body(self, source: &str) -> EmailBuilder<S, MarkdownBody> {
+        self.with_body(MarkdownBody {
+            source: source.to_owned(),
+            stylesheet: None,
+        })
+    }
+}
+
+impl<S> EmailBuilder<S, PlainBody> {
+    /// Plain-text alternative of the body. When not set, it is derived from the HTML body.
+    pub fn text_body(mut self, body: &str) -> Self {
+        self.body.text_body = Some(body.to_owned());
+        self
+    }
+
+    /// Embed the images that the body loads from local files, such as
+    /// `<img src="logo.png">`, as inline images. Paths are relative to `root`, and must not
+    /// escape it.
+    pub fn embed_local_images(mut self, root: impl Into<PathBuf>) -> Self {
+        self.body.images_root = Some(root.into());
+        self
+    }
+}
+
+impl<S> EmailBuilder<S, TemplateBody> {
+    /// Template of the plain-text alternative of the body. When not set, it is derived from
+    /// the rendered HTML body.
+    pub fn text_body(mut self, body: &str) -> Self {
+        self.body.text_body = Some(body.to_owned());
+        self
+    }
+}
+
+impl<S> EmailBuilder<S, MarkdownBody> {
+    /// CSS of the HTML compiled from the Markdown body, instead of the default stylesheet.
+    pub fn stylesheet(mut self, css: &str) -> Self {
+        self.body.stylesheet = Some(css.to_owned());
+        self
+    }
+}
+
+impl<S, B> EmailBuilder<S, B> {
     pub fn subject(mut self, subject: &str) -> Self {
         self.subject = Some(subject.to_owned());
         self
     }
 
-    pub fn sender_adresse(mut self, sender_adresse: &str) -> Result<Self> {
-        self.sender_adresse = Some(sender_adresse.parse()?);
+    pub fn tags(mut self, tags: Vec<String>) -> Result<Self> {
+        self.tags = Some(Tags::try_from(tags)?);
         Ok(self)
     }
 
-    pub fn tags(mut self, tags: Vec<String>) -> Result<Self> {
-        self.tags = Some(Tags::try_from(tags)?);
+    /// Locale of the content set on the builder, sent to the clients without a matching variant.
+    pub fn locale(mut self, locale: &str) -> Result<Self> {
+        self.locale = Some(normalize_locale(locale)?);
         Ok(self)
     }
 
-    pub fn plain_body(mut self, body: &str) -> Self {
-        if self.template_body.is_some() {
-            error!("Plain body and template body are incompatible (template body already set)");
-            panic!();
+    /// Content of the email in another locale, sent to the clients with that locale.
+    pub fn variant(mut self, locale: &str, email: EmailModel) -> Result<Self> {
+        let variant = EmailVariant::new(locale, email)?;
+
+        if self
+            .variants
+            .iter()
+            .any(|previous| previous.locale() == variant.locale())
+        {
+            bail!("Email already has a variant in locale {}", variant.locale());
         }
 
-        self.plain_body = Some(body.to_owned());
+        self.variants.push(variant);
+        Ok(self)
+    }
+
+    /// Create the email as a draft, which can be updated but not sent until it is published.
+    pub fn draft(mut self) -> Self {
+        self.draft = true;
+        self
+    }
+
+    /// Embed a tracking pixel recording when the email is opened, see
+    /// [`Email::tracks_opens`].
+    pub fn track_opens(mut self) -> Self {
+        self.track_opens = true;
         self
     }
 
-    pub fn template_body(mut self, body: &str) -> Self {
-        if self.plain_body.is_some() {
-            error!("Plain body and template body are incompatible (plain body already set)");
-            panic!();
+    pub fn attachment(mut self, filename: &str, content_type: &str, data: Vec<u8>) -> Result<Self> {
+        self.attachments
+            .push(Attachment::new(filename, content_type, data)?);
+        Ok(self)
+    }
+
+    /// Attach a file. Its MIME type is guessed from its extension.
+    pub fn attachment_path(mut self, path: impl AsRef<Path>) -> Result<Self> {
+        self.attachments.push(Attachment::from_path(path)?);
+        Ok(self)
+    }
+
+    /// Add an image that the HTML body displays with `<img src="cid:<content_id>">`. Each
+    /// image must have its own Content-ID.
+    pub fn inline_image(
+        mut self,
+        content_id: &str,
+        content_type: &str,
+        data: Vec<u8>,
+    ) -> Result<Self> {
+        self.check_content_id(content_id)?;
+        self.attachments
+            .push(Attachment::new_inline(content_id, content_type, data)?);
+        Ok(self)
+    }
+
+    /// Add an image file that the HTML body displays with `<img src="cid:<content_id>">`.
+    /// Each image must have its own Content-ID.
+    pub fn inline_image_path(mut self, content_id: &str, path: impl AsRef<Path>) -> Result<Self> {
+        self.check_content_id(content_id)?;
+        self.attachments
+            .push(Attachment::inline_from_path(content_id, path)?);
+        Ok(self)
+    }
+
+    fn check_content_id(&self, content_id: &str) -> Result<()> {
+        if self
+            .attachments
+            .iter()
+            .any(|attachment| attachment.content_id() == Some(content_id))
+        {
+            bail!("Content-ID {content_id} is already used by another inline image");
         }
 
-        self.template_body = Some(body.to_owned());
-        self
+        Ok(())
+    }
+
+    pub fn reply_to(mut self, reply_to: &str) -> Result<Self> {
+        self.headers.reply_to = Some(reply_to.parse()?);
+        Ok(self)
+    }
+
+    /// Add a carbon copy recipient, who receives every send of the email.
+    pub fn cc(mut self, cc: &str) -> Result<Self> {
+        self.headers.cc.push(cc.parse()?);
+        Ok(self)
     }
 
-    pub fn source_path(mut self, source_path: &str) -> Self {
-        self.source_path = Some(source_path.to_owned());
+    /// Add a blind carbon copy recipient, who receives every send of the email.
+    pub fn bcc(mut self, bcc: &str) -> Result<Self> {
+        self.headers.bcc.push(bcc.parse()?);
+        Ok(self)
+    }
+
+    /// Add a custom header, such as `X-Campaign`. Fails if a header with the same name was
+    /// already added.
+    pub fn header(mut self, name: &str, value: &str) -> Result<Self> {
+        self.headers.add_custom(CustomHeader::new(name, value)?)?;
+        Ok(self)
+    }
+
+    /// Limit on the total size of the attachments, in bytes.
+    /// Defaults to [`Attachment::DEFAULT_MAX_TOTAL_SIZE`].
+    pub fn max_attachments_size(mut self, max_size: usize) -> Self {
+        self.max_attachments_size = Some(max_size);
         self
     }
 
-    pub async fn create(self, db: &DB) -> Result<Email> {
+    fn with_body<B2>(self, body: B2) -> EmailBuilder<S, B2> {
+        let Self {
+            sender_adresse,
+            body: _,
+            tags,
+            subject,
+            attachments,
+            max_attachments_size,
+            headers,
+            locale,
+            variants,
+            draft,
+            track_opens,
+        } = self;
+
+        EmailBuilder {
+            sender_adresse,
+            body,
+            tags,
+            subject,
+            attachments,
+            max_attachments_size,
+            headers,
+            locale,
+            variants,
+            draft,
+            track_opens,
+        }
+    }
+
+    fn with_sender<S2>(self, sender_adresse: S2) -> EmailBuilder<S2, B> {
+        let Self {
+            sender_adresse: _,
+            body,
+            tags,
+            subject,
+            attachments,
+            max_attachments_size,
+            headers,
+            locale,
+            variants,
+            draft,
+            track_opens,
+        } = self;
+
+        EmailBuilder {
+            sender_adresse,
+            body,
+            tags,
+            subject,
+            attachments,
+            max_attachments_size,
+            headers,
+            locale,
+            variants,
+            draft,
+            track_opens,
+        }
+    }
+}
+
+impl<B: Body> EmailBuilder<HasSender, B> {
+    /// Build the email without saving it. It is saved with [`Email::create`].
+    pub fn build(mut self) -> Result<Email> {
         let subject = self.subject.unwrap_or_default();
+        let email = self.body.into_model(subject, &mut self.attachments)?;
 
-        let sender_adresse = if let Some(sender_adresse) = self.sender_adresse {
-            sender_adresse
-        } else {
-            error!("Sender adresse is required when building email");
-            panic!();
-        };
+        Attachment::check_total_size(
+            &self.attachments,
+            self.max_attachments_size
+                .unwrap_or(Attachment::DEFAULT_MAX_TOTAL_SIZE),
+        )?;
 
-        let email = if let Some(plain_body) = self.plain_body {
-            EmailModel::Plain(PlainEmail::new(subject, plain_body))
-        } else if let Some(template_body) = self.template_body {
-            let source_path = if let Some(source_path) = self.source_path {
-                source_path
-            } else {
-                error!("Source path is required when building template email");
-                panic!();
-            };
-
-            EmailModel::Template(TemplateEmail::new(subject, template_body, source_path))
+        let status = if self.draft {
+            EmailStatus::Draft
         } else {
-            EmailModel::Plain(PlainEmail::new(subject, "".to_owned()))
+            EmailStatus::Published
         };
 
-        let tags = self.tags.unwrap_or_default();
+        if let Some(locale) = &self.locale {
+            if self
+                .variants
+                .iter()
+                .any(|variant| variant.locale() == locale)
+            {
+                bail!("Locale {locale} is both the locale of the email and of a variant");
+            }
+        }
+
+        let mut email = Email::new(
+            status,
+            self.sender_adresse.adresse,
+            email,
+            self.tags.unwrap_or_default(),
+            self.headers,
+            self.attachments,
+        );
+        email.sender = self.sender_adresse.sender;
+        email.locale = self.locale;
+        email.track_opens = self.track_opens;
+        email.variants = self.variants;
 
-        Email::create(sender_adresse, email, tags.into_vec(), db).await
+        Ok(email)
     }
+
+    /// Build the email and save it.
+    pub async fn create(self, db: &DB) -> Result<Email> {
+        let email = self.build()?;
+
+        email.create(db).await?;
+
+        Ok(email)
+    }
+}
+
+/// Replace the local images of `html` by inline images, added to `attachments`.
+fn embed_local_images(
+    html: &str,
+    root: &Path,
+    attachments: &mut Vec<Attachment>,
+) -> Result<String> {
+    let root = root
+        .canonicalize()
+        .with_context(|| format!("Resolving image root {}", root.display()))?;
+    // The same file is only embedded once
+    let mut content_ids = HashMap::new();
+
+    rewrite_img_src(html, |src| {
+        let src = unescape_attribute(src);
+        if !is_local_url(&src) {
+            return Ok(None);
+        }
+
+        // Symbolic links and `..` are resolved before checking that the file is under the root
+        let path = root
+            .join(&src)
+            .canonicalize()
+            .with_context(|| format!("Resolving image {src}"))?;
+        if !path.starts_with(&root) {
+            bail!(
+                "Image {src} must be under the image root {}",
+                root.display()
+            );
+        }
+
+        if let Some(content_id) = content_ids.get(&path) {
+            return Ok(Some(format!("cid:{content_id}")));
+        }
+
+        let content_id = create_id();
+        attachments.push(Attachment::inline_from_path(&content_id, &path)?);
+        content_ids.insert(path, content_id.clone());
+
+        Ok(Some(format!("cid:{content_id}")))
+    })
 }
@@ -0,0 +1,529 @@
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fmt::Display;
+use std::path::Path;
+
+use color_eyre::eyre::{bail, Context, Result};
+use serde_derive::Deserialize;
+
+use crate::db::DB;
+
+use super::{Email, EmailBuilder, EmailModel, EmailStatus, HasSender, Headers, TemplateBody};
+
+/// Fields of an email that are part of its content, which is revised when one changes.
+const CONTENT_FIELDS: [&str; 6] = [
+    "subject",
+    "body kind",
+    "body",
+    "template_path",
+    "text_body",
+    "stylesheet",
+];
+
+/// An email kept in a YAML or TOML file, and synced into the database by its key:
+///
+/// ```yaml
+/// key: welcome
+/// sender: Bureau <bureau@example.com>
+/// subject: Welcome {{ client.adresse }}
+/// template_path: welcome/body.html
+/// tags: [onboarding]
+/// headers:
+///   X-Campaign: welcome
+/// ```
+///
+/// The body is exactly one of `body` (HTML), `markdown`, `template` and `template_path`.
+/// Syncing a definition whose content changed adds a revision to its email, even when it
+/// is published, since the file is the reference. Emails whose definition is removed are
+/// kept.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct EmailDefinition {
+    /// Stable key of the email, the name of the file without its extension by default
+    #[serde(default)]
+    key: String,
+    sender: String,
+    #[serde(default)]
+    subject: String,
+    body: Option<String>,
+    markdown: Option<String>,
+    template: Option<String>,
+    template_path: Option<String>,
+    text_body: Option<String>,
+    stylesheet: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    locale: Option<String>,
+    reply_to: Option<String>,
+    #[serde(default)]
+    cc: Vec<String>,
+    #[serde(default)]
+    bcc: Vec<String>,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    /// Create the email as a draft. The draft is published once this is unset.
+    #[serde(default)]
+    draft: bool,
+    /// Embed a tracking pixel recording when the email is opened
+    #[serde(default)]
+    track_opens: bool,
+}
+
+/// What syncing an [`EmailDefinition`] changes in the database.
+#[derive(Debug)]
+pub struct EmailDiff {
+    key: String,
+    change: EmailChange,
+}
+
+#[derive(Debug)]
+pub enum EmailChange {
+    Create,
+    Update(Vec<FieldChange>),
+    Unchanged,
+}
+
+/// A field of an email whose value differs from its definition.
+#[derive(Debug)]
+pub struct FieldChange {
+    field: &'static str,
+    before: String,
+    after: String,
+}
+
+impl EmailDefinition {
+    /// Read the definition in a `.yaml`, `.yml` or `.toml` file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Reading email definition {}", path.display()))?;
+
+        let mut definition: Self = match path.extension().and_then(OsStr::to_str) {
+            Some("yaml" | "yml") => serde_yaml::from_str(&source)
+                .with_context(|| format!("Parsing email definition {}", path.display()))?,
+            Some("toml") => toml::from_str(&source)
+                .with_context(|| format!("Parsing email definition {}", path.display()))?,
+            _ => bail!(
+                "Email definition {} must be a .yaml, .yml or .toml file",
+                path.display()
+            ),
+        };
+
+        if definition.key.is_empty() {
+            definition.key = path
+                .file_stem()
+                .and_then(OsStr::to_str)
+                .unwrap_or_default()
+                .to_owned();
+        }
+
+        Ok(definition)
+    }
+
+    /// Read the definitions of the `.yaml`, `.yml` and `.toml` files of `dir`, in the order
+    /// of their names. Other files are ignored.
+    pub fn load_dir(dir: impl AsRef<Path>) -> Result<Vec<Self>> {
+        let dir = dir.as_ref();
+
+        let mut paths = Vec::new();
+        for entry in std::fs::read_dir(dir)
+            .with_context(|| format!("Reading email definitions in {}", dir.display()))?
+        {
+            let path = entry?.path();
+
+            if path.is_file()
+                && matches!(
+                    path.extension().and_then(OsStr::to_str),
+                    Some("yaml" | "yml" | "toml")
+                )
+            {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        let mut keys = BTreeMap::new();
+        let mut definitions = Vec::with_capacity(paths.len());
+        for path in paths {
+            let definition = Self::from_file(&path)?;
+
+            if let Some(previous) = keys.insert(definition.key.clone(), path.clone()) {
+                bail!(
+                    "Email definitions {} and {} have the same key {:?}",
+                    previous.display(),
+                    path.display(),
+                    definition.key
+                );
+            }
+
+            definitions.push(definition);
+        }
+
+        Ok(definitions)
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// What [`EmailDefinition::sync`] would change, without changing anything.
+    pub async fn diff(&self, db: &DB) -> Result<EmailDiff> {
+        let new = self.build()?;
+
+        let change = match Email::get_by_key(&self.key, db).await? {
+            None => EmailChange::Create,
+            Some(existing) => changes(&existing, &new),
+        };
+
+        Ok(self.email_diff(change))
+    }
+
+    /// Create the email of the definition, or update it to match the definition.
+    pub async fn sync(&self, db: &DB) -> Result<EmailDiff> {
+        let new = self.build()?;
+
+        let Some(mut existing) = Email::get_by_key(&self.key, db).await? else {
+            new.create(db).await?;
+            return Ok(self.email_diff(EmailChange::Create));
+        };
+
+        let change = changes(&existing, &new);
+        if let EmailChange::Update(fields) = &change {
+            let content_changed = fields
+                .iter()
+                .any(|change| CONTENT_FIELDS.contains(&change.field));
+
+            existing.sync_from(new, content_changed, db).await?;
+        }
+
+        Ok(self.email_diff(change))
+    }
+
+    fn email_diff(&self, change: EmailChange) -> EmailDiff {
+        EmailDiff {
+            key: self.key.clone(),
+            change,
+        }
+    }
+
+    /// The email of the definition, not saved.
+    fn build(&self) -> Result<Email> {
+        let mut builder = EmailBuilder::new()
+            .sender_adresse(&self.sender)?
+            .subject(&self.subject)
+            .tags(self.tags.clone())?;
+
+        if let Some(locale) = &self.locale {
+            builder = builder.locale(locale)?;
+        }
+        if let Some(reply_to) = &self.reply_to {
+            builder = builder.reply_to(reply_to)?;
+        }
+        for cc in &self.cc {
+            builder = builder.cc(cc)?;
+        }
+        for bcc in &self.bcc {
+            builder = builder.bcc(bcc)?;
+        }
+        for (name, value) in &self.headers {
+            builder = builder.header(name, value)?;
+        }
+        if self.draft {
+            builder = builder.draft();
+        }
+        if self.track_opens {
+            builder = builder.track_opens();
+        }
+
+        let is_markdown = self.markdown.is_some();
+        if self.stylesheet.is_some() && !is_markdown {
+            bail!(
+                "Email definition {}: stylesheet is only for markdown bodies, which are compiled to HTML",
+                self.key
+            );
+        }
+        if self.text_body.is_some() && is_markdown {
+            bail!(
+                "Email definition {}: text_body isn't for markdown bodies, which are their own text body",
+                self.key
+            );
+        }
+
+        let mut email = match (
+            &self.body,
+            &self.markdown,
+            &self.template,
+            &self.template_path,
+        ) {
+            (Some(body), None, None, None) => {
+                let builder = builder.plain_body(body);
+                match &self.text_body {
+                    Some(text_body) => builder.text_body(text_body).build()?,
+                    None => builder.build()?,
+                }
+            }
+            (None, Some(source), None, None) => {
+                let builder = builder.markdown_body(source);
+                match &self.stylesheet {
+                    Some(stylesheet) => builder.stylesheet(stylesheet).build()?,
+                    None => builder.build()?,
+                }
+            }
+            (None, None, Some(template), None) => {
+                with_text_body(builder.template_body(template), &self.text_body)?
+            }
+            (None, None, None, Some(template_path)) => {
+                with_text_body(builder.template_path(template_path), &self.text_body)?
+            }
+            _ => bail!(
+                "Email definition {} must have exactly one of body, markdown, template and template_path",
+                self.key
+            ),
+        };
+
+        email.key = Some(self.key.clone());
+
+        Ok(email)
+    }
+}
+
+fn with_text_body(
+    builder: EmailBuilder<HasSender, TemplateBody>,
+    text_body: &Option<String>,
+) -> Result<Email> {
+    match text_body {
+        Some(text_body) => builder.text_body(text_body).build(),
+        None => builder.build(),
+    }
+}
+
+impl Email {
+    /// Update the email to `new`, built from its definition. The content is only replaced,
+    /// adding a revision, when it changed.
+    async fn sync_from(&mut self, new: Email, content_changed: bool, db: &DB) -> Result<()> {
+        let Email {
+            email,
+            sender_adresse,
+            status,
+            tags,
+            locale,
+            track_opens,
+            headers,
+            ..
+        } = new;
+
+        let revision = self.revision + u32::from(content_changed);
+
+        // A draft is published once its definition isn't a draft anymore, but a published
+        // email can't go back to being a draft
+        let status = if self.status == EmailStatus::Draft {
+            status
+        } else {
+            self.status
+        };
+
+        // The sender of the email doesn't match the definition anymore
+        let sender = self
+            .sender
+            .clone()
+            .filter(|sender| sender.adresse() == sender_adresse.as_str());
+
+        // The content and the rest of the email are written together, so that a failure
+        // doesn't leave a new revision with stale metadata
+        db.connection(|conn| {
+            let tx = conn.unchecked_transaction()?;
+
+            if content_changed {
+                self.write_content(revision, &email, &tx)?;
+            }
+
+            tx.execute(
+                r"
+                UPDATE Email SET status = ?, sender_adresse = ?, sender_ID = ?, reply_to = ?, cc = ?, bcc = ?, locale = ?, track_opens = ?
+                    WHERE ID = ?
+            ",
+                (
+                    status as u8,
+                    sender_adresse.as_str(),
+                    sender.as_ref().map(|sender| sender.id()),
+                    headers.reply_to.as_ref().map(ToString::to_string),
+                    Headers::mailboxes_to_sql(&headers.cc),
+                    Headers::mailboxes_to_sql(&headers.bcc),
+                    &locale,
+                    track_opens,
+                    &self.id,
+                ),
+            )?;
+
+            tx.execute("DELETE FROM MM_EmailTag WHERE email_ID = ?", [&self.id])?;
+            tags.write(&self.id, &tx)?;
+
+            tx.execute("DELETE FROM EmailHeader WHERE email_ID = ?", [&self.id])?;
+            for header in &headers.custom {
+                header.write(&self.id, &tx)?;
+            }
+
+            tx.commit()?;
+
+            Ok(())
+        })
+        .await?;
+
+        if content_changed {
+            self.email = email;
+            self.revision = revision;
+        }
+        self.status = status;
+        self.sender_adresse = sender_adresse;
+        self.sender = sender;
+        self.tags = tags;
+        self.locale = locale;
+        self.track_opens = track_opens;
+        self.headers = headers;
+
+        Ok(())
+    }
+}
+
+impl EmailDiff {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn change(&self) -> &EmailChange {
+        &self.change
+    }
+
+    pub fn is_unchanged(&self) -> bool {
+        matches!(self.change, EmailChange::Unchanged)
+    }
+}
+
+impl Display for EmailDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.change {
+            EmailChange::Create => write!(f, "+ {}", self.key),
+            EmailChange::Unchanged => write!(f, "= {}", self.key),
+            EmailChange::Update(fields) => {
+                write!(f, "~ {}", self.key)?;
+                for field in fields {
+                    write!(f, "\n    {field}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl FieldChange {
+    pub fn field(&self) -> &str {
+        self.field
+    }
+
+    /// Value in the database.
+    pub fn before(&self) -> &str {
+        &self.before
+    }
+
+    /// Value in the definition.
+    pub fn after(&self) -> &str {
+        &self.after
+    }
+}
+
+impl Display for FieldChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {:?} -> {:?}", self.field, self.before, self.after)
+    }
+}
+
+fn changes(existing: &Email, new: &Email) -> EmailChange {
+    let mut changes: Vec<FieldChange> = fields(existing)
+        .into_iter()
+        .zip(fields(new))
+        .filter(|((_, old), (_, new))| old != new)
+        .map(|((field, before), (_, after))| FieldChange {
+            field,
+            before,
+            after,
+        })
+        .collect();
+
+    if existing.status == EmailStatus::Draft && new.status == EmailStatus::Published {
+        changes.push(FieldChange {
+            field: "status",
+            before: "draft".to_owned(),
+            after: "published".to_owned(),
+        });
+    }
+
+    if changes.is_empty() {
+        EmailChange::Unchanged
+    } else {
+        EmailChange::Update(changes)
+    }
+}
+
+/// The fields of `email` that a definition describes, as text.
+fn fields(email: &Email) -> Vec<(&'static str, String)> {
+    let (kind, body, template_path, stylesheet) = match &email.email {
+        EmailModel::Plain(plain) => ("body", plain.body(), "", ""),
+        EmailModel::Template(template) if template.source_path().is_empty() => {
+            ("template", template.body(), "", "")
+        }
+        EmailModel::Template(template) => {
+            ("template_path", template.body(), template.source_path(), "")
+        }
+        EmailModel::Markdown(markdown) => (
+            "markdown",
+            markdown.source(),
+            "",
+            markdown.stylesheet().unwrap_or_default(),
+        ),
+    };
+
+    let mut headers: Vec<String> = email
+        .headers
+        .custom
+        .iter()
+        .map(|header| format!("{}: {}", header.name(), header.value()))
+        .collect();
+    headers.sort();
+
+    let join = |mailboxes: &[lettre::message::Mailbox]| {
+        mailboxes
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    vec![
+        ("sender", email.sender_adresse().to_owned()),
+        ("subject", email.subject().to_owned()),
+        ("body kind", kind.to_owned()),
+        ("body", body.to_owned()),
+        ("template_path", template_path.to_owned()),
+        (
+            "text_body",
+            email.text_body().unwrap_or_default().to_owned(),
+        ),
+        ("stylesheet", stylesheet.to_owned()),
+        ("tags", email.tags().join(", ")),
+        ("locale", email.locale().unwrap_or_default().to_owned()),
+        (
+            "reply_to",
+            email
+                .headers
+                .reply_to
+                .as_ref()
+                .map(ToString::to_string)
+                .unwrap_or_default(),
+        ),
+        ("cc", join(&email.headers.cc)),
+        ("bcc", join(&email.headers.bcc)),
+        ("headers", headers.join("\n")),
+        ("track_opens", email.tracks_opens().to_string()),
+    ]
+}
@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+
+use color_eyre::eyre::{bail, Result};
+use lettre::message::header::{HeaderName, HeaderValue};
+use lettre::message::{Mailbox, Mailboxes};
+use rusqlite::{params_from_iter, Connection};
+
+/// Headers that are set by Sequoia itself, and thus can't be custom headers.
+const RESERVED_HEADERS: &[&str] = &[
+    "bcc",
+    "cc",
+    "content-disposition",
+    "content-id",
+    "content-transfer-encoding",
+    "content-type",
+    "date",
+    "dkim-signature",
+    "from",
+    "message-id",
+    "mime-version",
+    "reply-to",
+    "return-path",
+    "sender",
+    "subject",
+    "to",
+];
+
+/// Headers of an email besides From, To and Subject.
+#[derive(Debug, Default)]
+pub struct Headers {
+    pub(super) reply_to: Option<Mailbox>,
+    pub(super) cc: Vec<Mailbox>,
+    pub(super) bcc: Vec<Mailbox>,
+    pub(super) custom: Vec<CustomHeader>,
+}
+
+impl Headers {
+    pub fn reply_to(&self) -> Option<&Mailbox> {
+        self.reply_to.as_ref()
+    }
+
+    pub fn cc(&self) -> &[Mailbox] {
+        &self.cc
+    }
+
+    pub fn bcc(&self) -> &[Mailbox] {
+        &self.bcc
+    }
+
+    /// Whether each message is copied to CC or BCC recipients.
+    pub fn has_copies(&self) -> bool {
+        !self.cc.is_empty() || !self.bcc.is_empty()
+    }
+
+    pub fn custom(&self) -> &[CustomHeader] {
+        &self.custom
+    }
+
+    /// Add a custom header. Header names are case-insensitive, and each can only be set once.
+    pub(super) fn add_custom(&mut self, header: CustomHeader) -> Result<()> {
+        if self
+            .custom
+            .iter()
+            .any(|custom| custom.name.eq_ignore_ascii_case(&header.name))
+        {
+            bail!("Header {} is already set", header.name);
+        }
+
+        self.custom.push(header);
+
+        Ok(())
+    }
+
+    /// Mailboxes in the format stored in the `Email` table, `NULL` for an empty list.
+    pub(super) fn mailboxes_to_sql(mailboxes: &[Mailbox]) -> Option<String> {
+        if mailboxes.is_empty() {
+            None
+        } else {
+            Some(Mailboxes::from_iter(mailboxes.iter().cloned()).to_string())
+        }
+    }
+
+    pub(super) fn mailboxes_from_sql(mailboxes: Option<String>) -> Result<Vec<Mailbox>> {
+        match mailboxes {
+            Some(mailboxes) if !mailboxes.is_empty() => {
+                Ok(mailboxes.parse::<Mailboxes>()?.into_iter().collect())
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+}
+
+/// An arbitrary header, such as `X-Campaign`.
+#[derive(Debug, Clone)]
+pub struct CustomHeader {
+    name: String,
+    value: String,
+}
+
+impl CustomHeader {
+    pub(crate) const CREATE_TABLES: &'static str = r#"
+        CREATE TABLE IF NOT EXISTS EmailHeader (
+            email_ID  TEXT NOT NULL,
+            name      TEXT NOT NULL,
+            value     TEXT NOT NULL,
+            FOREIGN KEY (email_ID)  REFERENCES Email(ID)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE
+        ) STRICT;
+    "#;
+
+    /// Fail when `name` isn't a valid header name (RFC 5322), or is a header set by Sequoia.
+    pub fn new(name: &str, value: &str) -> Result<Self> {
+        let valid = !name.is_empty()
+            && name
+                .bytes()
+                .all(|byte| byte.is_ascii_graphic() && byte != b':');
+
+        if !valid {
+            bail!("Invalid header name {name:?}");
+        }
+
+        if RESERVED_HEADERS.contains(&name.to_ascii_lowercase().as_str()) {
+            bail!("Header {name} can't be set as a custom header");
+        }
+
+        if value.contains(['\r', '\n']) {
+            bail!("Value of header {name} contains a line break");
+        }
+
+        Ok(Self {
+            name: name.to_owned(),
+            value: value.to_owned(),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub(crate) fn to_header_value(&self) -> Result<HeaderValue> {
+        let name = HeaderName::new_from_ascii(self.name.clone())?;
+
+        Ok(HeaderValue::new(name, self.value.clone()))
+    }
+
+    pub(super) fn write(&self, email_id: &str, conn: &Connection) -> Result<()> {
+        let mut stmt = conn
+            .prepare_cached("INSERT INTO EmailHeader (email_ID, name, value) VALUES (?, ?, ?)")?;
+
+        stmt.execute((email_id, &self.name, &self.value))?;
+
+        Ok(())
+    }
+
+    /// Custom headers of each of the emails `email_ids`, by email ID.
+    pub(super) fn get_for_emails(
+        email_ids: &[&str],
+        conn: &Connection,
+    ) -> Result<HashMap<String, Vec<Self>>> {
+        let placeholders = vec!["?"; email_ids.len()].join(", ");
+        let mut stmt = conn.prepare(&format!(
+            "SELECT email_ID, name, value FROM EmailHeader WHERE email_ID IN ({placeholders}) ORDER BY rowid",
+        ))?;
+
+        let rows = stmt.query_map(params_from_iter(email_ids), |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                Self {
+                    name: row.get(1)?,
+                    value: row.get(2)?,
+                },
+            ))
+        })?;
+
+        let mut headers: HashMap<String, Vec<Self>> = HashMap::new();
+        for row in rows {
+            let (email_id, header) = row?;
+            headers.entry(email_id).or_default().push(header);
+        }
+
+        Ok(headers)
+    }
+}
@@ -0,0 +1,173 @@
+use std::sync::LazyLock;
+
+use color_eyre::eyre::Result;
+use regex::{Captures, Regex};
+
+static IMG_SRC: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?is)(<img\b[^>]*?\bsrc\s*=\s*)(?:"([^"]*)"|'([^']*)')"#).unwrap()
+});
+
+// `href` follows whitespace, so that attributes such as `data-href` aren't mistaken for it
+static A_HREF: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?is)(<a\b[^>]*?\shref\s*=\s*)(?:"([^"]*)"|'([^']*)')"#).unwrap()
+});
+
+static A_TAG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?is)<a\b([^>]*)>(.*?)</a\s*>").unwrap());
+
+static IMG_TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?is)<img\b([^>]*)>").unwrap());
+
+static ATTRIBUTE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?is)(?:^|\s)([a-z][a-z0-9_:-]*)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'=<>`]+))"#)
+        .unwrap()
+});
+
+/// A link of an HTML document.
+pub(crate) struct Link<'h> {
+    pub href: Option<&'h str>,
+    /// HTML between the opening and closing tags
+    pub content: &'h str,
+}
+
+/// An image of an HTML document.
+pub(crate) struct Image<'h> {
+    pub src: Option<&'h str>,
+    pub alt: Option<&'h str>,
+}
+
+/// The `<a>` tags of `html`.
+pub(crate) fn links(html: &str) -> impl Iterator<Item = Link<'_>> {
+    A_TAG.captures_iter(html).map(|captures| {
+        // Unwrap is safe because both groups are part of every match
+        let attributes = captures.get(1).unwrap().as_str();
+
+        Link {
+            href: attribute(attributes, "href"),
+            content: captures.get(2).unwrap().as_str(),
+        }
+    })
+}
+
+/// The `<img>` tags of `html`.
+pub(crate) fn images(html: &str) -> impl Iterator<Item = Image<'_>> {
+    IMG_TAG.captures_iter(html).map(|captures| {
+        // Unwrap is safe because the group is part of every match
+        let attributes = captures.get(1).unwrap().as_str();
+
+        Image {
+            src: attribute(attributes, "src"),
+            alt: attribute(attributes, "alt"),
+        }
+    })
+}
+
+/// Value of the attribute `name` among the `attributes` of a tag.
+fn attribute<'h>(attributes: &'h str, name: &str) -> Option<&'h str> {
+    ATTRIBUTE
+        .captures_iter(attributes)
+        .find(|captures| captures[1].eq_ignore_ascii_case(name))
+        .and_then(|captures| {
+            [2, 3, 4]
+                .into_iter()
+                .find_map(|group| captures.get(group))
+                .map(|value| value.as_str())
+        })
+}
+
+static BODY_END: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)</body\s*>").unwrap());
+
+/// Insert `fragment` at the end of the `<body>` of `html`, or after `html` when it has no
+/// `<body>` tag.
+pub(crate) fn append_to_body(html: &str, fragment: &str) -> String {
+    match BODY_END.find_iter(html).last() {
+        Some(end) => format!(
+            "{}{fragment}\n{}",
+            &html[..end.start()],
+            &html[end.start()..]
+        ),
+        None => format!("{html}\n{fragment}"),
+    }
+}
+
+/// Replace the `src` of the `<img>` tags of `html`. `replace` returns the new source of an
+/// image, or `None` to keep it unchanged.
+pub(crate) fn rewrite_img_src(
+    html: &str,
+    mut replace: impl FnMut(&str) -> Result<Option<String>>,
+) -> Result<String> {
+    rewrite(&IMG_SRC, html, &mut replace)
+}
+
+/// Replace the `href` of the `<a>` tags of `html`. `replace` returns the new target of a
+/// link, or `None` to keep it unchanged.
+pub(crate) fn rewrite_a_href(
+    html: &str,
+    mut replace: impl FnMut(&str) -> Result<Option<String>>,
+) -> Result<String> {
+    rewrite(&A_HREF, html, &mut replace)
+}
+
+/// Decode the character references an attribute value usually holds, such as `&amp;` in
+/// the query of a URL.
+pub(crate) fn unescape_attribute(value: &str) -> String {
+    if !value.contains('&') {
+        return value.to_owned();
+    }
+
+    value
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// Whether `url` points to a local file, i.e. has neither a scheme nor a host.
+pub(crate) fn is_local_url(url: &str) -> bool {
+    let has_scheme = url
+        .split_once(':')
+        .is_some_and(|(scheme, _)| !scheme.is_empty() && !scheme.contains('/'));
+
+    !url.is_empty() && !has_scheme && !url.starts_with("//") && !url.starts_with('#')
+}
+
+/// Rewrite the attribute value captured by `regex`, in either the second (double-quoted)
+/// or third (single-quoted) group, keeping the first group as is.
+fn rewrite(
+    regex: &Regex,
+    html: &str,
+    replace: &mut impl FnMut(&str) -> Result<Option<String>>,
+) -> Result<String> {
+    let mut rewritten = String::with_capacity(html.len());
+    let mut last = 0;
+
+    for captures in regex.captures_iter(html) {
+        let (value, quote) = attribute_value(&captures);
+
+        let Some(new_value) = replace(value)? else {
+            continue;
+        };
+
+        // Unwrap is safe because the group 0 always exists
+        let whole = captures.get(0).unwrap();
+        rewritten.push_str(&html[last..whole.start()]);
+        rewritten.push_str(&captures[1]);
+        rewritten.push(quote);
+        rewritten.push_str(&new_value);
+        rewritten.push(quote);
+        last = whole.end();
+    }
+
+    rewritten.push_str(&html[last..]);
+
+    Ok(rewritten)
+}
+
+fn attribute_value<'h>(captures: &Captures<'h>) -> (&'h str, char) {
+    match captures.get(2) {
+        Some(value) => (value.as_str(), '"'),
+        // Unwrap is safe because one of the alternatives matched
+        None => (captures.get(3).unwrap().as_str(), '\''),
+    }
+}
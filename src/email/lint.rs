@@ -0,0 +1,499 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::sync::{Arc, LazyLock};
+
+use chrono::Local;
+use minijinja::value::{Value, ValueKind};
+use minijinja::Environment;
+use regex::Regex;
+
+use super::html::{images, is_local_url, links, Link};
+use super::{html_to_text, Email, EmailModel, RenderContext, TemplateStore};
+use crate::client::Client;
+
+/// Size of the HTML body above which Gmail clips the message behind a "View entire
+/// message" link, hiding its end, and often the unsubscribe link with it.
+pub const GMAIL_CLIP_SIZE: usize = 102 * 1024;
+
+/// Variables of the [`RenderContext`] a template can use, with their fields, as in
+/// `{{ client.adresse }}`. They are read from a serialized context, so they always match
+/// what templates are rendered with.
+static CONTEXT_VARIABLES: LazyLock<HashMap<String, Vec<String>>> = LazyLock::new(|| {
+    // Unwrap is safe because the adresse is valid
+    let client = Client::new("client@example.com").unwrap();
+    let context = Value::from_serialize(RenderContext::new(&client, Local::now()));
+
+    keys(&context)
+        .into_iter()
+        .map(|name| {
+            let fields = context
+                .get_attr(&name)
+                .map(|value| keys(&value))
+                .unwrap_or_default();
+
+            (name, fields)
+        })
+        .collect()
+});
+
+/// Globals and special variables of minijinja, which templates use without declaring them.
+const TEMPLATE_GLOBALS: [&str; 10] = [
+    "range",
+    "dict",
+    "debug",
+    "namespace",
+    "loop",
+    "self",
+    "super",
+    "caller",
+    "varargs",
+    "kwargs",
+];
+
+static PLACEHOLDER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\{\{\s*(.*?)\s*\}\}").unwrap());
+
+static HTTP_URL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)^https?://[^\s/?#@]+(?:[/?#]\S*)?$").unwrap());
+
+/// The layout or partial a template extends or includes by name.
+static SHARED_TEMPLATE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"\{%-?\s*(?:extends|include)\s+(?:"([^"]+)"|'([^']+)')"#).unwrap()
+});
+
+static UNSUBSCRIBE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)unsubscribe|d[ée]sabonn|d[ée]sinscri").unwrap());
+
+/// How serious a [`Lint`] is. An email with errors should not be sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// A problem found in an email before it is sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LintKind {
+    /// The subject is empty, which is the default of the [`EmailBuilder`](super::EmailBuilder)
+    EmptySubject,
+    EmptyBody,
+    /// A link that can't be followed: empty, `#`, `javascript:` or a malformed URL
+    BrokenLink {
+        href: String,
+    },
+    /// A link without a scheme and host, which email clients can't resolve
+    RelativeLink {
+        href: String,
+    },
+    MissingAltText {
+        src: String,
+    },
+    /// The HTML body is larger than [`GMAIL_CLIP_SIZE`]. For templates, this is the size of
+    /// the source, before it is rendered.
+    OversizedHtml {
+        size: usize,
+    },
+    /// A template variable that isn't part of the render context, or a `{{ ... }}`
+    /// placeholder in an email that isn't a template, which would be sent as is
+    UnresolvedVariable {
+        name: String,
+    },
+    /// Neither a `List-Unsubscribe` header nor a link to unsubscribe in the body
+    MissingUnsubscribeLink,
+    /// A template that can't be loaded or parsed
+    InvalidTemplate {
+        error: String,
+    },
+}
+
+impl LintKind {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::MissingAltText { .. }
+            | Self::OversizedHtml { .. }
+            | Self::MissingUnsubscribeLink => Severity::Warning,
+            Self::EmptySubject
+            | Self::EmptyBody
+            | Self::BrokenLink { .. }
+            | Self::RelativeLink { .. }
+            | Self::UnresolvedVariable { .. }
+            | Self::InvalidTemplate { .. } => Severity::Error,
+        }
+    }
+}
+
+impl Display for LintKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::EmptySubject => write!(f, "empty subject"),
+            Self::EmptyBody => write!(f, "empty body"),
+            Self::BrokenLink { href } => write!(f, "broken link {href:?}"),
+            Self::RelativeLink { href } => write!(f, "relative link {href:?}"),
+            Self::MissingAltText { src } => write!(f, "image {src:?} has no alt text"),
+            Self::OversizedHtml { size } => write!(
+                f,
+                "HTML body of {size} bytes is larger than the {GMAIL_CLIP_SIZE} bytes Gmail clips at"
+            ),
+            Self::UnresolvedVariable { name } => write!(f, "unresolved template variable {name:?}"),
+            Self::MissingUnsubscribeLink => write!(f, "no unsubscribe link"),
+            Self::InvalidTemplate { error } => write!(f, "invalid template: {error}"),
+        }
+    }
+}
+
+/// A problem found in the default content of an email or in one of its variants.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lint {
+    kind: LintKind,
+    /// Locale of the variant the problem is in, `None` for the default content
+    locale: Option<String>,
+}
+
+impl Lint {
+    pub fn kind(&self) -> &LintKind {
+        &self.kind
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.kind.severity()
+    }
+
+    pub fn locale(&self) -> Option<&str> {
+        self.locale.as_deref()
+    }
+}
+
+impl Display for Lint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity() {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+
+        match &self.locale {
+            Some(locale) => write!(f, "{severity} in variant {locale}: {}", self.kind),
+            None => write!(f, "{severity}: {}", self.kind),
+        }
+    }
+}
+
+/// Problems found by [`Email::lint`].
+#[derive(Debug, Default)]
+pub struct LintReport {
+    lints: Vec<Lint>,
+}
+
+impl LintReport {
+    pub fn lints(&self) -> &[Lint] {
+        &self.lints
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Lint> {
+        self.with_severity(Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Lint> {
+        self.with_severity(Severity::Warning)
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.errors().next().is_some()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lints.is_empty()
+    }
+
+    fn with_severity(&self, severity: Severity) -> impl Iterator<Item = &Lint> {
+        self.lints
+            .iter()
+            .filter(move |lint| lint.severity() == severity)
+    }
+}
+
+impl Email {
+    /// Check the email, and each of its variants, for problems to fix before it is sent.
+    ///
+    /// Templates are checked from their source, without rendering them: links and images
+    /// whose URL comes from a template expression aren't checked.
+    pub fn lint(&self, templates: &TemplateStore) -> LintReport {
+        let has_unsubscribe_header = self
+            .headers()
+            .custom()
+            .iter()
+            .any(|header| header.name().eq_ignore_ascii_case("List-Unsubscribe"));
+
+        let mut report = LintReport::default();
+
+        let models = std::iter::once((None, &self.email)).chain(
+            self.variants
+                .iter()
+                .map(|variant| (Some(variant.locale()), &variant.email)),
+        );
+
+        for (locale, model) in models {
+            let kinds = lint_model(model, templates, has_unsubscribe_header);
+
+            report.lints.extend(kinds.into_iter().map(|kind| Lint {
+                kind,
+                locale: locale.map(str::to_owned),
+            }));
+        }
+
+        report
+    }
+}
+
+fn lint_model(
+    model: &EmailModel,
+    templates: &TemplateStore,
+    has_unsubscribe_header: bool,
+) -> Vec<LintKind> {
+    let mut lints = Vec::new();
+
+    if model.subject().trim().is_empty() {
+        lints.push(LintKind::EmptySubject);
+    }
+
+    // Layouts and partials the template extends or includes, which often hold the footer
+    // with the unsubscribe link
+    let mut shared = Vec::new();
+
+    let html = match model {
+        EmailModel::Plain(_) | EmailModel::Markdown(_) => {
+            // The text body is often derived from the HTML one, so the same placeholder is
+            // reported once
+            for text in [Some(model.subject()), Some(model.body()), model.text_body()]
+                .into_iter()
+                .flatten()
+            {
+                for lint in placeholders(text) {
+                    if !lints.contains(&lint) {
+                        lints.push(lint);
+                    }
+                }
+            }
+
+            let text = match model {
+                EmailModel::Markdown(markdown) => markdown.source().to_owned(),
+                _ => html_to_text(model.body()).unwrap_or_else(|_| model.body().to_owned()),
+            };
+            if text.trim().is_empty() {
+                lints.push(LintKind::EmptyBody);
+            }
+
+            model.body().to_owned()
+        }
+        EmailModel::Template(template) => {
+            let body = if template.source_path().is_empty() {
+                template.body().to_owned()
+            } else {
+                match templates.load(template.source_path()) {
+                    Ok(body) => body.to_string(),
+                    Err(err) => {
+                        lints.push(LintKind::InvalidTemplate {
+                            error: format!("{err:#}"),
+                        });
+                        return lints;
+                    }
+                }
+            };
+
+            if body.trim().is_empty() {
+                lints.push(LintKind::EmptyBody);
+            }
+
+            for source in [
+                Some(template.subject()),
+                Some(body.as_str()),
+                template.text_body(),
+            ]
+            .into_iter()
+            .flatten()
+            {
+                lints.extend(undeclared_variables(source));
+            }
+
+            shared = shared_sources(&body, templates);
+
+            body
+        }
+    };
+
+    if html.len() > GMAIL_CLIP_SIZE {
+        lints.push(LintKind::OversizedHtml { size: html.len() });
+    }
+
+    let mut has_unsubscribe_link = false;
+    for link in links(&html) {
+        let Some(href) = link.href else {
+            // A named anchor
+            continue;
+        };
+
+        if is_unsubscribe_link(&link) {
+            has_unsubscribe_link = true;
+        }
+
+        lints.extend(check_href(href));
+    }
+
+    has_unsubscribe_link = has_unsubscribe_link
+        || shared
+            .iter()
+            .any(|source| links(source).any(|link| is_unsubscribe_link(&link)));
+
+    for image in images(&html) {
+        if image.alt.is_none() {
+            lints.push(LintKind::MissingAltText {
+                src: image.src.unwrap_or_default().to_owned(),
+            });
+        }
+    }
+
+    if !has_unsubscribe_header && !has_unsubscribe_link {
+        lints.push(LintKind::MissingUnsubscribeLink);
+    }
+
+    lints
+}
+
+fn is_unsubscribe_link(link: &Link) -> bool {
+    link.href.is_some_and(|href| UNSUBSCRIBE.is_match(href)) || UNSUBSCRIBE.is_match(link.content)
+}
+
+/// Sources of the layouts and partials that `source` extends or includes, directly or
+/// through another one. Those that can't be loaded are left to fail the rendering.
+fn shared_sources(source: &str, templates: &TemplateStore) -> Vec<Arc<str>> {
+    let mut sources = Vec::new();
+    let mut seen = HashSet::new();
+    let mut pending: Vec<String> = shared_names(source).collect();
+
+    while let Some(name) = pending.pop() {
+        if !seen.insert(name.clone()) {
+            continue;
+        }
+
+        if let Ok(shared) = templates.load(&name) {
+            pending.extend(shared_names(&shared));
+            sources.push(shared);
+        }
+    }
+
+    sources
+}
+
+fn shared_names(source: &str) -> impl Iterator<Item = String> + '_ {
+    SHARED_TEMPLATE
+        .captures_iter(source)
+        .filter_map(|captures| {
+            captures
+                .get(1)
+                .or_else(|| captures.get(2))
+                .map(|name| name.as_str().to_owned())
+        })
+}
+
+fn check_href(href: &str) -> Option<LintKind> {
+    let trimmed = href.trim();
+
+    if is_templated(trimmed) || (trimmed.starts_with('#') && trimmed.len() > 1) {
+        return None;
+    }
+
+    let broken = || {
+        Some(LintKind::BrokenLink {
+            href: href.to_owned(),
+        })
+    };
+
+    if trimmed.is_empty() || trimmed == "#" {
+        return broken();
+    }
+
+    if is_local_url(trimmed) || trimmed.starts_with("//") {
+        return Some(LintKind::RelativeLink {
+            href: href.to_owned(),
+        });
+    }
+
+    // Unwrap is safe because a URL that isn't local has a scheme
+    let (scheme, rest) = trimmed.split_once(':').unwrap();
+    match scheme.to_ascii_lowercase().as_str() {
+        "http" | "https" if !HTTP_URL.is_match(trimmed) => broken(),
+        "javascript" => broken(),
+        _ if rest.is_empty() => broken(),
+        _ => None,
+    }
+}
+
+/// Whether `value` is built by a template expression or statement.
+fn is_templated(value: &str) -> bool {
+    value.contains("{{") || value.contains("{%")
+}
+
+/// `{{ ... }}` placeholders in `text`, which isn't a template.
+fn placeholders(text: &str) -> impl Iterator<Item = LintKind> + '_ {
+    PLACEHOLDER
+        .captures_iter(text)
+        .map(|captures| LintKind::UnresolvedVariable {
+            name: captures[1].to_owned(),
+        })
+}
+
+/// Variables of the template `source` that the render context doesn't define.
+fn undeclared_variables(source: &str) -> Vec<LintKind> {
+    let env = Environment::new();
+    let template = match env.template_from_str(source) {
+        Ok(template) => template,
+        Err(err) => {
+            return vec![LintKind::InvalidTemplate {
+                error: err.to_string(),
+            }]
+        }
+    };
+
+    let mut names: Vec<String> = template
+        .undeclared_variables(true)
+        .into_iter()
+        .filter(|name| !is_context_variable(name))
+        .collect();
+    names.sort();
+
+    names
+        .into_iter()
+        .map(|name| LintKind::UnresolvedVariable { name })
+        .collect()
+}
+
+fn is_context_variable(name: &str) -> bool {
+    let mut path = name.split('.');
+    // Unwrap is safe because `split` always yields at least one item
+    let root = path.next().unwrap();
+
+    if TEMPLATE_GLOBALS.contains(&root) {
+        return true;
+    }
+
+    match CONTEXT_VARIABLES.get(root) {
+        None => false,
+        // A value without fields, such as the date
+        Some(fields) if fields.is_empty() => true,
+        Some(fields) => path
+            .next()
+            .is_none_or(|field| fields.iter().any(|known| known == field)),
+    }
+}
+
+/// Keys of `value` if it is a map, such as a serialized struct.
+fn keys(value: &Value) -> Vec<String> {
+    if value.kind() != ValueKind::Map {
+        return Vec::new();
+    }
+
+    value
+        .try_iter()
+        .into_iter()
+        .flatten()
+        .filter_map(|key| key.as_str().map(str::to_owned))
+        .collect()
+}
@@ -0,0 +1,134 @@
+use color_eyre::eyre::Result;
+use cuid2::create_id;
+use pulldown_cmark::{html, Options, Parser};
+use rusqlite::Connection;
+use serde_derive::{Deserialize, Serialize};
+use serde_rusqlite::to_params_named;
+
+use crate::db::DB;
+
+/// Stylesheet of Markdown emails that don't have their own.
+pub const DEFAULT_STYLESHEET: &str = r#"
+body { font-family: -apple-system, "Segoe UI", Helvetica, Arial, sans-serif; font-size: 16px; line-height: 1.5; color: #24292f; max-width: 640px; margin: 0 auto; padding: 16px; }
+h1, h2, h3 { line-height: 1.25; margin: 24px 0 16px; }
+a { color: #0969da; }
+blockquote { margin: 0; padding: 0 1em; color: #57606a; border-left: 4px solid #d0d7de; }
+code { font-family: ui-monospace, Menlo, Consolas, monospace; font-size: 85%; background: #f6f8fa; padding: 0.2em 0.4em; border-radius: 6px; }
+pre { background: #f6f8fa; padding: 16px; overflow: auto; border-radius: 6px; }
+pre code { padding: 0; background: none; }
+table { border-collapse: collapse; }
+th, td { border: 1px solid #d0d7de; padding: 6px 13px; }
+img { max-width: 100%; }
+"#;
+
+/// An email written in Markdown. The HTML body is compiled from the Markdown source, which
+/// is also sent as the plain-text alternative.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct MarkdownEmail {
+    #[serde(rename(deserialize = "ID"))]
+    id: String,
+    subject: String,
+    source: String,
+    /// CSS of the HTML body. [`DEFAULT_STYLESHEET`] is used when missing.
+    stylesheet: Option<String>,
+    #[serde(skip)]
+    html: String,
+}
+
+impl MarkdownEmail {
+    pub(crate) const CREATE_TABLES: &'static str = r#"
+        CREATE TABLE IF NOT EXISTS MarkdownEmail (
+            ID          TEXT PRIMARY KEY,
+            subject     TEXT,
+            source      TEXT,
+            stylesheet  TEXT
+        ) STRICT;
+        "#;
+
+    pub(super) fn new(subject: String, source: String, stylesheet: Option<String>) -> Self {
+        Self::from_sql(create_id(), subject, source, stylesheet)
+    }
+
+    pub(super) fn from_sql(
+        id: String,
+        subject: String,
+        source: String,
+        stylesheet: Option<String>,
+    ) -> Self {
+        let html = to_html(&source, stylesheet.as_deref().unwrap_or(DEFAULT_STYLESHEET));
+
+        Self {
+            id,
+            subject,
+            source,
+            stylesheet,
+            html,
+        }
+    }
+
+    pub async fn create(
+        subject: String,
+        source: String,
+        stylesheet: Option<String>,
+        db: &DB,
+    ) -> Result<Self> {
+        let this = Self::new(subject, source, stylesheet);
+
+        db.connection(|conn| this.write(conn)).await?;
+
+        Ok(this)
+    }
+
+    pub(super) fn write(&self, conn: &Connection) -> Result<()> {
+        let mut stmt = conn.prepare_cached(
+            "INSERT INTO MarkdownEmail (ID, subject, source, stylesheet) VALUES (:id, :subject, :source, :stylesheet)",
+        )?;
+
+        stmt.execute(to_params_named(self)?.to_slice().as_slice())?;
+
+        Ok(())
+    }
+
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    /// Markdown source of the body, also sent as its plain-text alternative.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn stylesheet(&self) -> Option<&str> {
+        self.stylesheet.as_deref()
+    }
+
+    /// HTML body compiled from the source.
+    pub fn html(&self) -> &str {
+        &self.html
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+/// Compile `source` to a sanitized HTML document styled with `stylesheet`.
+fn to_html(source: &str, stylesheet: &str) -> String {
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH;
+
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, Parser::new_ext(source, options));
+
+    // Raw HTML in the source could run scripts or break the layout of the email
+    let body = ammonia::Builder::default()
+        .add_url_schemes(["cid"])
+        .clean(&unsafe_html)
+        .to_string();
+
+    // A `</style>` in the stylesheet would end the element early
+    let stylesheet = stylesheet.replace("</", "<\\/");
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<style>{stylesheet}</style>\n</head>\n<body>\n{body}</body>\n</html>\n"
+    )
+}
@@ -0,0 +1,108 @@
+use chrono::Local;
+use color_eyre::eyre::{Context, Result};
+use lettre::message::{MultiPart, SinglePart};
+use lettre::Message;
+
+use crate::client::Client;
+use crate::sender::Sender;
+
+use super::html::append_to_body;
+use super::{html_to_text, Email, RenderContext, RenderedEmail, TemplateStore};
+
+impl Email {
+    /// The message `client` would receive if the email was sent now, without sending it.
+    ///
+    /// `message.formatted()` is the raw MIME message, which can be saved as an `.eml` file.
+    pub fn render_for(&self, client: &Client, templates: &TemplateStore) -> Result<Message> {
+        let renderer = self.renderer(templates)?;
+        let rendered = renderer.render(&RenderContext::new(client, Local::now()))?;
+
+        self.message(&self.signed(rendered)?, client)
+    }
+
+    /// `rendered` with the signature of the sender appended, if it has one.
+    pub(crate) fn signed(&self, rendered: RenderedEmail) -> Result<RenderedEmail> {
+        let Some(signature) = self.sender().and_then(Sender::signature) else {
+            return Ok(rendered);
+        };
+
+        Ok(RenderedEmail {
+            body: append_to_body(&rendered.body, signature),
+            // `-- ` is the usual delimiter of plain-text signatures
+            text_body: format!(
+                "{}\n\n-- \n{}",
+                rendered.text_body.trim_end(),
+                html_to_text(signature)?
+            ),
+            subject: rendered.subject,
+        })
+    }
+
+    /// Build the MIME message of the email, rendered for `client` and [signed](Self::signed).
+    pub(crate) fn message(&self, rendered: &RenderedEmail, client: &Client) -> Result<Message> {
+        let text = SinglePart::plain(rendered.text_body.clone());
+        let html = SinglePart::html(rendered.body.clone());
+
+        // mixed(alternative(text, related(html, inline images...)), attachments...)
+        let mut body = if self.inline_images().next().is_none() {
+            MultiPart::alternative().singlepart(text).singlepart(html)
+        } else {
+            let mut related = MultiPart::related().singlepart(html);
+            for image in self.inline_images() {
+                related = related.singlepart(image.to_part()?);
+            }
+
+            MultiPart::alternative().singlepart(text).multipart(related)
+        };
+
+        if self.attachments().next().is_some() {
+            let mut mixed = MultiPart::mixed().multipart(body);
+            for attachment in self.attachments() {
+                mixed = mixed.singlepart(attachment.to_part()?);
+            }
+            body = mixed;
+        }
+
+        let headers = self.headers();
+
+        let from = match self.sender() {
+            Some(sender) => sender
+                .mailbox()
+                .with_context(|| format!("Parsing adresse of sender {}", sender.id()))?,
+            None => self
+                .sender_adresse()
+                .parse()
+                .with_context(|| format!("Parsing sender adresse of email {}", self.id()))?,
+        };
+
+        let mut builder = Message::builder()
+            .from(from)
+            .to(client
+                .adresse()
+                .parse()
+                .with_context(|| format!("Parsing adresse of client {}", client.id()))?)
+            .subject(&rendered.subject);
+
+        // The reply-to of the email takes precedence over the one of its sender
+        if let Some(reply_to) = headers
+            .reply_to()
+            .or_else(|| self.sender().and_then(Sender::reply_to))
+        {
+            builder = builder.reply_to(reply_to.clone());
+        }
+        for cc in headers.cc() {
+            builder = builder.cc(cc.clone());
+        }
+        for bcc in headers.bcc() {
+            builder = builder.bcc(bcc.clone());
+        }
+
+        let mut message = builder.multipart(body)?;
+
+        for header in headers.custom() {
+            message.headers_mut().insert_raw(header.to_header_value()?);
+        }
+
+        Ok(message)
+    }
+}
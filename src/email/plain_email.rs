@@ -1,67 +1,78 @@
 use color_eyre::eyre::Result;
 use cuid2::create_id;
+use rusqlite::Connection;
 use serde_derive::{Deserialize, Serialize};
 use serde_rusqlite::to_params_named;
 
 use crate::db::DB;
 
+use super::text::html_to_text;
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct PlainEmail {
     #[serde(rename(deserialize = "ID"))]
     id: String,
     subject: String,
     body: String,
+    /// Plain-text alternative of `body`
+    text_body: String,
 }
 
 impl PlainEmail {
     pub(crate) const CREATE_TABLES: &'static str = r#"
         CREATE TABLE IF NOT EXISTS PlainEmail (
-            ID       TEXT PRIMARY KEY,
-            subject  TEXT,
-            body     TEXT
+            ID         TEXT PRIMARY KEY,
+            subject    TEXT,
+            body       TEXT,
+            text_body  TEXT
         ) STRICT;
         "#;
 
-    pub(super) fn new(subject: String, body: String) -> Self {
-        Self {
+    /// The plain-text alternative is derived from `body` when `text_body` is `None`.
+    pub(super) fn new(subject: String, body: String, text_body: Option<String>) -> Result<Self> {
+        let text_body = match text_body {
+            Some(text_body) => text_body,
+            None => html_to_text(&body)?,
+        };
+
+        Ok(Self {
             id: create_id(),
             subject,
             body,
-        }
+            text_body,
+        })
     }
 
-    pub(super) fn from_sql(id: String, subject: String, body: String) -> Self {
-        Self { id, subject, body }
+    pub(super) fn from_sql(id: String, subject: String, body: String, text_body: String) -> Self {
+        Self {
+            id,
+            subject,
+            body,
+            text_body,
+        }
     }
 
-    pub async fn create(subject: String, body: String, db: &DB) -> Result<Self> {
-        let this = Self::new(subject, body);
-
-        db.connection(|conn| {
-            let mut stmt = conn.prepare_cached(
-                "INSERT INTO PlainEmail (ID, subject, body) VALUES (:id, :subject, :body)",
-            )?;
+    pub async fn create(
+        subject: String,
+        body: String,
+        text_body: Option<String>,
+        db: &DB,
+    ) -> Result<Self> {
+        let this = Self::new(subject, body, text_body)?;
 
-            stmt.execute(to_params_named(&this)?.to_slice().as_slice())?;
-
-            Ok(())
-        })
-        .await?;
+        db.connection(|conn| this.write(conn)).await?;
 
         Ok(this)
     }
 
-    pub(super) async fn write(&self, db: &DB) -> Result<()> {
-        db.connection(|conn| {
-            let mut stmt = conn.prepare_cached(
-                "INSERT INTO PlainEmail (ID, subject, body) VALUES (:id, :subject, :body)",
-            )?;
+    pub(super) fn write(&self, conn: &Connection) -> Result<()> {
+        let mut stmt = conn.prepare_cached(
+            "INSERT INTO PlainEmail (ID, subject, body, text_body) VALUES (:id, :subject, :body, :text_body)",
+        )?;
 
-            stmt.execute(to_params_named(self)?.to_slice().as_slice())?;
+        stmt.execute(to_params_named(self)?.to_slice().as_slice())?;
 
-            Ok(())
-        })
-        .await
+        Ok(())
     }
 
     pub fn subject(&self) -> &str {
@@ -72,6 +83,10 @@ impl PlainEmail {
         &self.body
     }
 
+    pub fn text_body(&self) -> &str {
+        &self.text_body
+    }
+
     pub fn id(&self) -> &str {
         &self.id
     }
@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Arc;
+
+use chrono::{DateTime, Local};
+use color_eyre::eyre::Result;
+use minijinja::{Environment, UndefinedBehavior};
+use serde_derive::Serialize;
+
+use crate::client::Client;
+
+use super::{revision::TemplateSources, text::html_to_text, Email, EmailModel, TemplateStore};
+
+const SUBJECT_TEMPLATE: &str = "subject.txt";
+// The `.html` extension turns on HTML auto-escaping of the rendered values
+const BODY_TEMPLATE: &str = "body.html";
+const TEXT_BODY_TEMPLATE: &str = "body.txt";
+
+/// Values available to a template when it is rendered for a recipient.
+#[derive(Serialize, Debug)]
+pub struct RenderContext<'a> {
+    pub client: &'a Client,
+    pub group: Option<&'a str>,
+    pub generation: Option<u64>,
+    /// Send date, in RFC 3339 format. Use the `format_date` filter to display it.
+    pub date: String,
+}
+
+impl<'a> RenderContext<'a> {
+    pub fn new(client: &'a Client, date: DateTime<Local>) -> Self {
+        Self {
+            client,
+            group: None,
+            generation: None,
+            date: date.to_rfc3339(),
+        }
+    }
+
+    pub fn group(mut self, group: Option<&'a str>) -> Self {
+        self.group = group;
+        self
+    }
+
+    pub fn generation(mut self, generation: Option<u64>) -> Self {
+        self.generation = generation;
+        self
+    }
+}
+
+/// Subject and body of an email, ready to be sent to one recipient.
+#[derive(Debug)]
+pub struct RenderedEmail {
+    pub subject: String,
+    /// HTML body
+    pub body: String,
+    /// Plain-text alternative of the body
+    pub text_body: String,
+}
+
+/// An email prepared for rendering. Templates are parsed once, when the renderer is
+/// created, and then rendered for each recipient. Layouts and partials are read again for
+/// each renderer, so a change to a shared file applies to every later send.
+///
+/// Each recipient gets the variant of the email matching their locale.
+pub struct Renderer<'e> {
+    email: &'e Email,
+    default: ModelRenderer<'e>,
+    variants: Vec<ModelRenderer<'e>>,
+}
+
+/// The content of an email, or of one of its variants, prepared for rendering.
+struct ModelRenderer<'e> {
+    email: &'e EmailModel,
+    env: Option<Environment<'e>>,
+    /// Content of the template file, for templates read from a file
+    source: Option<Arc<str>>,
+}
+
+impl<'e> Renderer<'e> {
+    pub(super) fn new(email: &'e Email, templates: &TemplateStore) -> Result<Self> {
+        Ok(Self {
+            email,
+            default: ModelRenderer::new(&email.email, templates)?,
+            variants: Result::from_iter(
+                email
+                    .variants
+                    .iter()
+                    .map(|variant| ModelRenderer::new(&variant.email, templates)),
+            )?,
+        })
+    }
+
+    pub fn render(&self, context: &RenderContext) -> Result<RenderedEmail> {
+        match self.email.variant_index(context.client.locale()) {
+            Some(variant) => self.variants[variant].render(context),
+            None => self.default.render(context),
+        }
+    }
+
+    /// Content of the template files the renderer loaded, to record with the sending.
+    pub(crate) fn sources(&self) -> TemplateSources {
+        TemplateSources::new(
+            self.default.source.clone(),
+            BTreeMap::from_iter(self.email.variants.iter().zip(&self.variants).filter_map(
+                |(variant, renderer)| Some((variant.locale().to_owned(), renderer.source.clone()?)),
+            )),
+        )
+    }
+}
+
+impl<'e> ModelRenderer<'e> {
+    fn new(email: &'e EmailModel, templates: &TemplateStore) -> Result<Self> {
+        let mut source = None;
+
+        let env = match email {
+            EmailModel::Plain(_) | EmailModel::Markdown(_) => None,
+            EmailModel::Template(template_email) => {
+                let mut env = Self::environment();
+                templates.register(&mut env);
+                env.add_template(SUBJECT_TEMPLATE, template_email.subject())?;
+
+                if template_email.source_path().is_empty() {
+                    env.add_template(BODY_TEMPLATE, template_email.body())?;
+                } else {
+                    let body = templates.load(template_email.source_path())?;
+                    env.add_template_owned(BODY_TEMPLATE, body.to_string())?;
+                    source = Some(body);
+                }
+
+                if let Some(text_body) = template_email.text_body() {
+                    env.add_template(TEXT_BODY_TEMPLATE, text_body)?;
+                }
+
+                Some(env)
+            }
+        };
+
+        Ok(Self { email, env, source })
+    }
+
+    fn environment() -> Environment<'e> {
+        let mut env = Environment::new();
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+        env.add_filter("format_date", format_date);
+        env
+    }
+
+    fn render(&self, context: &RenderContext) -> Result<RenderedEmail> {
+        match &self.env {
+            None => Ok(RenderedEmail {
+                subject: self.email.subject().to_owned(),
+                body: self.email.body().to_owned(),
+                // Plain and Markdown emails always have their plain-text alternative
+                text_body: self.email.text_body().unwrap_or_default().to_owned(),
+            }),
+            Some(env) => {
+                let body = env.get_template(BODY_TEMPLATE)?.render(context)?;
+
+                let text_body = if self.email.text_body().is_some() {
+                    env.get_template(TEXT_BODY_TEMPLATE)?.render(context)?
+                } else {
+                    html_to_text(&body)?
+                };
+
+                Ok(RenderedEmail {
+                    subject: env.get_template(SUBJECT_TEMPLATE)?.render(context)?,
+                    body,
+                    text_body,
+                })
+            }
+        }
+    }
+}
+
+/// `{{ date | format_date("%d/%m/%Y") }}`: format an RFC 3339 date with a strftime pattern.
+fn format_date(date: &str, format: &str) -> Result<String, minijinja::Error> {
+    let date = DateTime::parse_from_rfc3339(date).map_err(|err| {
+        minijinja::Error::new(minijinja::ErrorKind::InvalidOperation, err.to_string())
+    })?;
+
+    // Formatting fails, instead of panicking, on an invalid pattern when done through `write!`
+    let mut formatted = String::new();
+    write!(formatted, "{}", date.format(format)).map_err(|_| {
+        minijinja::Error::new(
+            minijinja::ErrorKind::InvalidOperation,
+            format!("invalid date format {format:?}"),
+        )
+    })?;
+
+    Ok(formatted)
+}
@@ -0,0 +1,398 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use color_eyre::eyre::Result;
+use rusqlite::Connection;
+use serde_derive::Deserialize;
+use serde_rusqlite::{columns_from_statement, from_row_with_columns};
+
+use super::{EmailModel, EmailVariant};
+
+/// A version of the content of an email. A revision is recorded each time the content or
+/// one of its variants is written, or a template file changed since it was last sent, so the
+/// history holds every version that was ever sent.
+#[derive(Deserialize, Debug)]
+pub struct EmailRevision {
+    revision: u32,
+    email_discriminant: u8,
+    subject: String,
+    body: String,
+    text_body: Option<String>,
+    source_path: Option<String>,
+    timestamp: u64,
+    #[serde(skip)]
+    variants: Vec<EmailRevisionVariant>,
+}
+
+/// Content of the template files of an email and of its variants, as loaded to send it.
+#[derive(Debug, Default)]
+pub(crate) struct TemplateSources {
+    default: Option<Arc<str>>,
+    /// By locale
+    variants: BTreeMap<String, Arc<str>>,
+}
+
+impl TemplateSources {
+    pub(super) fn new(default: Option<Arc<str>>, variants: BTreeMap<String, Arc<str>>) -> Self {
+        Self { default, variants }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.default.is_none() && self.variants.is_empty()
+    }
+
+    /// The file of the default content, or of the variant in `locale`.
+    fn get(&self, locale: Option<&str>) -> Option<&str> {
+        match locale {
+            None => self.default.as_deref(),
+            Some(locale) => self.variants.get(locale).map(|source| &**source),
+        }
+    }
+}
+
+/// The content of a revision in another locale than the default one.
+#[derive(Deserialize, Debug)]
+pub struct EmailRevisionVariant {
+    #[serde(skip)]
+    revision: u32,
+    locale: String,
+    email_discriminant: u8,
+    subject: String,
+    body: String,
+    text_body: Option<String>,
+    source_path: Option<String>,
+}
+
+impl EmailRevision {
+    pub(crate) const CREATE_TABLES: &'static str = r#"
+        CREATE TABLE IF NOT EXISTS EmailRevision (
+            email_ID            TEXT NOT NULL,
+            revision            INTEGER NOT NULL,
+            email_discriminant  INTEGER NOT NULL,
+            subject             TEXT NOT NULL,
+            body                TEXT NOT NULL,
+            text_body           TEXT,
+            source_path         TEXT,
+            timestamp           INTEGER NOT NULL,
+            PRIMARY KEY (email_ID, revision),
+            FOREIGN KEY (email_ID)  REFERENCES Email(ID)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE
+        ) STRICT;
+
+        CREATE TABLE IF NOT EXISTS EmailRevisionVariant (
+            email_ID            TEXT NOT NULL,
+            revision            INTEGER NOT NULL,
+            locale              TEXT NOT NULL,
+            email_discriminant  INTEGER NOT NULL,
+            subject             TEXT NOT NULL,
+            body                TEXT NOT NULL,
+            text_body           TEXT,
+            source_path         TEXT,
+            PRIMARY KEY (email_ID, revision, locale),
+            FOREIGN KEY (email_ID, revision)  REFERENCES EmailRevision(email_ID, revision)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE
+        ) STRICT;
+    "#;
+
+    pub fn revision(&self) -> u32 {
+        self.revision
+    }
+
+    pub fn is_template(&self) -> bool {
+        self.email_discriminant == EmailModel::TEMPLATE_DISCRIMINANT
+    }
+
+    /// For Markdown emails, the body is the Markdown source.
+    pub fn is_markdown(&self) -> bool {
+        self.email_discriminant == EmailModel::MARKDOWN_DISCRIMINANT
+    }
+
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    /// For templates read from a file, the content of the file sent with the revision, or
+    /// empty if it wasn't sent yet.
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
+    pub fn text_body(&self) -> Option<&str> {
+        self.text_body.as_deref()
+    }
+
+    pub fn source_path(&self) -> Option<&str> {
+        self.source_path.as_deref()
+    }
+
+    /// When the revision was written, in seconds since the Unix epoch.
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    /// Content of the revision in other locales than the default one.
+    pub fn variants(&self) -> &[EmailRevisionVariant] {
+        &self.variants
+    }
+
+    /// Record `email` and `variants` as the revision `revision` of the email `email_id`.
+    ///
+    /// For a template read from a file, the content of the file in `sources` is recorded as
+    /// the body. Without it, the body stays empty until the revision is sent, see
+    /// [`EmailRevision::for_sending`].
+    pub(super) fn write<'a>(
+        email_id: &str,
+        revision: u32,
+        email: &EmailModel,
+        variants: impl IntoIterator<Item = &'a EmailVariant>,
+        sources: &TemplateSources,
+        conn: &Connection,
+    ) -> Result<()> {
+        let mut stmt = conn.prepare_cached(
+            r"
+            INSERT INTO EmailRevision (email_ID, revision, email_discriminant, subject, body, text_body, source_path, timestamp)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+        ",
+        )?;
+
+        let (body, text_body, source_path) = snapshot(email, sources.get(None));
+
+        stmt.execute((
+            email_id,
+            revision,
+            email.discriminant(),
+            email.subject(),
+            body,
+            text_body,
+            source_path,
+            // Unwrap in safe because `UNIX_EPOCH` is 0 and thus less than `SystemTime::now()`
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+        ))?;
+
+        let mut stmt = conn.prepare_cached(
+            r"
+            INSERT INTO EmailRevisionVariant (email_ID, revision, locale, email_discriminant, subject, body, text_body, source_path)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+        ",
+        )?;
+
+        for variant in variants {
+            let (body, text_body, source_path) =
+                snapshot(&variant.email, sources.get(Some(variant.locale())));
+
+            stmt.execute((
+                email_id,
+                revision,
+                variant.locale(),
+                variant.email.discriminant(),
+                variant.email.subject(),
+                body,
+                text_body,
+                source_path,
+            ))?;
+        }
+
+        Ok(())
+    }
+
+    /// The revision a sending of the email `email_id` is recorded with, given the template
+    /// files loaded to send it.
+    ///
+    /// A file whose content isn't recorded yet is recorded in the current revision. A file
+    /// that changed since it was recorded makes a new revision, so that each revision
+    /// identifies the content that was sent with it.
+    pub(super) fn for_sending(
+        email_id: &str,
+        email: &EmailModel,
+        variants: &[EmailVariant],
+        sources: &TemplateSources,
+        conn: &Connection,
+    ) -> Result<u32> {
+        let tx = conn.unchecked_transaction()?;
+
+        let revision: u32 = tx.query_row(
+            "SELECT revision FROM Email WHERE ID = ?",
+            [email_id],
+            |row| row.get(0),
+        )?;
+
+        if sources.is_empty() {
+            return Ok(revision);
+        }
+
+        let mut recorded: HashMap<Option<String>, String> = HashMap::new();
+        recorded.insert(
+            None,
+            tx.query_row(
+                "SELECT body FROM EmailRevision WHERE email_ID = ? AND revision = ?",
+                (email_id, revision),
+                |row| row.get(0),
+            )?,
+        );
+        {
+            let mut stmt = tx.prepare_cached(
+                "SELECT locale, body FROM EmailRevisionVariant WHERE email_ID = ? AND revision = ?",
+            )?;
+            let rows = stmt.query_map((email_id, revision), |row| {
+                Ok((Some(row.get::<_, String>(0)?), row.get::<_, String>(1)?))
+            })?;
+            for row in rows {
+                let (locale, body) = row?;
+                recorded.insert(locale, body);
+            }
+        }
+
+        let files: Vec<_> = recorded
+            .iter()
+            .filter_map(|(locale, body)| Some((locale, sources.get(locale.as_deref())?, body)))
+            .collect();
+
+        let changed = files
+            .iter()
+            .any(|(_, source, body)| !body.is_empty() && source != body);
+
+        let revision = if changed {
+            let revision = revision.max(tx.query_row(
+                "SELECT MAX(revision) FROM EmailRevision WHERE email_ID = ?",
+                [email_id],
+                |row| row.get(0),
+            )?) + 1;
+
+            Self::write(email_id, revision, email, variants, sources, &tx)?;
+            tx.execute(
+                "UPDATE Email SET revision = ? WHERE ID = ?",
+                (revision, email_id),
+            )?;
+
+            revision
+        } else {
+            for (locale, source, body) in files {
+                if !body.is_empty() {
+                    continue;
+                }
+
+                match locale {
+                    None => tx.execute(
+                        "UPDATE EmailRevision SET body = ? WHERE email_ID = ? AND revision = ?",
+                        (source, email_id, revision),
+                    )?,
+                    Some(locale) => tx.execute(
+                        "UPDATE EmailRevisionVariant SET body = ? WHERE email_ID = ? AND revision = ? AND locale = ?",
+                        (source, email_id, revision, locale),
+                    )?,
+                };
+            }
+
+            revision
+        };
+
+        tx.commit()?;
+
+        Ok(revision)
+    }
+
+    pub(super) fn get_for_email(email_id: &str, conn: &Connection) -> Result<Vec<Self>> {
+        let mut stmt = conn.prepare_cached(
+            r"
+            SELECT revision, email_discriminant, subject, body, text_body, source_path, timestamp
+                FROM EmailRevision
+                WHERE email_ID = ?
+                ORDER BY revision
+        ",
+        )?;
+
+        let columns = columns_from_statement(&stmt);
+
+        let mut revisions: Vec<Self> =
+            Result::from_iter(stmt.query_and_then([email_id], |row| {
+                from_row_with_columns::<Self>(row, &columns)
+            })?)?;
+
+        let mut stmt = conn.prepare_cached(
+            r"
+            SELECT revision, locale, email_discriminant, subject, body, text_body, source_path
+                FROM EmailRevisionVariant
+                WHERE email_ID = ?
+                ORDER BY revision, rowid
+        ",
+        )?;
+
+        let columns = columns_from_statement(&stmt);
+
+        let variants = stmt.query_and_then([email_id], |row| {
+            let mut variant = from_row_with_columns::<EmailRevisionVariant>(row, &columns)?;
+            variant.revision = row.get(0)?;
+            Ok::<_, color_eyre::eyre::Error>(variant)
+        })?;
+
+        for variant in variants {
+            let variant = variant?;
+            if let Some(revision) = revisions
+                .iter_mut()
+                .find(|revision| revision.revision == variant.revision)
+            {
+                revision.variants.push(variant);
+            }
+        }
+
+        Ok(revisions)
+    }
+}
+
+impl EmailRevisionVariant {
+    pub fn locale(&self) -> &str {
+        &self.locale
+    }
+
+    pub fn is_template(&self) -> bool {
+        self.email_discriminant == EmailModel::TEMPLATE_DISCRIMINANT
+    }
+
+    /// For Markdown variants, the body is the Markdown source.
+    pub fn is_markdown(&self) -> bool {
+        self.email_discriminant == EmailModel::MARKDOWN_DISCRIMINANT
+    }
+
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    /// For templates read from a file, the content of the file sent with the revision, or
+    /// empty if it wasn't sent yet.
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
+    pub fn text_body(&self) -> Option<&str> {
+        self.text_body.as_deref()
+    }
+
+    pub fn source_path(&self) -> Option<&str> {
+        self.source_path.as_deref()
+    }
+}
+
+/// The body, text body and source path recorded for `email`, with `source` the content of
+/// its template file if it was loaded.
+fn snapshot(email: &EmailModel, source: Option<&str>) -> (String, Option<String>, Option<String>) {
+    // The Markdown source is recorded rather than the HTML compiled from it
+    match email {
+        EmailModel::Plain(_) => (
+            email.body().to_owned(),
+            email.text_body().map(str::to_owned),
+            None,
+        ),
+        EmailModel::Template(template_email) => (
+            source.unwrap_or(email.body()).to_owned(),
+            email.text_body().map(str::to_owned),
+            Some(template_email.source_path().to_owned()),
+        ),
+        EmailModel::Markdown(markdown_email) => (markdown_email.source().to_owned(), None, None),
+    }
+}
@@ -1,11 +1,11 @@
-use std::fmt::Display;
+use std::collections::HashMap;
 
-use color_eyre::eyre::Result;
-use tracing::warn;
+use color_eyre::eyre::{bail, Result};
+use rusqlite::{params_from_iter, Connection, OptionalExtension};
 
-const TAG_SEPARATOR: char = '$';
+use crate::db::DB;
 
-/// A list of tag (`String`). '$' is forbidden in a tag.
+/// The tags of an email, without duplicates.
 #[derive(Debug, Default)]
 pub(super) struct Tags {
     tags: Vec<String>,
@@ -13,23 +13,26 @@ pub(super) struct Tags {
 
 impl Tags {
     pub(super) fn new(tags: Vec<String>) -> Result<Self> {
-        // TODO: Gérer les erreurs correctement
+        let mut this = Self::default();
 
-        if let Some(tag) = tags.iter().find(|tag| tag.contains(TAG_SEPARATOR)) {
-            warn!("{TAG_SEPARATOR} is forbidden in tag. Found it in {tag}");
-            panic!()
+        for tag in tags {
+            this.push(tag)?;
         }
 
-        Ok(Self { tags })
+        Ok(this)
     }
 
-    pub(super) fn push(&mut self, tag: String) -> Result<(), String> {
-        if tag.contains(TAG_SEPARATOR) {
-            Err(tag)
-        } else {
+    /// Add `tag`, unless the email already has it.
+    pub(super) fn push(&mut self, tag: String) -> Result<()> {
+        if tag.is_empty() {
+            bail!("A tag can't be empty");
+        }
+
+        if !self.tags.contains(&tag) {
             self.tags.push(tag);
-            Ok(())
         }
+
+        Ok(())
     }
 
     pub(super) fn remove(&mut self, tag: &str) -> bool {
@@ -41,14 +44,101 @@ impl Tags {
         }
     }
 
+    pub(super) fn as_slice(&self) -> &[String] {
+        &self.tags
+    }
+
     pub(super) fn into_vec(self) -> Vec<String> {
         self.tags
     }
-}
 
-impl Display for Tags {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(&self.tags.join(&TAG_SEPARATOR.to_string()))
+    /// Tag the email `email_id`, creating the tags that don't exist yet.
+    pub(super) fn write(&self, email_id: &str, conn: &Connection) -> Result<()> {
+        let mut insert_tag = conn.prepare_cached("INSERT OR IGNORE INTO Tag (name) VALUES (?)")?;
+        let mut insert_email_tag = conn.prepare_cached(
+            r"
+            INSERT OR IGNORE INTO MM_EmailTag (email_ID, tag_ID)
+                SELECT ?, ID FROM Tag WHERE name = ?
+        ",
+        )?;
+
+        for tag in &self.tags {
+            insert_tag.execute([tag])?;
+            insert_email_tag.execute((email_id, tag))?;
+        }
+
+        Ok(())
+    }
+
+    /// Tags of each of the emails `email_ids`, by email ID.
+    pub(super) fn get_for_emails(
+        email_ids: &[&str],
+        conn: &Connection,
+    ) -> Result<HashMap<String, Self>> {
+        let placeholders = vec!["?"; email_ids.len()].join(", ");
+        let mut stmt = conn.prepare(&format!(
+            r"
+            SELECT et.email_ID, t.name
+                FROM MM_EmailTag et
+                JOIN Tag t ON et.tag_ID = t.ID
+                WHERE et.email_ID IN ({placeholders})
+                ORDER BY et.rowid
+        "
+        ))?;
+
+        let rows = stmt.query_map(params_from_iter(email_ids), |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+
+        let mut tags: HashMap<String, Self> = HashMap::new();
+        for row in rows {
+            let (email_id, tag) = row?;
+            tags.entry(email_id).or_default().tags.push(tag);
+        }
+
+        Ok(tags)
+    }
+
+    /// IDs of the emails matching `tags`, in creation order.
+    pub(super) fn query_email_ids(
+        tags: &[&str],
+        matching: TagMatch,
+        conn: &Connection,
+    ) -> Result<Vec<String>> {
+        if tags.is_empty() {
+            // Every email has all of no tag, and none has any of them
+            return match matching {
+                TagMatch::Any => Ok(Vec::new()),
+                TagMatch::All => {
+                    let mut stmt = conn.prepare_cached("SELECT ID FROM Email ORDER BY rowid")?;
+                    let ids = Result::from_iter(stmt.query_map([], |row| row.get(0))?)?;
+                    Ok(ids)
+                }
+            };
+        }
+
+        let placeholders = vec!["?"; tags.len()].join(", ");
+        let having = match matching {
+            TagMatch::Any => String::new(),
+            TagMatch::All => format!("HAVING COUNT(DISTINCT t.ID) = {}", tags.len()),
+        };
+
+        let mut stmt = conn.prepare(&format!(
+            r"
+            SELECT em.ID
+                FROM Email em
+                JOIN MM_EmailTag et ON et.email_ID = em.ID
+                JOIN Tag t ON et.tag_ID = t.ID
+                WHERE t.name IN ({placeholders})
+                GROUP BY em.ID
+                {having}
+                ORDER BY em.rowid
+        "
+        ))?;
+
+        let ids = Result::from_iter(stmt.query_map(params_from_iter(tags), |row| row.get(0))?)?;
+
+        Ok(ids)
     }
 }
 
@@ -60,10 +150,146 @@ impl TryFrom<Vec<String>> for Tags {
     }
 }
 
-impl From<String> for Tags {
-    fn from(value: String) -> Self {
-        let tags = value.split(TAG_SEPARATOR).map(str::to_owned).collect();
+/// How the tags given to [`Email::list_by_tags`](super::Email::list_by_tags) are matched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagMatch {
+    /// Emails with at least one of the tags
+    Any,
+    /// Emails with every one of the tags
+    All,
+}
+
+/// A tag and the number of emails tagged with it.
+#[derive(Debug)]
+pub struct Tag {
+    name: String,
+    emails: usize,
+}
+
+impl Tag {
+    pub(crate) const CREATE_TABLES: &'static str = r#"
+        CREATE TABLE IF NOT EXISTS Tag (
+            ID    INTEGER PRIMARY KEY,
+            name  TEXT NOT NULL UNIQUE
+        ) STRICT;
+
+        CREATE TABLE IF NOT EXISTS MM_EmailTag (
+            email_ID  TEXT NOT NULL,
+            tag_ID    INTEGER NOT NULL,
+            PRIMARY KEY (email_ID, tag_ID),
+            FOREIGN KEY (email_ID)  REFERENCES Email(ID)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE,
+            FOREIGN KEY (tag_ID)  REFERENCES Tag(ID)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE
+        ) STRICT;
+    "#;
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn emails(&self) -> usize {
+        self.emails
+    }
+
+    /// Every tag, by name, with the number of emails tagged with it.
+    pub async fn counts(db: &DB) -> Result<Vec<Self>> {
+        db.connection(|conn| {
+            let mut stmt = conn.prepare_cached(
+                r"
+                SELECT t.name, COUNT(et.email_ID)
+                    FROM Tag t
+                    LEFT JOIN MM_EmailTag et ON et.tag_ID = t.ID
+                    GROUP BY t.ID
+                    ORDER BY t.name
+            ",
+            )?;
+
+            let tags = Result::from_iter(stmt.query_map([], |row| {
+                Ok(Self {
+                    name: row.get(0)?,
+                    emails: row.get(1)?,
+                })
+            })?)?;
+
+            Ok(tags)
+        })
+        .await
+    }
+
+    /// Rename the tag `name` on every email. Fail if `new_name` is already a tag, in which
+    /// case the tags should be merged.
+    pub async fn rename(name: &str, new_name: &str, db: &DB) -> Result<()> {
+        if name == new_name {
+            return Ok(());
+        }
+
+        if new_name.is_empty() {
+            bail!("A tag can't be empty");
+        }
+
+        db.connection(|conn| {
+            if Self::id(new_name, conn)?.is_some() {
+                bail!("Tag {new_name} already exists, merge {name} into it instead");
+            }
+
+            let renamed =
+                conn.execute("UPDATE Tag SET name = ? WHERE name = ?", (new_name, name))?;
+
+            if renamed == 0 {
+                bail!("Tag {name} doesn't exist");
+            }
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// Tag with `into` every email tagged with `from`, then delete `from`. `into` is created
+    /// if it doesn't exist.
+    pub async fn merge(from: &str, into: &str, db: &DB) -> Result<()> {
+        if from == into {
+            return Ok(());
+        }
+
+        if into.is_empty() {
+            bail!("A tag can't be empty");
+        }
+
+        db.connection(|conn| {
+            let Some(from_id) = Self::id(from, conn)? else {
+                bail!("Tag {from} doesn't exist");
+            };
+
+            let tx = conn.unchecked_transaction()?;
+
+            tx.execute("INSERT OR IGNORE INTO Tag (name) VALUES (?)", [into])?;
+            tx.execute(
+                r"
+                INSERT OR IGNORE INTO MM_EmailTag (email_ID, tag_ID)
+                    SELECT et.email_ID, t.ID
+                        FROM MM_EmailTag et, Tag t
+                        WHERE et.tag_ID = ? AND t.name = ?
+            ",
+                (from_id, into),
+            )?;
+            // Deleting the tag deletes its links to the emails too
+            tx.execute("DELETE FROM Tag WHERE ID = ?", [from_id])?;
+
+            tx.commit()?;
+
+            Ok(())
+        })
+        .await
+    }
 
-        Self { tags }
+    fn id(name: &str, conn: &Connection) -> Result<Option<i64>> {
+        Ok(conn
+            .query_row("SELECT ID FROM Tag WHERE name = ?", [name], |row| {
+                row.get(0)
+            })
+            .optional()?)
     }
 }
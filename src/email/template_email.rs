@@ -1,10 +1,15 @@
 use color_eyre::eyre::Result;
 use cuid2::create_id;
+use rusqlite::Connection;
 use serde_derive::{Deserialize, Serialize};
 use serde_rusqlite::to_params_named;
 
 use crate::db::DB;
 
+/// An email whose subject and body are templates rendered for each recipient.
+///
+/// When `source_path` is set, the body is read from that file, relative to the template
+/// root of the [`TemplateStore`](super::TemplateStore), instead of `body`.
 #[derive(Deserialize, Serialize, Debug)]
 pub struct TemplateEmail {
     #[serde(rename(deserialize = "ID"))]
@@ -12,6 +17,9 @@ pub struct TemplateEmail {
     subject: String,
     body: String,
     source_path: String,
+    /// Template of the plain-text alternative. When missing, it is derived from the
+    /// rendered body.
+    text_body: Option<String>,
 }
 
 impl TemplateEmail {
@@ -20,16 +28,39 @@ impl TemplateEmail {
             ID           TEXT PRIMARY KEY,
             subject      TEXT,
             body         TEXT,
-            source_path  TEXT
+            source_path  TEXT,
+            text_body    TEXT
         ) STRICT;
     "#;
 
-    pub(super) fn new(subject: String, body: String, source_path: String) -> Self {
+    pub(super) fn new(
+        subject: String,
+        body: String,
+        source_path: String,
+        text_body: Option<String>,
+    ) -> Self {
         Self {
             id: create_id(),
             subject,
             body,
             source_path,
+            text_body,
+        }
+    }
+
+    pub(super) fn from_sql(
+        id: String,
+        subject: String,
+        body: String,
+        source_path: String,
+        text_body: Option<String>,
+    ) -> Self {
+        Self {
+            id,
+            subject,
+            body,
+            source_path,
+            text_body,
         }
     }
 
@@ -37,33 +68,40 @@ impl TemplateEmail {
         subject: String,
         body: String,
         source_path: String,
+        text_body: Option<String>,
         db: &DB,
     ) -> Result<Self> {
-        let this = Self::new(subject, body, source_path);
+        let this = Self::new(subject, body, source_path, text_body);
 
-        db.connection(|conn| {
-            let mut stmt = conn.prepare_cached(
-                "INSERT INTO TemplateEmail (ID, subject, body, source_path) VALUES (:id, :adresse, :body, :source_path)",
-            )?;
+        db.connection(|conn| this.write(conn)).await?;
 
-            stmt.execute(to_params_named(&this)?.to_slice().as_slice())?;
+        Ok(this)
+    }
 
-            Ok(())
-        }).await?;
+    pub(super) fn write(&self, conn: &Connection) -> Result<()> {
+        let mut stmt = conn.prepare_cached(
+            "INSERT INTO TemplateEmail (ID, subject, body, source_path, text_body) VALUES (:id, :subject, :body, :source_path, :text_body)",
+        )?;
 
-        Ok(this)
+        stmt.execute(to_params_named(self)?.to_slice().as_slice())?;
+
+        Ok(())
     }
 
-    pub(super) async fn write(&self, db: &DB) -> Result<()> {
-        db.connection(|conn| {
-            let mut stmt = conn.prepare_cached(
-                "INSERT INTO TemplateEmail (ID, subject, body, source_path) VALUES (:id, :adresse, :body, :source_path)",
-            )?;
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
 
-            stmt.execute(to_params_named(self)?.to_slice().as_slice())?;
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
+    pub fn source_path(&self) -> &str {
+        &self.source_path
+    }
 
-            Ok(())
-        }).await
+    pub fn text_body(&self) -> Option<&str> {
+        self.text_body.as_deref()
     }
 
     pub fn id(&self) -> &str {
@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use color_eyre::eyre::{bail, Context, Result};
+use minijinja::{Environment, ErrorKind};
+use tracing::debug;
+
+/// Template sources read from files under a root directory.
+///
+/// Files are cached along with their modification time and read again when it changes,
+/// so an edited template is picked up by the next send without restarting.
+///
+/// Templates can share content with the other files of the root: `{% extends "layouts/base.html" %}`
+/// inherits a layout and overrides its `{% block %}`s, and `{% include "partials/footer.html" %}`
+/// inserts a partial. Names are paths relative to the root.
+#[derive(Clone)]
+pub struct TemplateStore {
+    root: PathBuf,
+    cache: Arc<Mutex<HashMap<PathBuf, CachedTemplate>>>,
+}
+
+struct CachedTemplate {
+    modified: SystemTime,
+    source: Arc<str>,
+}
+
+impl TemplateStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Use the `TEMPLATE_ROOT` environment variable as root, or `templates` by default.
+    pub fn from_env() -> Self {
+        Self::new(dotenvy::var("TEMPLATE_ROOT").unwrap_or_else(|_| "templates".to_owned()))
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Source of the template at `source_path`, relative to the root.
+    pub fn load(&self, source_path: &str) -> Result<Arc<str>> {
+        let path = self.resolve(source_path)?;
+
+        let modified = std::fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .with_context(|| format!("Reading metadata of template {}", path.display()))?;
+
+        // Unwrap is safe because the lock is never held across a panic
+        if let Some(cached) = self.cache.lock().unwrap().get(&path) {
+            if cached.modified == modified {
+                return Ok(cached.source.clone());
+            }
+        }
+
+        debug!("Load template {}", path.display());
+
+        let source = std::fs::read_to_string(&path)
+            .with_context(|| format!("Reading template {}", path.display()))?;
+
+        Environment::new()
+            .template_from_str(&source)
+            .with_context(|| format!("Validating template {}", path.display()))?;
+
+        let source: Arc<str> = source.into();
+
+        self.cache.lock().unwrap().insert(
+            path,
+            CachedTemplate {
+                modified,
+                source: source.clone(),
+            },
+        );
+
+        Ok(source)
+    }
+
+    /// Register the files of the root as layouts and partials that `env` can extend or include.
+    pub fn register(&self, env: &mut Environment) {
+        let store = self.clone();
+
+        env.set_loader(move |name| {
+            let exists = store.resolve(name).is_ok_and(|path| path.is_file());
+            if !exists {
+                return Ok(None);
+            }
+
+            match store.load(name) {
+                Ok(source) => Ok(Some(source.to_string())),
+                Err(err) => Err(minijinja::Error::new(
+                    ErrorKind::InvalidOperation,
+                    format!("{err:#}"),
+                )),
+            }
+        });
+    }
+
+    /// Join `source_path` to the root, refusing paths that would escape it.
+    fn resolve(&self, source_path: &str) -> Result<PathBuf> {
+        let relative = Path::new(source_path);
+
+        if !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+        {
+            bail!("Template path {source_path:?} must be relative to the template root");
+        }
+
+        Ok(self.root.join(relative))
+    }
+}
@@ -0,0 +1,11 @@
+use color_eyre::eyre::Result;
+
+/// Width at which the derived plain-text parts are wrapped.
+const TEXT_WIDTH: usize = 78;
+
+/// Derive the plain-text alternative of an HTML body. Links are kept as numbered footnotes.
+pub(crate) fn html_to_text(html: &str) -> Result<String> {
+    Ok(html2text::config::plain()
+        .link_footnotes(true)
+        .string_from_read(html.as_bytes(), TEXT_WIDTH)?)
+}
@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+
+use color_eyre::eyre::{bail, Result};
+use rusqlite::{params_from_iter, Connection};
+use serde_rusqlite::{columns_from_statement, from_row_with_columns};
+
+use super::{EmailModel, SQLEmailModel};
+
+/// The subject and body of an email in another language than its default content.
+#[derive(Debug)]
+pub struct EmailVariant {
+    locale: String,
+    pub(super) email: EmailModel,
+}
+
+impl EmailVariant {
+    pub(crate) const CREATE_TABLES: &'static str = r#"
+        CREATE TABLE IF NOT EXISTS EmailVariant (
+            email_ID            TEXT NOT NULL,
+            locale              TEXT NOT NULL,
+            email_discriminant  INTEGER CHECK(email_discriminant IN (0, 1, 2)),
+            plain_email_ID      TEXT,
+            template_email_ID   TEXT,
+            markdown_email_ID   TEXT,
+            PRIMARY KEY (email_ID, locale),
+            FOREIGN KEY (email_ID)  REFERENCES Email(ID)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE,
+            FOREIGN KEY (plain_email_ID)     REFERENCES PlainEmail(ID)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE,
+            FOREIGN KEY (template_email_ID)  REFERENCES TemplateEmail(ID)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE,
+            FOREIGN KEY (markdown_email_ID)  REFERENCES MarkdownEmail(ID)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE
+        ) STRICT;
+    "#;
+
+    pub(super) fn new(locale: &str, email: EmailModel) -> Result<Self> {
+        Ok(Self {
+            locale: normalize_locale(locale)?,
+            email,
+        })
+    }
+
+    pub fn locale(&self) -> &str {
+        &self.locale
+    }
+
+    /// For template variants, this is the unrendered template.
+    pub fn subject(&self) -> &str {
+        self.email.subject()
+    }
+
+    /// For template variants, this is the unrendered template, and for Markdown variants
+    /// the HTML compiled from the source.
+    pub fn body(&self) -> &str {
+        self.email.body()
+    }
+
+    pub fn text_body(&self) -> Option<&str> {
+        self.email.text_body()
+    }
+
+    /// Write the variant of the email `email_id`. Its model must already be written.
+    pub(super) fn write(&self, email_id: &str, conn: &Connection) -> Result<()> {
+        let mut stmt = conn.prepare_cached(
+            r"
+            INSERT INTO EmailVariant (email_ID, locale, email_discriminant, plain_email_ID, template_email_ID, markdown_email_ID)
+            VALUES (?, ?, ?, ?, ?, ?)
+        ",
+        )?;
+
+        let (plain_email_id, template_email_id, markdown_email_id) = self.email.ids();
+
+        stmt.execute((
+            email_id,
+            &self.locale,
+            self.email.discriminant(),
+            plain_email_id,
+            template_email_id,
+            markdown_email_id,
+        ))?;
+
+        Ok(())
+    }
+
+    /// Delete the variant and its model.
+    pub(super) fn delete(&self, email_id: &str, conn: &Connection) -> Result<()> {
+        conn.execute(
+            "DELETE FROM EmailVariant WHERE email_ID = ? AND locale = ?",
+            (email_id, &self.locale),
+        )?;
+
+        self.email.delete(conn)
+    }
+
+    /// Variants of each of the emails `email_ids`, by email ID.
+    pub(super) fn get_for_emails(
+        email_ids: &[&str],
+        conn: &Connection,
+    ) -> Result<HashMap<String, Vec<Self>>> {
+        let placeholders = vec!["?"; email_ids.len()].join(", ");
+        let mut stmt = conn.prepare(&format!(
+            "SELECT em.email_ID, em.locale, {} FROM EmailVariant em {} WHERE em.email_ID IN ({placeholders}) ORDER BY em.rowid",
+            EmailModel::SQL_COLUMNS,
+            EmailModel::SQL_JOINS,
+        ))?;
+
+        let columns = columns_from_statement(&stmt);
+
+        let rows = stmt.query_and_then(params_from_iter(email_ids), |row| {
+            let email_id: String = row.get("email_ID")?;
+            let locale: String = row.get("locale")?;
+            let model = from_row_with_columns::<SQLEmailModel>(row, &columns)?;
+
+            Ok::<_, color_eyre::eyre::Error>((email_id, locale, model))
+        })?;
+
+        let mut variants: HashMap<String, Vec<Self>> = HashMap::new();
+        for row in rows {
+            let (email_id, locale, model) = row?;
+
+            variants.entry(email_id).or_default().push(Self {
+                email: EmailModel::try_from(model)?,
+                locale,
+            });
+        }
+
+        Ok(variants)
+    }
+}
+
+/// Lowercase `locale` with `-` as separator, such as `fr` or `en-gb`.
+pub(crate) fn normalize_locale(locale: &str) -> Result<String> {
+    let locale = locale.trim().replace('_', "-").to_ascii_lowercase();
+
+    let valid = !locale.is_empty()
+        && locale.split('-').all(|subtag| {
+            !subtag.is_empty() && subtag.bytes().all(|byte| byte.is_ascii_alphanumeric())
+        });
+
+    if !valid {
+        bail!("Invalid locale {locale:?}");
+    }
+
+    Ok(locale)
+}
+
+/// Primary language of `locale`, `fr` for `fr-ca`.
+pub(super) fn language(locale: &str) -> &str {
+    locale.split('-').next().unwrap_or(locale)
+}
@@ -5,3 +5,5 @@ pub mod db;
 pub mod email;
 pub mod mailer;
 pub mod scheduler;
+pub mod sender;
+pub mod tracking;
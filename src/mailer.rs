@@ -1,18 +1,40 @@
+use std::collections::BTreeMap;
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use color_eyre::eyre::Result;
-use lettre::message::header::ContentType;
-use lettre::transport::smtp::authentication::Credentials;
-use lettre::transport::smtp::PoolConfig;
-use lettre::{Message, SmtpTransport, Transport};
-use tracing::debug;
+use chrono::Local;
+use color_eyre::eyre::{bail, Result};
+use tracing::{debug, warn};
 
 use crate::client::{Client, Group};
 use crate::db::DB;
-use crate::email::Email;
+use crate::email::{Email, EmailStatus, RenderContext, RenderedEmail, TemplateStore};
+use crate::sender::Sender;
+use crate::tracking::Delivery;
+
+mod ab_test;
+pub mod dkim;
+mod receiver;
+mod transport;
+
+pub use ab_test::{AbTest, AbTestResult};
+pub use dkim::{Dkim, DkimAlgorithm};
+pub use receiver::{Receiver, Recipients};
+pub use transport::{
+    AuthMechanism, FileTransport, MailTransport, MemoryTransport, SentMessage, SmtpConfig,
+    SmtpRelay, TlsMode,
+};
 
 pub struct Mailer<'a> {
-    smtp_transport: SmtpTransport,
+    transport: Box<dyn MailTransport>,
+    templates: TemplateStore,
+    /// Whether to refuse sending emails with lint errors
+    deny_lint_errors: bool,
+    /// Base URL of the tracking server the links are redirected through, if tracked
+    click_tracking: Option<String>,
+    /// Base URL of the tracking server serving the tracking pixel, if opens are tracked
+    open_tracking: Option<String>,
+    /// Signing of every message sent
+    dkim: Option<Dkim>,
     db: &'a DB,
 }
 
@@ -20,6 +42,7 @@ impl<'a> Mailer<'a> {
     pub const CREATE_TABLES: &'static str = r"
         CREATE TABLE IF NOT EXISTS MM_EmailClient (
             email_ID   TEXT,
+            revision   INTEGER,
             client_ID  TEXT,
             timestamp  INTEGER,
             FOREIGN KEY(email_ID)  REFERENCES Email(ID)
@@ -30,8 +53,20 @@ impl<'a> Mailer<'a> {
                 ON DELETE CASCADE
         ) STRICT;
 
+        CREATE TABLE IF NOT EXISTS EmailSending (
+            email_ID    TEXT,
+            revision    INTEGER,
+            receiver    TEXT NOT NULL,
+            recipients  INTEGER NOT NULL,
+            timestamp   INTEGER,
+            FOREIGN KEY(email_ID)  REFERENCES Email(ID)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE
+        ) STRICT;
+
         CREATE TABLE IF NOT EXISTS MM_EmailClientGroup (
             email_ID         TEXT,
+            revision         INTEGER,
             client_group_ID  TEXT,
             timestamp        INTEGER,
             FOREIGN KEY(email_ID)  REFERENCES Email(ID)
@@ -43,79 +78,466 @@ impl<'a> Mailer<'a> {
         ) STRICT;
     ";
 
-    pub fn new(db: &'a DB) -> Result<Self> {
-        let username = dotenvy::var("SMTP_USERNAME")?;
-        let password = dotenvy::var("SMTP_PASSWORD")?;
-        let creds = Credentials::new(username, password);
+    /// Number of clients loaded from the database at once when sending.
+    const PAGE_SIZE: usize = 500;
 
-        // Open a remote connection to gmail
-        let mailer = SmtpTransport::relay("smtp.gmail.com")
-            .unwrap()
-            .credentials(creds)
-            .pool_config(PoolConfig::new())
-            .build();
+    /// A mailer sending through the SMTP relay configured in the environment, see
+    /// [`SmtpRelay::from_env`].
+    pub fn new(db: &'a DB) -> Result<Self> {
+        Self::with_transport(SmtpRelay::from_env()?, db)
+    }
 
-        mailer.test_connection()?;
+    /// A mailer handing the messages to `transport`, once it passed its connection test.
+    pub fn with_transport(transport: impl MailTransport + 'static, db: &'a DB) -> Result<Self> {
+        transport.test_connection()?;
 
         Ok(Self {
-            smtp_transport: mailer,
+            transport: Box::new(transport),
+            templates: TemplateStore::from_env(),
+            deny_lint_errors: false,
+            click_tracking: None,
+            open_tracking: None,
+            dkim: Dkim::from_env()?,
             db,
         })
     }
 
+    /// Load template files from `templates` rather than from the root configured in the
+    /// environment, see [`TemplateStore::from_env`].
+    pub fn templates(mut self, templates: TemplateStore) -> Self {
+        self.templates = templates;
+        self
+    }
+
+    /// Refuse to send emails for which [`Email::lint`] reports errors. Lints are logged
+    /// either way.
+    pub fn deny_lint_errors(mut self, deny: bool) -> Self {
+        self.deny_lint_errors = deny;
+        self
+    }
+
+    /// Sign every message sent with DKIM, or stop signing with `None`. Defaults to the
+    /// signing configured in the environment, see [`Dkim::from_env`].
+    pub fn dkim(mut self, dkim: Option<Dkim>) -> Self {
+        self.dkim = dkim;
+        self
+    }
+
+    /// Redirect the HTTP links of the emails sent through the tracking server at
+    /// `base_url`, such as `https://t.example.com`, to record clicks. Each recipient gets
+    /// its own links, see [`Delivery`] and [`tracking::serve`](crate::tracking::serve).
+    ///
+    /// Clients who opted out with [`Client::set_do_not_track`] get the original links, as do
+    /// emails copied to CC or BCC recipients.
+    pub fn track_clicks(mut self, base_url: &str) -> Self {
+        self.click_tracking = Some(base_url.trim_end_matches('/').to_owned());
+        self
+    }
+
+    /// Embed a tracking pixel served by the tracking server at `base_url` in the emails
+    /// that opted in with [`Email::tracks_opens`], to record opens.
+    ///
+    /// Clients who opted out with [`Client::set_do_not_track`] get no pixel, nor do emails
+    /// copied to CC or BCC recipients.
+    pub fn track_opens(mut self, base_url: &str) -> Self {
+        self.open_tracking = Some(base_url.trim_end_matches('/').to_owned());
+        self
+    }
+
     pub async fn send(&self, email: &Email, receiver: &mut Receiver) -> Result<()> {
-        match receiver {
-            Receiver::Client(client) => self.send_to_client(email, client)?,
-            Receiver::Group(group) => self.send_to_group(email, group, self.db).await?,
+        self.send_inner(email, receiver, None).await
+    }
+
+    /// Send an email on behalf of a trigger, see [`Scheduler::schedule_email`]. The
+    /// generation is exposed to templates.
+    ///
+    /// [`Scheduler::schedule_email`]: crate::scheduler::Scheduler::schedule_email
+    pub async fn send_generation(
+        &self,
+        email: &Email,
+        receiver: &Receiver,
+        generation: u64,
+    ) -> Result<()> {
+        self.send_inner(email, receiver, Some(generation)).await
+    }
+
+    async fn send_inner(
+        &self,
+        email: &Email,
+        receiver: &Receiver,
+        generation: Option<u64>,
+    ) -> Result<()> {
+        // TODO: Gérer les erreurs pour les cas où tous les mails ne sont pas envoyé.
+
+        debug!(
+            "Send email to receiver. email = {}, receiver = {}",
+            email.id(),
+            receiver
+        );
+
+        if email.status() == EmailStatus::Draft {
+            bail!(
+                "Email {} is a draft and must be published to be sent",
+                email.id()
+            );
         }
 
-        self.write_sending(email, receiver).await?;
+        self.check_lints(email)?;
+
+        // Each message is copied to them, so they would get a copy per recipient
+        if email.headers().has_copies() && !matches!(receiver, Receiver::Client(_)) {
+            bail!(
+                "Email {} has CC or BCC recipients and can only be sent to a single client",
+                email.id()
+            );
+        }
+
+        let renderer = email.renderer(&self.templates)?;
+        let revision = email.sending_revision(&renderer, self.db).await?;
+        let date = Local::now();
+        let group = match receiver {
+            Receiver::Group(group) => Some(group.name()),
+            _ => None,
+        };
+
+        let mut recipients = receiver.recipients(Self::PAGE_SIZE, self.db).await?;
+        let mut sent = 0;
+        // Recipients getting the default content for lack of a variant in their locale
+        let mut missing_translations = BTreeMap::<String, usize>::new();
+
+        while let Some(clients) = recipients.next_page(self.db).await? {
+            for client in &clients {
+                let context = RenderContext::new(client, date)
+                    .group(group)
+                    .generation(generation);
+
+                self.deliver(email, revision, renderer.render(&context)?, client)
+                    .await?;
+            }
+
+            if email.is_localized() {
+                for locale in clients.iter().filter_map(Client::locale) {
+                    if !email.is_translated_for(locale) {
+                        *missing_translations.entry(locale.to_owned()).or_insert(0) += 1;
+                    }
+                }
+            }
+
+            sent += clients.len();
+        }
+
+        for (locale, recipients) in missing_translations {
+            warn!(
+                "Email {} has no variant in locale {locale}, {recipients} recipients get the default content",
+                email.id()
+            );
+        }
+
+        self.write_sending(email, revision, receiver, sent).await?;
 
         Ok(())
     }
 
-    fn send_to_client(&self, email: &Email, client: &Client) -> Result<()> {
+    /// Send the sample of an A/B test, each client of the sample receiving the variant it is
+    /// assigned to. The clients of the remainder are recorded to receive the winner later.
+    ///
+    /// Each assignment is recorded as soon as its variant is delivered, so an interrupted
+    /// sending can be resumed without emailing a client twice.
+    pub async fn send_ab_test(&self, ab_test: &mut AbTest) -> Result<()> {
+        debug!("Send A/B test. ab_test = {}", ab_test.id());
+
+        if ab_test.sent_timestamp().is_some() {
+            bail!("A/B test {} was already sent", ab_test.id());
+        }
+
+        ab_test.check_variants()?;
+        for email in ab_test.variants() {
+            self.check_lints(email)?;
+        }
+
+        let renderers = ab_test
+            .variants()
+            .iter()
+            .map(|email| email.renderer(&self.templates))
+            .collect::<Result<Vec<_>>>()?;
+        let mut revisions = Vec::with_capacity(renderers.len());
+        for (email, renderer) in ab_test.variants().iter().zip(&renderers) {
+            revisions.push(email.sending_revision(renderer, self.db).await?);
+        }
+        let date = Local::now();
+        let timestamp = now();
+        let group = ab_test.group();
+
+        let mut members = group.members(Self::PAGE_SIZE);
+
+        while let Some(clients) = members.next_page(self.db).await? {
+            let client_ids: Vec<&str> = clients.iter().map(Client::id).collect();
+            let assigned = ab_test.assigned(&client_ids, self.db).await?;
+            let clients: Vec<_> = clients
+                .iter()
+                .filter(|client| !assigned.contains(client.id()))
+                .collect();
+
+            let remainder: Vec<_> = clients
+                .iter()
+                .filter(|client| ab_test.assign(client.id()).is_none())
+                .map(|client| (client.id(), None))
+                .collect();
+            ab_test
+                .record_assignments(&remainder, timestamp, self.db)
+                .await?;
+
+            for client in clients {
+                let Some(variant) = ab_test.assign(client.id()) else {
+                    continue;
+                };
+
+                let context = RenderContext::new(client, date).group(Some(group.name()));
+                let rendered = renderers[variant].render(&context)?;
+
+                self.deliver(
+                    &ab_test.variants()[variant],
+                    revisions[variant],
+                    rendered,
+                    client,
+                )
+                .await?;
+                ab_test
+                    .record_assignments(&[(client.id(), Some(variant))], timestamp, self.db)
+                    .await?;
+            }
+        }
+
+        // Counted from the assignments, to include the clients of an interrupted sending
+        let results = ab_test.results(self.db).await?;
+        let receiver = format!("ab_test:{}", ab_test.id());
+        for ((email, revision), result) in ab_test.variants().iter().zip(revisions).zip(results) {
+            self.write_email_sending(email, revision, &receiver, result.recipients(), timestamp)
+                .await?;
+            self.write_group_sending(email, revision, group, timestamp)
+                .await?;
+        }
+
+        ab_test.set_sent(timestamp, self.db).await
+    }
+
+    /// Send the variant `winner_id` to the clients of the remainder of an A/B test that
+    /// didn't receive it yet. An interrupted sending can be resumed with the same winner.
+    pub async fn send_ab_test_remainder(
+        &self,
+        ab_test: &mut AbTest,
+        winner_id: &str,
+    ) -> Result<()> {
+        debug!(
+            "Send winner of A/B test to the remainder. ab_test = {}, email = {}",
+            ab_test.id(),
+            winner_id
+        );
+
+        if ab_test.sent_timestamp().is_none() {
+            bail!("The sample of A/B test {} wasn't sent yet", ab_test.id());
+        }
+
+        let Some(position) = ab_test
+            .variants()
+            .iter()
+            .position(|email| email.id() == winner_id)
+        else {
+            bail!(
+                "Email {winner_id} isn't a variant of A/B test {}",
+                ab_test.id()
+            );
+        };
+
+        match ab_test.winner() {
+            Some(winner) if winner != winner_id => {
+                bail!(
+                    "A/B test {} was already won by email {winner}",
+                    ab_test.id()
+                )
+            }
+            Some(_) => {}
+            None => ab_test.set_winner(winner_id, self.db).await?,
+        }
+
+        ab_test.check_variants()?;
+
+        let email = &ab_test.variants()[position];
+        let renderer = email.renderer(&self.templates)?;
+        let revision = email.sending_revision(&renderer, self.db).await?;
+        let date = Local::now();
+        let timestamp = now();
+        let group = ab_test.group();
+        let mut sent = 0;
+
+        loop {
+            let clients = ab_test.pending_remainder(Self::PAGE_SIZE, self.db).await?;
+            if clients.is_empty() {
+                break;
+            }
+
+            // Each client is recorded as soon as it is emailed, so that a resumed sending
+            // doesn't email it again
+            for client in &clients {
+                let context = RenderContext::new(client, date).group(Some(group.name()));
+
+                self.deliver(email, revision, renderer.render(&context)?, client)
+                    .await?;
+                ab_test
+                    .record_remainder(&[client.id()], email.id(), timestamp, self.db)
+                    .await?;
+                sent += 1;
+            }
+        }
+
+        let receiver = format!("ab_test:{}", ab_test.id());
+        self.write_email_sending(email, revision, &receiver, sent, timestamp)
+            .await?;
+        self.write_group_sending(email, revision, group, timestamp)
+            .await
+    }
+
+    /// Once the remainder delay of an A/B test has passed, send the variant chosen by
+    /// `pick_winner` from the results to the remainder. Returns `false` when no winner was
+    /// picked and nothing was sent.
+    ///
+    /// Fails if the delay hasn't passed yet. See
+    /// [`Scheduler::schedule_ab_test_remainder`](crate::scheduler::Scheduler::schedule_ab_test_remainder)
+    /// to call it when the delay has passed.
+    pub async fn finish_ab_test(
+        &self,
+        ab_test: &mut AbTest,
+        pick_winner: impl FnOnce(&[AbTestResult]) -> Option<String>,
+    ) -> Result<bool> {
+        let Some(remainder_timestamp) = ab_test.remainder_timestamp() else {
+            bail!(
+                "A/B test {} has no remainder delay or wasn't sent yet",
+                ab_test.id()
+            );
+        };
+
+        if remainder_timestamp > now() {
+            bail!(
+                "The remainder of A/B test {} can't be sent before {remainder_timestamp}",
+                ab_test.id()
+            );
+        }
+
+        let results = ab_test.results(self.db).await?;
+        let Some(winner_id) = pick_winner(&results) else {
+            return Ok(false);
+        };
+
+        self.send_ab_test_remainder(ab_test, &winner_id).await?;
+
+        Ok(true)
+    }
+
+    /// Log the lints of `email`, and fail on errors if they are denied.
+    fn check_lints(&self, email: &Email) -> Result<()> {
+        let report = email.lint(&self.templates);
+
+        for lint in report.lints() {
+            warn!("Lint of email {}: {lint}", email.id());
+        }
+
+        if self.deny_lint_errors && report.has_errors() {
+            bail!(
+                "Email {} has {} lint errors and won't be sent",
+                email.id(),
+                report.errors().count()
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Send `rendered` to `client`, with its clicks and opens tracked if enabled and
+    /// allowed by the client. The links of the signature and of the plain-text alternative
+    /// are tracked too. The delivery is recorded with the revision `revision` of the email.
+    async fn deliver(
+        &self,
+        email: &Email,
+        revision: u32,
+        rendered: RenderedEmail,
+        client: &Client,
+    ) -> Result<()> {
+        let mut rendered = email.signed(rendered)?;
+        let click_tracking = self.click_tracking.as_deref();
+        let open_tracking = self
+            .open_tracking
+            .as_deref()
+            .filter(|_| email.tracks_opens());
+
+        // The clicks and opens of the CC and BCC recipients couldn't be told apart from the
+        // ones of the client
+        let tracked = !client.do_not_track() && !email.headers().has_copies();
+
+        if tracked && (click_tracking.is_some() || open_tracking.is_some()) {
+            let mut delivery = Delivery::new(email, client).revision(revision);
+
+            if let Some(base_url) = click_tracking {
+                rendered.body = delivery.track_links(&rendered.body, base_url)?;
+                rendered.text_body = delivery.track_text_links(&rendered.text_body, base_url);
+            }
+            if let Some(base_url) = open_tracking {
+                rendered.body = delivery.track_opens(&rendered.body, base_url);
+            }
+
+            delivery.write(now(), self.db).await?;
+        }
+
+        self.send_to_client(email, &rendered, client)
+    }
+
+    fn send_to_client(
+        &self,
+        email: &Email,
+        rendered: &RenderedEmail,
+        client: &Client,
+    ) -> Result<()> {
         debug!(
             "Send email to client. email = {}, client = {}",
             email.id(),
             client.id()
         );
 
-        // Unwrap is safe because from and to are provided
-        let message = Message::builder()
-            .from(email.sender_adresse().parse().unwrap())
-            .to(client.adresse().parse().unwrap())
-            .subject(email.subject())
-            .header(ContentType::TEXT_HTML)
-            .body(email.body())
-            .unwrap();
+        let mut message = email.message(rendered, client)?;
+        if let Some(dkim) = &self.dkim {
+            dkim.sign(&mut message);
+        }
 
-        // debug!(self.smtp_transport.);
-        self.smtp_transport.send(&message)?;
+        let credentials = email.sender().map(Sender::credentials).transpose()?;
+        self.transport
+            .send(&message, credentials.flatten().as_ref())?;
 
         Ok(())
     }
 
-    async fn send_to_group(&self, email: &Email, group: &Group, db: &DB) -> Result<()> {
-        // TODO: Gérer les erreurs pour les cas où tous les mails ne sont pas envoyé.
-        // TODO: Gérer le cas où les clients du group n'ont pas été fetch.
+    async fn write_sending(
+        &self,
+        email: &Email,
+        revision: u32,
+        receiver: &Receiver,
+        recipients: usize,
+    ) -> Result<()> {
+        let timestamp = now();
 
         debug!(
-            "Send email to group. email = {}, group = {}",
+            "Write to database email sent to receiver. email={}, receiver={}",
             email.id(),
-            group.id()
+            receiver
         );
 
-        group.query_clients(db).await?;
+        self.write_email_sending(
+            email,
+            revision,
+            &receiver.to_string(),
+            recipients,
+            timestamp,
+        )
+        .await?;
 
-        group
-            .clients()
-            .unwrap()
-            .iter()
-            .try_for_each(|client| self.send_to_client(email, client))
-    }
-
-    async fn write_sending(&self, email: &Email, receiver: &Receiver) -> Result<()> {
         match receiver {
             Receiver::Client(client) => {
                 debug!(
@@ -128,74 +550,82 @@ impl<'a> Mailer<'a> {
                     .connection(|conn| {
                         let mut stmt = conn.prepare_cached(
                             r"
-                        INSERT INTO MM_EmailClient (email_ID, client_ID, timestamp) VALUES (?, ?, ?)
+                        INSERT INTO MM_EmailClient (email_ID, revision, client_ID, timestamp) VALUES (?, ?, ?, ?)
                     ",
                         )?;
 
-                        let params = (
-                            email.id(),
-                            client.id(),
-                            // Unwrap in safe because `UNIX_EPOCH` is 0 and thus less than `SystemTime::now()`
-                            SystemTime::now()
-                                .duration_since(UNIX_EPOCH)
-                                .unwrap()
-                                .as_secs(),
-                        );
-
-                        stmt.execute(params)?;
+                        stmt.execute((email.id(), revision, client.id(), timestamp))?;
 
                         Ok(())
                     })
                     .await?;
             }
             Receiver::Group(group) => {
-                debug!(
-                    "Write to database email sent to group. email={}, group={}",
-                    email.id(),
-                    group.id()
-                );
-
-                self.db.connection(|conn| {
-                    let mut stmt = conn.prepare_cached(
-                        r"
-                        INSERT INTO MM_EmailClientGroup (email_ID, client_group_ID, timestamp) VALUES (?, ?, ?)
-                    ",
-                    )?;
-
-                    let params = (
-                        email.id(),
-                        group.id(),
-                        // Unwrap in safe because `UNIX_EPOCH` is 0 and thus less than `SystemTime::now()`
-                        SystemTime::now()
-                            .duration_since(UNIX_EPOCH)
-                            .unwrap()
-                            .as_secs(),
-                    );
-
-                    stmt.execute(params)?;
-
-                    Ok(())
-                }).await?;
+                self.write_group_sending(email, revision, group, timestamp)
+                    .await?
             }
+            // Compound receivers are only logged through their expression
+            _ => {}
         }
 
         Ok(())
     }
-}
 
-pub enum Receiver {
-    Client(Client),
-    Group(Group),
-}
+    async fn write_email_sending(
+        &self,
+        email: &Email,
+        revision: u32,
+        receiver: &str,
+        recipients: usize,
+        timestamp: u64,
+    ) -> Result<()> {
+        self.db
+            .connection(|conn| {
+                let mut stmt = conn.prepare_cached(
+                    r"
+                    INSERT INTO EmailSending (email_ID, revision, receiver, recipients, timestamp) VALUES (?, ?, ?, ?, ?)
+                ",
+                )?;
+
+                stmt.execute((email.id(), revision, receiver, recipients, timestamp))?;
+
+                Ok(())
+            })
+            .await
+    }
 
-impl From<Client> for Receiver {
-    fn from(value: Client) -> Self {
-        Self::Client(value)
+    async fn write_group_sending(
+        &self,
+        email: &Email,
+        revision: u32,
+        group: &Group,
+        timestamp: u64,
+    ) -> Result<()> {
+        debug!(
+            "Write to database email sent to group. email={}, group={}",
+            email.id(),
+            group.id()
+        );
+
+        self.db.connection(|conn| {
+            let mut stmt = conn.prepare_cached(
+                r"
+                INSERT INTO MM_EmailClientGroup (email_ID, revision, client_group_ID, timestamp) VALUES (?, ?, ?, ?)
+            ",
+            )?;
+
+            stmt.execute((email.id(), revision, group.id(), timestamp))?;
+
+            Ok(())
+        }).await
     }
 }
 
-impl From<Group> for Receiver {
-    fn from(value: Group) -> Self {
-        Self::Group(value)
-    }
+/// Seconds since the Unix epoch.
+pub(crate) fn now() -> u64 {
+    // Unwrap in safe because `UNIX_EPOCH` is 0 and thus less than `SystemTime::now()`
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
 }
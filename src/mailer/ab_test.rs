@@ -0,0 +1,540 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use color_eyre::eyre::{bail, ContextCompat, Result};
+use cuid2::create_id;
+use rusqlite::{params_from_iter, OptionalExtension};
+
+use crate::client::{Client, Group};
+use crate::db::DB;
+use crate::email::{Email, EmailStatus};
+
+/// A/B test of several emails on a group.
+///
+/// A sample of the group is split among the variants, and the rest of the group, the
+/// remainder, can later receive the variant that performed best. Which part of the group a
+/// client falls into only depends on the seed and on the client, so the same seed always
+/// gives the same split.
+#[derive(Debug)]
+pub struct AbTest {
+    id: String,
+    group: Group,
+    variants: Vec<Email>,
+    seed: u64,
+    /// Percentage of the group in the sample, from 1 to 100
+    sample_percent: u8,
+    /// Time to wait after the sample was sent before sending the winner to the remainder
+    remainder_delay: Option<Duration>,
+    /// When the sample was sent, in seconds since the Unix epoch
+    sent_timestamp: Option<u64>,
+    /// ID of the variant sent to the remainder
+    winner: Option<String>,
+}
+
+/// How a variant of an A/B test performed.
+#[derive(Debug)]
+pub struct AbTestResult {
+    email_id: String,
+    recipients: usize,
+    clickers: usize,
+    openers: usize,
+}
+
+impl AbTestResult {
+    pub fn email_id(&self) -> &str {
+        &self.email_id
+    }
+
+    /// Number of clients of the sample the variant was sent to.
+    pub fn recipients(&self) -> usize {
+        self.recipients
+    }
+
+    /// Number of clients of the sample who clicked a link of the variant, when it was sent
+    /// with click tracking.
+    pub fn clickers(&self) -> usize {
+        self.clickers
+    }
+
+    /// Number of clients of the sample who opened the variant, when it was sent with open
+    /// tracking.
+    pub fn openers(&self) -> usize {
+        self.openers
+    }
+}
+
+impl AbTest {
+    pub(crate) const CREATE_TABLES: &'static str = r#"
+        CREATE TABLE IF NOT EXISTS AbTest (
+            ID               TEXT PRIMARY KEY,
+            client_group_ID  TEXT NOT NULL,
+            seed             INTEGER NOT NULL,
+            sample_percent   INTEGER NOT NULL CHECK(sample_percent BETWEEN 1 AND 100),
+            remainder_delay  INTEGER,
+            sent_timestamp   INTEGER,
+            winner_email_ID  TEXT,
+            FOREIGN KEY (client_group_ID)  REFERENCES ClientGroup(ID)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE,
+            FOREIGN KEY (winner_email_ID)  REFERENCES Email(ID)
+                ON UPDATE CASCADE
+                ON DELETE SET NULL
+        ) STRICT;
+
+        CREATE TABLE IF NOT EXISTS AbTestVariant (
+            ab_test_ID  TEXT NOT NULL,
+            position    INTEGER NOT NULL,
+            email_ID    TEXT NOT NULL,
+            PRIMARY KEY (ab_test_ID, position),
+            FOREIGN KEY (ab_test_ID)  REFERENCES AbTest(ID)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE,
+            FOREIGN KEY (email_ID)  REFERENCES Email(ID)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE
+        ) STRICT;
+
+        CREATE TABLE IF NOT EXISTS AbTestAssignment (
+            ab_test_ID  TEXT NOT NULL,
+            client_ID   TEXT NOT NULL,
+            remainder   INTEGER NOT NULL CHECK(remainder IN (0, 1)),
+            email_ID    TEXT,
+            timestamp   INTEGER,
+            PRIMARY KEY (ab_test_ID, client_ID),
+            FOREIGN KEY (ab_test_ID)  REFERENCES AbTest(ID)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE,
+            FOREIGN KEY (client_ID)  REFERENCES Client(ID)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE,
+            FOREIGN KEY (email_ID)  REFERENCES Email(ID)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE
+        ) STRICT;
+    "#;
+
+    /// Test `variants` on `sample_percent` percent of `group`. With a `remainder_delay`,
+    /// the rest of the group can receive the winner once the delay has passed.
+    pub async fn create(
+        group: Group,
+        variants: Vec<Email>,
+        seed: u64,
+        sample_percent: u8,
+        remainder_delay: Option<Duration>,
+        db: &DB,
+    ) -> Result<Self> {
+        if variants.len() < 2 {
+            bail!("An A/B test needs at least two variants");
+        }
+
+        if let Some((i, email)) = variants.iter().enumerate().find(|(i, email)| {
+            variants[..*i]
+                .iter()
+                .any(|previous| previous.id() == email.id())
+        }) {
+            bail!("Email {} is variant {i} of the A/B test twice", email.id());
+        }
+
+        if !(1..=100).contains(&sample_percent) {
+            bail!("Sample of an A/B test must be between 1 and 100 percent, not {sample_percent}");
+        }
+
+        let this = Self {
+            id: create_id(),
+            group,
+            variants,
+            seed,
+            sample_percent,
+            remainder_delay,
+            sent_timestamp: None,
+            winner: None,
+        };
+
+        db.connection(|conn| {
+            let tx = conn.unchecked_transaction()?;
+
+            tx.execute(
+                r"
+                INSERT INTO AbTest (ID, client_group_ID, seed, sample_percent, remainder_delay)
+                VALUES (?, ?, ?, ?, ?)
+            ",
+                (
+                    &this.id,
+                    this.group.id(),
+                    // Stored as the signed integer with the same bits
+                    this.seed as i64,
+                    this.sample_percent,
+                    this.remainder_delay.map(|delay| delay.as_secs()),
+                ),
+            )?;
+
+            let mut stmt = tx.prepare_cached(
+                "INSERT INTO AbTestVariant (ab_test_ID, position, email_ID) VALUES (?, ?, ?)",
+            )?;
+            for (position, email) in this.variants.iter().enumerate() {
+                stmt.execute((&this.id, position, email.id()))?;
+            }
+            drop(stmt);
+
+            tx.commit()?;
+
+            Ok(())
+        })
+        .await?;
+
+        Ok(this)
+    }
+
+    pub async fn get_one(id: &str, db: &DB) -> Result<Option<Self>> {
+        let row = db
+            .connection(|conn| {
+                Ok(conn
+                    .query_row(
+                        r"
+                        SELECT client_group_ID, seed, sample_percent, remainder_delay, sent_timestamp, winner_email_ID
+                            FROM AbTest
+                            WHERE ID = ?
+                    ",
+                        [id],
+                        |row| {
+                            Ok((
+                                row.get::<_, String>(0)?,
+                                row.get::<_, i64>(1)?,
+                                row.get::<_, u8>(2)?,
+                                row.get::<_, Option<u64>>(3)?,
+                                row.get::<_, Option<u64>>(4)?,
+                                row.get::<_, Option<String>>(5)?,
+                            ))
+                        },
+                    )
+                    .optional()?)
+            })
+            .await?;
+
+        let Some((group_id, seed, sample_percent, remainder_delay, sent_timestamp, winner)) = row
+        else {
+            return Ok(None);
+        };
+
+        let group = Group::get_one(&group_id, db)
+            .await?
+            .with_context(|| format!("Group {group_id} of A/B test {id} is missing"))?;
+
+        let variant_ids: Vec<String> = db
+            .connection(|conn| {
+                let mut stmt = conn.prepare_cached(
+                    "SELECT email_ID FROM AbTestVariant WHERE ab_test_ID = ? ORDER BY position",
+                )?;
+
+                let ids = Result::from_iter(stmt.query_map([id], |row| row.get(0))?)?;
+
+                Ok(ids)
+            })
+            .await?;
+
+        let mut variants = Vec::with_capacity(variant_ids.len());
+        for email_id in variant_ids {
+            variants.push(
+                Email::get_one(&email_id, db)
+                    .await?
+                    .with_context(|| format!("Variant {email_id} of A/B test {id} is missing"))?,
+            );
+        }
+
+        Ok(Some(Self {
+            id: id.to_owned(),
+            group,
+            variants,
+            seed: seed as u64,
+            sample_percent,
+            remainder_delay: remainder_delay.map(Duration::from_secs),
+            sent_timestamp,
+            winner,
+        }))
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn group(&self) -> &Group {
+        &self.group
+    }
+
+    pub fn variants(&self) -> &[Email] {
+        &self.variants
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn sample_percent(&self) -> u8 {
+        self.sample_percent
+    }
+
+    pub fn remainder_delay(&self) -> Option<Duration> {
+        self.remainder_delay
+    }
+
+    /// When the sample was sent, in seconds since the Unix epoch.
+    pub fn sent_timestamp(&self) -> Option<u64> {
+        self.sent_timestamp
+    }
+
+    /// When the winner can be sent to the remainder, in seconds since the Unix epoch.
+    pub fn remainder_timestamp(&self) -> Option<u64> {
+        Some(self.sent_timestamp? + self.remainder_delay?.as_secs())
+    }
+
+    /// ID of the variant sent to the remainder.
+    pub fn winner(&self) -> Option<&str> {
+        self.winner.as_deref()
+    }
+
+    /// Index of the variant sent to `client_id`, or `None` when the client is in the
+    /// remainder.
+    pub fn assign(&self, client_id: &str) -> Option<usize> {
+        let hash = hash(self.seed, client_id);
+
+        if hash % 100 >= self.sample_percent as u64 {
+            return None;
+        }
+
+        Some(((hash / 100) % self.variants.len() as u64) as usize)
+    }
+
+    /// Number of clients of the sample each variant was sent to, in the order of the variants.
+    pub async fn results(&self, db: &DB) -> Result<Vec<AbTestResult>> {
+        db.connection(|conn| {
+            let mut stmt = conn.prepare_cached(
+                r"
+                SELECT v.email_ID, COUNT(a.client_ID), COUNT(
+                    CASE WHEN EXISTS (
+                        SELECT 1 FROM Click c
+                            JOIN Delivery d ON d.ID = c.delivery_ID
+                            WHERE d.email_ID = a.email_ID AND d.client_ID = a.client_ID
+                                AND d.timestamp >= a.timestamp
+                    ) THEN 1 END
+                ), COUNT(
+                    CASE WHEN EXISTS (
+                        SELECT 1 FROM Open o
+                            JOIN Delivery d ON d.ID = o.delivery_ID
+                            WHERE d.email_ID = a.email_ID AND d.client_ID = a.client_ID
+                                AND d.timestamp >= a.timestamp
+                    ) THEN 1 END
+                )
+                    FROM AbTestVariant v
+                    LEFT JOIN AbTestAssignment a
+                        ON a.ab_test_ID = v.ab_test_ID AND a.email_ID = v.email_ID AND a.remainder = 0
+                    WHERE v.ab_test_ID = ?
+                    GROUP BY v.position
+                    ORDER BY v.position
+            ",
+            )?;
+
+            let results = Result::from_iter(stmt.query_map([&self.id], |row| {
+                Ok(AbTestResult {
+                    email_id: row.get(0)?,
+                    recipients: row.get(1)?,
+                    clickers: row.get(2)?,
+                    openers: row.get(3)?,
+                })
+            })?)?;
+
+            Ok(results)
+        })
+        .await
+    }
+
+    /// Fail unless every variant is published.
+    pub(super) fn check_variants(&self) -> Result<()> {
+        if let Some(email) = self
+            .variants
+            .iter()
+            .find(|email| email.status() == EmailStatus::Draft)
+        {
+            bail!(
+                "Variant {} of A/B test {} is a draft and must be published to be sent",
+                email.id(),
+                self.id
+            );
+        }
+
+        if let Some(email) = self
+            .variants
+            .iter()
+            .find(|email| email.headers().has_copies())
+        {
+            bail!(
+                "Variant {} of A/B test {} has CC or BCC recipients, who would get a copy per client of the group",
+                email.id(),
+                self.id
+            );
+        }
+
+        Ok(())
+    }
+
+    /// The clients among `client_ids` that already have an assignment.
+    pub(super) async fn assigned(&self, client_ids: &[&str], db: &DB) -> Result<HashSet<String>> {
+        if client_ids.is_empty() {
+            return Ok(HashSet::new());
+        }
+
+        db.connection(|conn| {
+            let placeholders = vec!["?"; client_ids.len()].join(", ");
+            let mut stmt = conn.prepare(&format!(
+                "SELECT client_ID FROM AbTestAssignment WHERE ab_test_ID = ? AND client_ID IN ({placeholders})"
+            ))?;
+
+            let params = std::iter::once(self.id.as_str()).chain(client_ids.iter().copied());
+            let assigned =
+                Result::from_iter(stmt.query_map(params_from_iter(params), |row| row.get(0))?)?;
+
+            Ok(assigned)
+        })
+        .await
+    }
+
+    /// Record which variant was sent to each of `assignments`, `None` for the clients of the
+    /// remainder.
+    pub(super) async fn record_assignments(
+        &self,
+        assignments: &[(&str, Option<usize>)],
+        timestamp: u64,
+        db: &DB,
+    ) -> Result<()> {
+        db.connection(|conn| {
+            let tx = conn.unchecked_transaction()?;
+
+            let mut stmt = tx.prepare_cached(
+                r"
+                INSERT INTO AbTestAssignment (ab_test_ID, client_ID, remainder, email_ID, timestamp)
+                VALUES (?, ?, ?, ?, ?)
+            ",
+            )?;
+            for (client_id, variant) in assignments {
+                let email_id = variant.map(|variant| self.variants[variant].id());
+
+                stmt.execute((
+                    &self.id,
+                    client_id,
+                    variant.is_none(),
+                    email_id,
+                    email_id.map(|_| timestamp),
+                ))?;
+            }
+            drop(stmt);
+
+            tx.commit()?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    pub(super) async fn set_sent(&mut self, timestamp: u64, db: &DB) -> Result<()> {
+        db.connection(|conn| {
+            conn.execute(
+                "UPDATE AbTest SET sent_timestamp = ? WHERE ID = ?",
+                (timestamp, &self.id),
+            )?;
+
+            Ok(())
+        })
+        .await?;
+
+        self.sent_timestamp = Some(timestamp);
+
+        Ok(())
+    }
+
+    pub(super) async fn set_winner(&mut self, email_id: &str, db: &DB) -> Result<()> {
+        db.connection(|conn| {
+            conn.execute(
+                "UPDATE AbTest SET winner_email_ID = ? WHERE ID = ?",
+                (email_id, &self.id),
+            )?;
+
+            Ok(())
+        })
+        .await?;
+
+        self.winner = Some(email_id.to_owned());
+
+        Ok(())
+    }
+
+    /// Clients of the remainder that didn't receive the winner yet, at most `page_size`.
+    pub(super) async fn pending_remainder(&self, page_size: usize, db: &DB) -> Result<Vec<Client>> {
+        let ids: Vec<String> = db
+            .connection(|conn| {
+                let mut stmt = conn.prepare_cached(
+                    r"
+                    SELECT client_ID FROM AbTestAssignment
+                        WHERE ab_test_ID = ? AND remainder = 1 AND email_ID IS NULL
+                        ORDER BY client_ID
+                        LIMIT ?
+                ",
+                )?;
+
+                let ids =
+                    Result::from_iter(stmt.query_map((&self.id, page_size), |row| row.get(0))?)?;
+
+                Ok(ids)
+            })
+            .await?;
+
+        Ok(Client::get_many(ids.into_iter(), db)
+            .await?
+            .into_iter()
+            .flatten()
+            .collect())
+    }
+
+    /// Record that the winner was sent to `client_ids`.
+    pub(super) async fn record_remainder(
+        &self,
+        client_ids: &[&str],
+        email_id: &str,
+        timestamp: u64,
+        db: &DB,
+    ) -> Result<()> {
+        db.connection(|conn| {
+            let tx = conn.unchecked_transaction()?;
+
+            let mut stmt = tx.prepare_cached(
+                r"
+                UPDATE AbTestAssignment SET email_ID = ?, timestamp = ?
+                    WHERE ab_test_ID = ? AND client_ID = ?
+            ",
+            )?;
+            for client_id in client_ids {
+                stmt.execute((email_id, timestamp, &self.id, client_id))?;
+            }
+            drop(stmt);
+
+            tx.commit()?;
+
+            Ok(())
+        })
+        .await
+    }
+}
+
+/// FNV-1a hash of the seed and the client, mixed with the SplitMix64 finalizer. Unlike the
+/// hashers of the standard library, it is stable across Rust versions and platforms.
+fn hash(seed: u64, client_id: &str) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in seed.to_le_bytes().iter().chain(client_id.as_bytes()) {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+
+    hash ^= hash >> 30;
+    hash = hash.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash ^= hash >> 27;
+    hash = hash.wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
@@ -0,0 +1,421 @@
+use std::fmt::Debug;
+use std::str::FromStr;
+use std::sync::LazyLock;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use color_eyre::eyre::{bail, Context, ContextCompat, Result};
+use ed25519_dalek::{Signature, SigningKey, Verifier, VerifyingKey};
+use lettre::message::dkim::{
+    DkimCanonicalization, DkimCanonicalizationType, DkimConfig, DkimSigningAlgorithm,
+    DkimSigningKey,
+};
+use lettre::message::header::HeaderName;
+use lettre::Message;
+use regex::Regex;
+use rsa::pkcs1::{DecodeRsaPrivateKey, EncodeRsaPrivateKey, LineEnding};
+use rsa::pkcs8::{DecodePrivateKey, DecodePublicKey, EncodePublicKey};
+use rsa::{Pkcs1v15Sign, RsaPrivateKey, RsaPublicKey};
+use sha2::{Digest, Sha256};
+
+/// Headers covered by the signature. Those a message doesn't have are signed too, so that
+/// they can't be added on the way.
+const SIGNED_HEADERS: &[&str] = &[
+    "From",
+    "Reply-To",
+    "To",
+    "Cc",
+    "Subject",
+    "Date",
+    "Message-ID",
+    "MIME-Version",
+];
+
+/// The `b=` tag of a DKIM-Signature header, which holds the signature itself.
+static SIGNATURE_TAG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(^|;)(\s*b\s*=)[^;]*").unwrap());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DkimAlgorithm {
+    Rsa,
+    Ed25519,
+}
+
+impl FromStr for DkimAlgorithm {
+    type Err = color_eyre::eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "rsa" => Ok(Self::Rsa),
+            "ed25519" => Ok(Self::Ed25519),
+            _ => bail!("Unknown DKIM algorithm {s}, expected rsa or ed25519"),
+        }
+    }
+}
+
+/// DKIM signing of the messages sent, so that receivers can check that they come from
+/// `domain` and weren't altered on the way.
+///
+/// The public key must be published in a DNS TXT record named [`Dkim::dns_name`], with
+/// [`Dkim::dns_record`] as its value. For DMARC to pass, the domain must also be the one of
+/// the From adresse, or a parent of it.
+pub struct Dkim {
+    selector: String,
+    domain: String,
+    algorithm: DkimAlgorithm,
+    /// Public key, as published in DNS
+    public_key: String,
+    config: DkimConfig,
+}
+
+impl Dkim {
+    /// Sign as `domain` with the key published under `selector`.
+    ///
+    /// RSA keys are PEM, in PKCS#1 (`BEGIN RSA PRIVATE KEY`) or PKCS#8 (`BEGIN PRIVATE
+    /// KEY`). Ed25519 keys are PEM in PKCS#8, as written by `openssl genpkey`, or the base64
+    /// of their 32 bytes.
+    pub fn new(
+        selector: &str,
+        domain: &str,
+        algorithm: DkimAlgorithm,
+        private_key: &str,
+    ) -> Result<Self> {
+        for (name, value) in [("selector", selector), ("domain", domain)] {
+            if value.is_empty()
+                || !value
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+            {
+                bail!("Invalid DKIM {name} {value:?}");
+            }
+        }
+
+        let private_key = private_key.trim();
+
+        let (public_key, signing_key) = match algorithm {
+            DkimAlgorithm::Rsa => {
+                let key = RsaPrivateKey::from_pkcs1_pem(private_key)
+                    .or_else(|_| RsaPrivateKey::from_pkcs8_pem(private_key))
+                    .context("Parsing RSA DKIM key, expected PEM in PKCS#1 or PKCS#8")?;
+                let public_key = RsaPublicKey::from(&key).to_public_key_der()?;
+
+                (
+                    BASE64.encode(public_key.as_bytes()),
+                    DkimSigningKey::new(
+                        &key.to_pkcs1_pem(LineEnding::LF)?,
+                        DkimSigningAlgorithm::Rsa,
+                    )?,
+                )
+            }
+            DkimAlgorithm::Ed25519 => {
+                let key = match SigningKey::from_pkcs8_pem(private_key) {
+                    Ok(key) => key,
+                    Err(_) => SigningKey::from_bytes(
+                        &BASE64
+                            .decode(private_key)
+                            .ok()
+                            .and_then(|bytes| bytes.try_into().ok())
+                            .context(
+                                "Parsing Ed25519 DKIM key, expected PEM in PKCS#8 or 32 bytes in base64",
+                            )?,
+                    ),
+                };
+
+                (
+                    BASE64.encode(key.verifying_key().as_bytes()),
+                    DkimSigningKey::new(
+                        &BASE64.encode(key.to_bytes()),
+                        DkimSigningAlgorithm::Ed25519,
+                    )?,
+                )
+            }
+        };
+
+        let config = DkimConfig::new(
+            selector.to_owned(),
+            domain.to_owned(),
+            signing_key,
+            SIGNED_HEADERS
+                .iter()
+                .map(|name| HeaderName::new_from_ascii_str(name))
+                .collect(),
+            // Relaxed canonicalization survives relays that rewrap headers or trim spaces
+            DkimCanonicalization {
+                header: DkimCanonicalizationType::Relaxed,
+                body: DkimCanonicalizationType::Relaxed,
+            },
+        );
+
+        Ok(Self {
+            selector: selector.to_owned(),
+            domain: domain.to_owned(),
+            algorithm,
+            public_key,
+            config,
+        })
+    }
+
+    /// The signing configured in the environment, if any: `DKIM_SELECTOR`, `DKIM_DOMAIN`,
+    /// `DKIM_PRIVATE_KEY_PATH` and `DKIM_ALGORITHM`, `rsa` by default.
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(selector) = dotenvy::var("DKIM_SELECTOR") else {
+            return Ok(None);
+        };
+
+        let domain = dotenvy::var("DKIM_DOMAIN").context("DKIM_DOMAIN is missing")?;
+        let path =
+            dotenvy::var("DKIM_PRIVATE_KEY_PATH").context("DKIM_PRIVATE_KEY_PATH is missing")?;
+        let algorithm = match dotenvy::var("DKIM_ALGORITHM") {
+            Ok(algorithm) => algorithm.parse()?,
+            Err(_) => DkimAlgorithm::Rsa,
+        };
+
+        let private_key = std::fs::read_to_string(&path)
+            .with_context(|| format!("Reading DKIM private key {path}"))?;
+
+        Self::new(&selector, &domain, algorithm, &private_key).map(Some)
+    }
+
+    pub fn selector(&self) -> &str {
+        &self.selector
+    }
+
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    pub fn algorithm(&self) -> DkimAlgorithm {
+        self.algorithm
+    }
+
+    /// Name of the DNS TXT record holding the public key.
+    pub fn dns_name(&self) -> String {
+        format!("{}._domainkey.{}", self.selector, self.domain)
+    }
+
+    /// Value of the DNS TXT record holding the public key.
+    pub fn dns_record(&self) -> String {
+        let key_type = match self.algorithm {
+            DkimAlgorithm::Rsa => "rsa",
+            DkimAlgorithm::Ed25519 => "ed25519",
+        };
+
+        format!("v=DKIM1; k={key_type}; p={}", self.public_key)
+    }
+
+    /// Add a DKIM-Signature header to `message`. The message must not change afterwards.
+    pub fn sign(&self, message: &mut Message) {
+        message.sign(&self.config);
+    }
+}
+
+// The private key must not end up in the logs
+impl Debug for Dkim {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Dkim")
+            .field("selector", &self.selector)
+            .field("domain", &self.domain)
+            .field("algorithm", &self.algorithm)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Check the first DKIM signature of the formatted `message` against the public key in
+/// `dns_record`, as returned by [`Dkim::dns_record`]. Meant for tests, the key isn't looked
+/// up in DNS.
+pub fn verify(message: &[u8], dns_record: &str) -> Result<()> {
+    let record = tags(dns_record);
+    let key_type = record
+        .iter()
+        .find(|(name, _)| *name == "k")
+        .map_or("rsa", |(_, value)| value);
+    let public_key = BASE64.decode(
+        record
+            .iter()
+            .find(|(name, _)| *name == "p")
+            .map(|(_, value)| value.as_str())
+            .context("DKIM record has no public key")?,
+    )?;
+
+    let split = message
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .context("Message has no body")?;
+    let headers = fields(std::str::from_utf8(&message[..split + 2])?);
+    let body = &message[split + 4..];
+
+    let (signature_field, signature_value) = headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("DKIM-Signature"))
+        .context("Message has no DKIM-Signature header")?;
+    let signature = tags(signature_value);
+    let tag = |name: &str| {
+        signature
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+            .with_context(|| format!("DKIM-Signature has no {name}= tag"))
+    };
+
+    let expected_algorithm = format!("{key_type}-sha256");
+    if tag("a")? != expected_algorithm {
+        bail!(
+            "DKIM-Signature is {}, but the key is for {expected_algorithm}",
+            tag("a")?
+        );
+    }
+
+    let canonicalization = tag("c").unwrap_or("simple");
+    let (header_canonicalization, body_canonicalization) = canonicalization
+        .split_once('/')
+        .unwrap_or((canonicalization, "simple"));
+    let relaxed_headers = is_relaxed(header_canonicalization)?;
+    let relaxed_body = is_relaxed(body_canonicalization)?;
+
+    let body_hash = BASE64.encode(Sha256::digest(canonical_body(body, relaxed_body)));
+    if body_hash != tag("bh")? {
+        bail!("DKIM body hash doesn't match, the body was altered");
+    }
+
+    // Each name in h= stands for the last instance of that header not signed yet
+    let mut data = String::new();
+    let mut used = vec![false; headers.len()];
+    for name in tag("h")?.split(':') {
+        let name = name.trim();
+        if let Some(index) = (0..headers.len())
+            .rev()
+            .find(|&index| !used[index] && headers[index].0.eq_ignore_ascii_case(name))
+        {
+            used[index] = true;
+            data.push_str(&canonical_header(&headers[index], relaxed_headers));
+        }
+    }
+
+    let unsigned = (
+        signature_field.clone(),
+        SIGNATURE_TAG.replace(signature_value, "$1$2").into_owned(),
+    );
+    data.push_str(canonical_header(&unsigned, relaxed_headers).trim_end_matches("\r\n"));
+
+    let hash = Sha256::digest(data.as_bytes());
+    let signature = BASE64.decode(tag("b")?)?;
+
+    let verified = match key_type {
+        "rsa" => RsaPublicKey::from_public_key_der(&public_key)?
+            .verify(Pkcs1v15Sign::new::<Sha256>(), &hash, &signature)
+            .is_ok(),
+        "ed25519" => VerifyingKey::from_bytes(
+            &public_key
+                .try_into()
+                .ok()
+                .context("Ed25519 public key isn't 32 bytes")?,
+        )?
+        .verify(&hash, &Signature::from_slice(&signature)?)
+        .is_ok(),
+        _ => bail!("Unknown DKIM key type {key_type}"),
+    };
+
+    if !verified {
+        bail!("DKIM signature doesn't match, the headers were altered or the key is wrong");
+    }
+
+    Ok(())
+}
+
+/// The `name=value` tags of a DKIM-Signature header or DNS record, without whitespace.
+fn tags(value: &str) -> Vec<(String, String)> {
+    value
+        .split(';')
+        .filter_map(|tag| tag.split_once('='))
+        .map(|(name, value)| {
+            (
+                name.trim().to_owned(),
+                value.chars().filter(|c| !c.is_whitespace()).collect(),
+            )
+        })
+        .collect()
+}
+
+/// The header fields of a message head, as names and raw values with their folding.
+fn fields(head: &str) -> Vec<(String, String)> {
+    let mut fields: Vec<(String, String)> = Vec::new();
+
+    for line in head.split_terminator("\r\n") {
+        match fields.last_mut() {
+            Some((_, value)) if line.starts_with([' ', '\t']) => {
+                value.push_str("\r\n");
+                value.push_str(line);
+            }
+            _ => {
+                let (name, value) = line.split_once(':').unwrap_or((line, ""));
+                fields.push((name.to_owned(), value.to_owned()));
+            }
+        }
+    }
+
+    fields
+}
+
+fn is_relaxed(canonicalization: &str) -> Result<bool> {
+    match canonicalization {
+        "relaxed" => Ok(true),
+        "simple" => Ok(false),
+        _ => bail!("Unknown DKIM canonicalization {canonicalization}"),
+    }
+}
+
+fn canonical_header((name, value): &(String, String), relaxed: bool) -> String {
+    if !relaxed {
+        return format!("{name}:{value}\r\n");
+    }
+
+    let value = value.replace("\r\n", "");
+    let value = value.split([' ', '\t']).filter(|word| !word.is_empty());
+
+    format!(
+        "{}:{}\r\n",
+        name.trim().to_ascii_lowercase(),
+        value.collect::<Vec<_>>().join(" ")
+    )
+}
+
+fn canonical_body(body: &[u8], relaxed: bool) -> Vec<u8> {
+    let mut lines: Vec<Vec<u8>> = body
+        .split(|&byte| byte == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line).to_vec())
+        .collect();
+
+    if relaxed {
+        for line in &mut lines {
+            let mut canonical = Vec::with_capacity(line.len());
+            for &byte in line.iter() {
+                let is_space = matches!(byte, b' ' | b'\t');
+                if !(is_space && canonical.last() == Some(&b' ')) {
+                    canonical.push(if is_space { b' ' } else { byte });
+                }
+            }
+            while canonical.last() == Some(&b' ') {
+                canonical.pop();
+            }
+            *line = canonical;
+        }
+    }
+
+    while lines.last().is_some_and(Vec::is_empty) {
+        lines.pop();
+    }
+
+    // An empty body is a single line break in simple canonicalization, and nothing in
+    // relaxed canonicalization
+    if lines.is_empty() {
+        return if relaxed {
+            Vec::new()
+        } else {
+            b"\r\n".to_vec()
+        };
+    }
+
+    let mut canonical = lines.join(&b"\r\n"[..]);
+    canonical.extend_from_slice(b"\r\n");
+    canonical
+}
@@ -0,0 +1,203 @@
+use std::collections::BTreeSet;
+use std::fmt::Display;
+use std::ops::{BitAnd, BitOr, Sub};
+
+use color_eyre::eyre::Result;
+
+use crate::client::{Client, ClientRef, Group, GroupMembers};
+use crate::db::DB;
+
+/// Who an email is sent to. Besides a single client or group, receivers can be combined
+/// with `|` (union), `&` (intersection) and `-` (exclusion), e.g. `(a | b) - unsubscribed`.
+pub enum Receiver {
+    Client(Client),
+    Group(Group),
+    Union(Vec<Receiver>),
+    Intersection(Vec<Receiver>),
+    Exclusion(Box<Receiver>, Box<Receiver>),
+}
+
+impl Receiver {
+    pub fn union<I: IntoIterator<Item = Receiver>>(receivers: I) -> Self {
+        Self::Union(receivers.into_iter().collect())
+    }
+
+    pub fn intersection<I: IntoIterator<Item = Receiver>>(receivers: I) -> Self {
+        Self::Intersection(receivers.into_iter().collect())
+    }
+
+    pub fn exclusion(receiver: Receiver, excluded: Receiver) -> Self {
+        Self::Exclusion(Box::new(receiver), Box::new(excluded))
+    }
+
+    /// Resolve the expression into the deduplicated set of clients it designates.
+    pub async fn resolve(&self, db: &DB) -> Result<Vec<ClientRef>> {
+        let ids = self.resolve_ids(db).await?;
+
+        Ok(ids.into_iter().map(ClientRef::new).collect())
+    }
+
+    /// Stream the clients designated by the expression, `page_size` clients at a time.
+    pub async fn recipients(&self, page_size: usize, db: &DB) -> Result<Recipients<'_>> {
+        let source = match self {
+            Self::Group(group) => RecipientsSource::Group(group.members(page_size)),
+            receiver => {
+                let ids = receiver.resolve_ids(db).await?;
+                RecipientsSource::Ids(Vec::from_iter(ids).into_iter())
+            }
+        };
+
+        Ok(Recipients { page_size, source })
+    }
+
+    /// Resolve the expression into the set of IDs of the clients it designates.
+    pub async fn resolve_ids(&self, db: &DB) -> Result<BTreeSet<String>> {
+        let ids = match self {
+            Self::Client(client) => BTreeSet::from([client.id().to_owned()]),
+            Self::Group(group) => group.query_client_ids(db).await?,
+            Self::Union(receivers) => {
+                let mut ids = BTreeSet::new();
+
+                for receiver in receivers {
+                    ids.append(&mut Box::pin(receiver.resolve_ids(db)).await?);
+                }
+
+                ids
+            }
+            Self::Intersection(receivers) => {
+                let mut receivers = receivers.iter();
+
+                let mut ids = match receivers.next() {
+                    Some(receiver) => Box::pin(receiver.resolve_ids(db)).await?,
+                    None => BTreeSet::new(),
+                };
+
+                for receiver in receivers {
+                    let other = Box::pin(receiver.resolve_ids(db)).await?;
+                    ids.retain(|id| other.contains(id));
+                }
+
+                ids
+            }
+            Self::Exclusion(receiver, excluded) => {
+                let mut ids = Box::pin(receiver.resolve_ids(db)).await?;
+                let excluded = Box::pin(excluded.resolve_ids(db)).await?;
+
+                ids.retain(|id| !excluded.contains(id));
+
+                ids
+            }
+        };
+
+        Ok(ids)
+    }
+}
+
+/// Pages of the clients designated by a [`Receiver`].
+///
+/// A lone group is streamed straight from the database. Other expressions need their
+/// member IDs to be deduplicated first, but the clients are still only loaded page by page.
+pub struct Recipients<'r> {
+    page_size: usize,
+    source: RecipientsSource<'r>,
+}
+
+enum RecipientsSource<'r> {
+    Group(GroupMembers<'r>),
+    Ids(std::vec::IntoIter<String>),
+}
+
+impl Recipients<'_> {
+    pub async fn next_page(&mut self, db: &DB) -> Result<Option<Vec<Client>>> {
+        match &mut self.source {
+            RecipientsSource::Group(members) => members.next_page(db).await,
+            RecipientsSource::Ids(ids) => {
+                let page = ids.by_ref().take(self.page_size).collect::<Vec<_>>();
+
+                if page.is_empty() {
+                    return Ok(None);
+                }
+
+                let clients = Client::get_many(page.into_iter(), db).await?;
+
+                Ok(Some(clients.into_iter().flatten().collect()))
+            }
+        }
+    }
+}
+
+/// Textual form of the expression, as recorded in the send log.
+impl Display for Receiver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn write_list(
+            f: &mut std::fmt::Formatter<'_>,
+            receivers: &[Receiver],
+            operator: &str,
+        ) -> std::fmt::Result {
+            f.write_str("(")?;
+            for (i, receiver) in receivers.iter().enumerate() {
+                if i > 0 {
+                    write!(f, " {operator} ")?;
+                }
+                write!(f, "{receiver}")?;
+            }
+            f.write_str(")")
+        }
+
+        match self {
+            Self::Client(client) => write!(f, "client:{}", client.id()),
+            Self::Group(group) => write!(f, "group:{}", group.id()),
+            Self::Union(receivers) => write_list(f, receivers, "|"),
+            Self::Intersection(receivers) => write_list(f, receivers, "&"),
+            Self::Exclusion(receiver, excluded) => write!(f, "({receiver} - {excluded})"),
+        }
+    }
+}
+
+impl From<Client> for Receiver {
+    fn from(value: Client) -> Self {
+        Self::Client(value)
+    }
+}
+
+impl From<Group> for Receiver {
+    fn from(value: Group) -> Self {
+        Self::Group(value)
+    }
+}
+
+impl<R: Into<Receiver>> BitOr<R> for Receiver {
+    type Output = Receiver;
+
+    fn bitor(self, rhs: R) -> Self::Output {
+        match self {
+            Self::Union(mut receivers) => {
+                receivers.push(rhs.into());
+                Self::Union(receivers)
+            }
+            receiver => Self::Union(vec![receiver, rhs.into()]),
+        }
+    }
+}
+
+impl<R: Into<Receiver>> BitAnd<R> for Receiver {
+    type Output = Receiver;
+
+    fn bitand(self, rhs: R) -> Self::Output {
+        match self {
+            Self::Intersection(mut receivers) => {
+                receivers.push(rhs.into());
+                Self::Intersection(receivers)
+            }
+            receiver => Self::Intersection(vec![receiver, rhs.into()]),
+        }
+    }
+}
+
+impl<R: Into<Receiver>> Sub<R> for Receiver {
+    type Output = Receiver;
+
+    fn sub(self, rhs: R) -> Self::Output {
+        Self::exclusion(self, rhs.into())
+    }
+}
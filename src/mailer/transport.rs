@@ -0,0 +1,25 @@
+use color_eyre::eyre::Result;
+use lettre::Message;
+
+use crate::sender::SmtpCredentials;
+
+mod file;
+mod memory;
+mod smtp;
+
+pub use file::FileTransport;
+pub use memory::{MemoryTransport, SentMessage};
+pub use smtp::{AuthMechanism, SmtpConfig, SmtpRelay, TlsMode};
+
+/// Where the [`Mailer`](super::Mailer) hands the messages it sends.
+pub trait MailTransport: Send + Sync {
+    /// Send `message`, logged in with `credentials` instead of the default ones when the
+    /// email is sent as a [`Sender`](crate::sender::Sender) with its own. Transports that
+    /// don't log in ignore them.
+    fn send(&self, message: &Message, credentials: Option<&SmtpCredentials>) -> Result<()>;
+
+    /// Check that messages can be sent, before sending any.
+    fn test_connection(&self) -> Result<()> {
+        Ok(())
+    }
+}
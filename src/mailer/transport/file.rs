@@ -0,0 +1,85 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{Context, Result};
+use cuid2::create_id;
+use lettre::Message;
+
+use super::MailTransport;
+use crate::mailer::now;
+use crate::sender::SmtpCredentials;
+
+/// Writing the messages to files instead of sending them, to look at them in a mail client.
+pub struct FileTransport {
+    dir: PathBuf,
+    maildir: bool,
+}
+
+impl FileTransport {
+    /// Write each message to an `.eml` file in `dir`.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            maildir: false,
+        }
+    }
+
+    /// Deliver each message to the Maildir `dir`, as new mail. The `tmp`, `new` and `cur`
+    /// directories are created as needed.
+    pub fn maildir(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            maildir: true,
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn create_dirs(&self) -> Result<()> {
+        let dirs = if self.maildir {
+            vec![
+                self.dir.join("tmp"),
+                self.dir.join("new"),
+                self.dir.join("cur"),
+            ]
+        } else {
+            vec![self.dir.clone()]
+        };
+
+        for dir in dirs {
+            fs::create_dir_all(&dir)
+                .with_context(|| format!("Creating directory {}", dir.display()))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl MailTransport for FileTransport {
+    fn send(&self, message: &Message, _credentials: Option<&SmtpCredentials>) -> Result<()> {
+        self.create_dirs()?;
+
+        // Names sort in the order the messages were sent, and never collide
+        let name = format!("{}.{}", now(), create_id());
+
+        if self.maildir {
+            // A message only appears in `new` once fully written
+            let tmp = self.dir.join("tmp").join(&name);
+            fs::write(&tmp, message.formatted())
+                .with_context(|| format!("Writing message to {}", tmp.display()))?;
+            fs::rename(&tmp, self.dir.join("new").join(&name))?;
+        } else {
+            let path = self.dir.join(format!("{name}.eml"));
+            fs::write(&path, message.formatted())
+                .with_context(|| format!("Writing message to {}", path.display()))?;
+        }
+
+        Ok(())
+    }
+
+    fn test_connection(&self) -> Result<()> {
+        self.create_dirs()
+    }
+}
@@ -0,0 +1,82 @@
+use std::sync::{Arc, Mutex};
+
+use color_eyre::eyre::Result;
+use lettre::Message;
+
+use super::MailTransport;
+use crate::sender::SmtpCredentials;
+
+/// Keeping the messages in memory instead of sending them, for tests. Clones share the
+/// same messages, so a clone can be kept to look at what a mailer sent.
+#[derive(Clone, Default)]
+pub struct MemoryTransport {
+    messages: Arc<Mutex<Vec<SentMessage>>>,
+}
+
+/// A message kept by a [`MemoryTransport`].
+#[derive(Debug, Clone)]
+pub struct SentMessage {
+    recipients: Vec<String>,
+    username: Option<String>,
+    formatted: Vec<u8>,
+}
+
+impl MemoryTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The messages sent so far, in order.
+    pub fn messages(&self) -> Vec<SentMessage> {
+        // Unwrap is safe because the lock is never held across a panic
+        self.messages.lock().unwrap().clone()
+    }
+
+    /// Forget the messages sent so far.
+    pub fn clear(&self) {
+        self.messages.lock().unwrap().clear();
+    }
+}
+
+impl MailTransport for MemoryTransport {
+    fn send(&self, message: &Message, credentials: Option<&SmtpCredentials>) -> Result<()> {
+        let sent = SentMessage {
+            recipients: message
+                .envelope()
+                .to()
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+            username: credentials.map(|credentials| credentials.username().to_owned()),
+            formatted: message.formatted(),
+        };
+
+        self.messages.lock().unwrap().push(sent);
+
+        Ok(())
+    }
+}
+
+impl SentMessage {
+    /// Adresses of the envelope, including the Cc and Bcc ones.
+    pub fn recipients(&self) -> &[String] {
+        &self.recipients
+    }
+
+    /// Username of the credentials the message would have been sent with, if not the
+    /// default ones.
+    pub fn username(&self) -> Option<&str> {
+        self.username.as_deref()
+    }
+
+    /// The message, as it would have been sent.
+    pub fn formatted(&self) -> &[u8] {
+        &self.formatted
+    }
+
+    /// The message as text, for assertions.
+    pub fn as_str(&self) -> &str {
+        // Formatted messages are ASCII, their non-ASCII content being encoded
+        std::str::from_utf8(&self.formatted).unwrap_or_default()
+    }
+}
@@ -0,0 +1,355 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use color_eyre::eyre::{bail, Result};
+use lettre::transport::smtp::authentication::Mechanism;
+use lettre::transport::smtp::client::{Tls, TlsParameters};
+use lettre::transport::smtp::extension::ClientId;
+use lettre::transport::smtp::PoolConfig;
+use lettre::{Message, SmtpTransport, Transport};
+use tracing::{debug, warn};
+
+use super::MailTransport;
+use crate::sender::SmtpCredentials;
+
+/// The relay used when `SMTP_HOST` is missing, which was the only one supported before the
+/// relay could be configured.
+const DEPRECATED_DEFAULT_HOST: &str = "smtp.gmail.com";
+
+/// How the connection to the SMTP relay is encrypted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsMode {
+    /// TLS from the start of the connection, usually on port 465
+    Implicit,
+    /// Plain connection upgraded with STARTTLS, which the relay must support, usually on
+    /// port 587
+    StartTls,
+    /// No encryption, for local relays and test sinks only
+    None,
+}
+
+/// How to log in to the SMTP relay, when it offers several mechanisms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthMechanism {
+    Plain,
+    Login,
+    Xoauth2,
+}
+
+/// Settings of the connection to the SMTP relay.
+///
+/// ```ignore
+/// let config = SmtpConfig::new("smtp.example.com")
+///     .tls(TlsMode::StartTls)
+///     .credentials(SmtpCredentials::new("bureau", "secret"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct SmtpConfig {
+    host: String,
+    port: Option<u16>,
+    tls: TlsMode,
+    credentials: Option<SmtpCredentials>,
+    auth_mechanism: Option<AuthMechanism>,
+    pool_size: u32,
+    pool_idle_timeout: Duration,
+    timeout: Option<Duration>,
+    helo_name: Option<String>,
+}
+
+/// Sending through an SMTP relay.
+pub struct SmtpRelay {
+    config: SmtpConfig,
+    transport: SmtpTransport,
+    /// Transports logged in with the credentials of a sender, by username, along with the
+    /// password they were logged in with
+    sender_transports: Mutex<HashMap<String, (String, SmtpTransport)>>,
+}
+
+impl FromStr for TlsMode {
+    type Err = color_eyre::eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "implicit" => Ok(Self::Implicit),
+            "starttls" => Ok(Self::StartTls),
+            "none" => Ok(Self::None),
+            _ => bail!("Unknown TLS mode {s}, expected implicit, starttls or none"),
+        }
+    }
+}
+
+impl FromStr for AuthMechanism {
+    type Err = color_eyre::eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "plain" => Ok(Self::Plain),
+            "login" => Ok(Self::Login),
+            "xoauth2" => Ok(Self::Xoauth2),
+            _ => bail!("Unknown SMTP auth mechanism {s}, expected plain, login or xoauth2"),
+        }
+    }
+}
+
+impl From<AuthMechanism> for Mechanism {
+    fn from(value: AuthMechanism) -> Self {
+        match value {
+            AuthMechanism::Plain => Self::Plain,
+            AuthMechanism::Login => Self::Login,
+            AuthMechanism::Xoauth2 => Self::Xoauth2,
+        }
+    }
+}
+
+impl SmtpConfig {
+    /// Send through the relay at `host` with STARTTLS, without logging in.
+    pub fn new(host: &str) -> Self {
+        Self {
+            host: host.to_owned(),
+            port: None,
+            tls: TlsMode::StartTls,
+            credentials: None,
+            auth_mechanism: None,
+            pool_size: 10,
+            pool_idle_timeout: Duration::from_secs(60),
+            timeout: Some(Duration::from_secs(60)),
+            helo_name: None,
+        }
+    }
+
+    /// The configuration in the environment:
+    ///
+    /// - `SMTP_HOST`. Deprecated default: `smtp.gmail.com` with implicit TLS, with a warning
+    /// - `SMTP_PORT`, by default the usual port of the TLS mode
+    /// - `SMTP_TLS`: `implicit`, `starttls` (default) or `none`
+    /// - `SMTP_USERNAME` and `SMTP_PASSWORD`, to log in
+    /// - `SMTP_AUTH`: `plain`, `login` or `xoauth2`, any offered by the relay by default
+    /// - `SMTP_POOL_SIZE`, the most connections kept open at once
+    /// - `SMTP_POOL_IDLE_TIMEOUT`, in seconds, before an unused connection is closed
+    /// - `SMTP_TIMEOUT`, in seconds, for each command sent to the relay
+    /// - `SMTP_HELO_NAME`, the name the client introduces itself with, the hostname by
+    ///   default
+    pub fn from_env() -> Result<Self> {
+        let mut config = match env::<String>("SMTP_HOST")? {
+            Some(host) => Self::new(&host),
+            None => {
+                warn!("SMTP_HOST is missing, sending through {DEPRECATED_DEFAULT_HOST}. This default is deprecated and will be removed, set SMTP_HOST");
+                Self::new(DEPRECATED_DEFAULT_HOST).tls(TlsMode::Implicit)
+            }
+        };
+
+        if let Some(port) = env("SMTP_PORT")? {
+            config = config.port(port);
+        }
+        if let Some(tls) = env("SMTP_TLS")? {
+            config = config.tls(tls);
+        }
+        match (
+            env::<String>("SMTP_USERNAME")?,
+            env::<String>("SMTP_PASSWORD")?,
+        ) {
+            (Some(username), Some(password)) => {
+                config = config.credentials(SmtpCredentials::new(&username, &password));
+            }
+            (None, None) => {}
+            _ => bail!("SMTP_USERNAME and SMTP_PASSWORD must be set together"),
+        }
+        if let Some(auth_mechanism) = env("SMTP_AUTH")? {
+            config = config.auth_mechanism(auth_mechanism);
+        }
+        if let Some(pool_size) = env("SMTP_POOL_SIZE")? {
+            config = config.pool_size(pool_size);
+        }
+        if let Some(seconds) = env("SMTP_POOL_IDLE_TIMEOUT")? {
+            config = config.pool_idle_timeout(Duration::from_secs(seconds));
+        }
+        if let Some(seconds) = env("SMTP_TIMEOUT")? {
+            config = config.timeout(Some(Duration::from_secs(seconds)));
+        }
+        if let Some(helo_name) = env::<String>("SMTP_HELO_NAME")? {
+            config = config.helo_name(&helo_name);
+        }
+
+        Ok(config)
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    pub fn tls(mut self, tls: TlsMode) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    pub fn credentials(mut self, credentials: SmtpCredentials) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    pub fn auth_mechanism(mut self, auth_mechanism: AuthMechanism) -> Self {
+        self.auth_mechanism = Some(auth_mechanism);
+        self
+    }
+
+    /// Most connections to the relay kept open at once. Defaults to 10.
+    pub fn pool_size(mut self, pool_size: u32) -> Self {
+        self.pool_size = pool_size;
+        self
+    }
+
+    /// Time before an unused connection is closed. Defaults to a minute.
+    pub fn pool_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.pool_idle_timeout = timeout;
+        self
+    }
+
+    /// Time to wait for the relay to answer each command, `None` to wait forever.
+    /// Defaults to a minute.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Name the client introduces itself with, in the `EHLO` command.
+    pub fn helo_name(mut self, helo_name: &str) -> Self {
+        self.helo_name = Some(helo_name.to_owned());
+        self
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// The port set, or else the usual port of the TLS mode.
+    pub fn port_or_default(&self) -> u16 {
+        self.port.unwrap_or(match self.tls {
+            TlsMode::Implicit => 465,
+            TlsMode::StartTls => 587,
+            TlsMode::None => 25,
+        })
+    }
+
+    pub fn tls_mode(&self) -> TlsMode {
+        self.tls
+    }
+
+    /// A transport to the relay, logged in with `credentials`.
+    fn transport(&self, credentials: Option<&SmtpCredentials>) -> Result<SmtpTransport> {
+        let tls = match self.tls {
+            TlsMode::Implicit => Tls::Wrapper(TlsParameters::new(self.host.clone())?),
+            TlsMode::StartTls => Tls::Required(TlsParameters::new(self.host.clone())?),
+            TlsMode::None => Tls::None,
+        };
+
+        let mut builder = SmtpTransport::builder_dangerous(&self.host)
+            .port(self.port_or_default())
+            .tls(tls)
+            .timeout(self.timeout)
+            .pool_config(
+                PoolConfig::new()
+                    .max_size(self.pool_size)
+                    .idle_timeout(self.pool_idle_timeout),
+            );
+
+        if let Some(credentials) = credentials {
+            builder = builder.credentials(credentials.into());
+        }
+        if let Some(auth_mechanism) = self.auth_mechanism {
+            builder = builder.authentication(vec![auth_mechanism.into()]);
+        }
+        if let Some(helo_name) = &self.helo_name {
+            builder = builder.hello_name(ClientId::Domain(helo_name.clone()));
+        }
+
+        Ok(builder.build())
+    }
+}
+
+/// The variable `name` of the environment parsed, `None` when unset.
+fn env<T>(name: &str) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let Ok(value) = dotenvy::var(name) else {
+        return Ok(None);
+    };
+
+    match value.parse() {
+        Ok(value) => Ok(Some(value)),
+        Err(err) => bail!("Invalid {name} {value:?}: {err}"),
+    }
+}
+
+impl SmtpRelay {
+    /// Send through the relay described by `config`. Nothing is sent to the relay until
+    /// the first message or connection test.
+    pub fn new(config: SmtpConfig) -> Result<Self> {
+        Ok(Self {
+            transport: config.transport(config.credentials.as_ref())?,
+            config,
+            sender_transports: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Send through the relay configured in the environment, see [`SmtpConfig::from_env`].
+    pub fn from_env() -> Result<Self> {
+        Self::new(SmtpConfig::from_env()?)
+    }
+
+    pub fn config(&self) -> &SmtpConfig {
+        &self.config
+    }
+
+    /// The transport logged in with `credentials`, created on first use.
+    fn sender_transport(&self, credentials: &SmtpCredentials) -> Result<SmtpTransport> {
+        // Unwrap is safe because the lock is never held across a panic
+        let mut transports = self.sender_transports.lock().unwrap();
+
+        if let Some((password, transport)) = transports.get(credentials.username()) {
+            if password == credentials.password() {
+                return Ok(transport.clone());
+            }
+        }
+
+        debug!("Log in to the SMTP relay as {}", credentials.username());
+
+        let transport = self.config.transport(Some(credentials))?;
+        transports.insert(
+            credentials.username().to_owned(),
+            (credentials.password().to_owned(), transport.clone()),
+        );
+
+        Ok(transport)
+    }
+}
+
+impl MailTransport for SmtpRelay {
+    fn send(&self, message: &Message, credentials: Option<&SmtpCredentials>) -> Result<()> {
+        let transport = match credentials {
+            Some(credentials) => self.sender_transport(credentials)?,
+            None => self.transport.clone(),
+        };
+
+        transport.send(message)?;
+
+        Ok(())
+    }
+
+    fn test_connection(&self) -> Result<()> {
+        if !self.transport.test_connection()? {
+            bail!(
+                "Can't connect to the SMTP relay {}:{}",
+                self.config.host,
+                self.config.port_or_default()
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -1,5 +1,3 @@
-#![feature(async_closure)]
-
 use std::sync::Arc;
 
 use chrono::{Local, TimeDelta, Timelike};
@@ -1,11 +1,15 @@
+use std::num::NonZero;
 use std::{future::Future, sync::Arc};
 
-use color_eyre::eyre::ContextCompat;
+use chrono::{DateTime, Datelike, Local, TimeDelta, Timelike};
+use color_eyre::eyre::{bail, ContextCompat, Result};
+use tokio::sync::Mutex;
 use tokio::task::JoinHandle;
-use tracing::debug;
-use trigger::Trigger;
+use tracing::{debug, error};
+use trigger::{DatetimeTrigger, Day, Month, NaiveTime, PartialDate, Trigger};
 
-use crate::mailer::Mailer;
+use crate::email::Email;
+use crate::mailer::{AbTest, AbTestResult, Mailer, Receiver};
 
 pub mod trigger;
 
@@ -57,4 +61,80 @@ impl Scheduler {
         self.tasks.push(trigger);
         self.actions.push(action);
     }
+
+    /// Send `email` to `receiver` each time `trigger` fires. The generation of the trigger is
+    /// available to templates as `generation`.
+    pub fn schedule_email(&mut self, trigger: Trigger, email: Email, receiver: Receiver) {
+        let email = Arc::new(email);
+        let receiver = Arc::new(receiver);
+
+        self.register_trigger_with_action(trigger, move |generation, mailer| {
+            let email = email.clone();
+            let receiver = receiver.clone();
+
+            async move {
+                if let Err(err) = mailer.send_generation(&email, &receiver, generation).await {
+                    error!(
+                        "Scheduled sending of email {} failed at generation {generation}: {err}",
+                        email.id()
+                    );
+                }
+            }
+        });
+    }
+
+    /// Send the winner of `ab_test` to its remainder once the remainder delay has passed,
+    /// the winner being chosen by `pick_winner` from the results of the sample.
+    pub fn schedule_ab_test_remainder(
+        &mut self,
+        ab_test: AbTest,
+        pick_winner: impl Fn(&[AbTestResult]) -> Option<String> + Send + Sync + 'static,
+    ) -> Result<()> {
+        let Some(remainder_timestamp) = ab_test.remainder_timestamp() else {
+            bail!(
+                "A/B test {} has no remainder delay or wasn't sent yet",
+                ab_test.id()
+            );
+        };
+
+        // A trigger only fires in the future, so a delay that already passed fires right away
+        let at = DateTime::from_timestamp(remainder_timestamp as i64, 0)
+            .context("Remainder timestamp out of range")?
+            .with_timezone(&Local)
+            .max(Local::now() + TimeDelta::seconds(1));
+        let trigger = DatetimeTrigger::new(
+            PartialDate::new_ymd(
+                at.year() as u32,
+                Month::from_ordinal(at.month()),
+                // Unwrap is safe because days of the month start at 1
+                Day::Ordinal(NonZero::new(at.day()).unwrap()),
+            ),
+            NaiveTime::from_hms_opt(at.hour(), at.minute(), at.second())
+                .context("Invalid remainder time")?,
+        );
+
+        let ab_test = Arc::new(Mutex::new(ab_test));
+        let pick_winner = Arc::new(pick_winner);
+
+        self.register_trigger_with_action(trigger.into(), move |_, mailer| {
+            let ab_test = ab_test.clone();
+            let pick_winner = pick_winner.clone();
+
+            async move {
+                let mut ab_test = ab_test.lock().await;
+
+                if let Err(err) = mailer
+                    .finish_ab_test(&mut ab_test, |results| pick_winner(results))
+                    .await
+                {
+                    error!(
+                        "Scheduled sending of the remainder of A/B test {} failed: {err}",
+                        ab_test.id()
+                    );
+                }
+            }
+        });
+
+        Ok(())
+    }
 }
@@ -0,0 +1,385 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+use color_eyre::eyre::{Context, Result};
+use cuid2::create_id;
+use email_address::EmailAddress;
+use lettre::message::Mailbox;
+use rusqlite::{params_from_iter, Connection, Row};
+
+use crate::db::DB;
+
+/// Someone emails are sent as, a person such as "Tarak <tarak@example.com>" or a role such
+/// as "Bureau <bureau@example.com>".
+#[derive(Debug, Clone)]
+pub struct Sender {
+    id: String,
+    name: Option<String>,
+    adresse: EmailAddress,
+    /// Where replies go, instead of the adresse of the sender
+    reply_to: Option<Mailbox>,
+    /// HTML appended to the body of the emails sent as the sender
+    signature: Option<String>,
+    /// How to log in to the SMTP relay as the sender, instead of with the default credentials
+    smtp_login: Option<SmtpLogin>,
+}
+
+/// Username to log in to the SMTP relay with, and the environment variable holding its
+/// password, so that the password isn't kept in the database.
+#[derive(Debug, Clone)]
+pub struct SmtpLogin {
+    username: String,
+    password_env: String,
+}
+
+impl SmtpLogin {
+    pub fn new(username: &str, password_env: &str) -> Self {
+        Self {
+            username: username.to_owned(),
+            password_env: password_env.to_owned(),
+        }
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    /// Name of the environment variable holding the password.
+    pub fn password_env(&self) -> &str {
+        &self.password_env
+    }
+
+    /// The credentials, with the password read from the environment.
+    pub fn credentials(&self) -> Result<SmtpCredentials> {
+        let password = dotenvy::var(&self.password_env).with_context(|| {
+            format!(
+                "Reading the SMTP password of {} from {}",
+                self.username, self.password_env
+            )
+        })?;
+
+        Ok(SmtpCredentials::new(&self.username, &password))
+    }
+}
+
+/// Username and password to log in to the SMTP relay.
+#[derive(Clone)]
+pub struct SmtpCredentials {
+    username: String,
+    password: String,
+}
+
+impl SmtpCredentials {
+    pub fn new(username: &str, password: &str) -> Self {
+        Self {
+            username: username.to_owned(),
+            password: password.to_owned(),
+        }
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn password(&self) -> &str {
+        &self.password
+    }
+}
+
+// The password must not end up in the logs
+impl Debug for SmtpCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SmtpCredentials")
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+impl From<&SmtpCredentials> for lettre::transport::smtp::authentication::Credentials {
+    fn from(value: &SmtpCredentials) -> Self {
+        Self::new(value.username.clone(), value.password.clone())
+    }
+}
+
+impl Sender {
+    pub(crate) const CREATE_TABLES: &'static str = r#"
+        CREATE TABLE IF NOT EXISTS Sender (
+            ID                 TEXT PRIMARY KEY,
+            name               TEXT,
+            adresse            TEXT NOT NULL,
+            reply_to           TEXT,
+            signature          TEXT,
+            smtp_username      TEXT,
+            smtp_password_env  TEXT,
+            CHECK ((smtp_username IS NULL) = (smtp_password_env IS NULL))
+        ) STRICT;
+    "#;
+
+    const SQL_COLUMNS: &'static str =
+        "ID, name, adresse, reply_to, signature, smtp_username, smtp_password_env";
+
+    /// A sender with a display `name`, such as "Tarak", and an `adresse`.
+    pub async fn create(name: Option<&str>, adresse: &str, db: &DB) -> Result<Self> {
+        let this = Self {
+            id: create_id(),
+            name: name.map(str::to_owned),
+            adresse: adresse.parse()?,
+            reply_to: None,
+            signature: None,
+            smtp_login: None,
+        };
+
+        db.connection(|conn| {
+            conn.execute(
+                &format!(
+                    "INSERT INTO Sender ({}) VALUES (?, ?, ?, ?, ?, ?, ?)",
+                    Self::SQL_COLUMNS
+                ),
+                (
+                    &this.id,
+                    &this.name,
+                    this.adresse.as_str(),
+                    None::<String>,
+                    None::<String>,
+                    None::<String>,
+                    None::<String>,
+                ),
+            )?;
+
+            Ok(())
+        })
+        .await?;
+
+        Ok(this)
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn adresse(&self) -> &str {
+        self.adresse.as_str()
+    }
+
+    /// The display name and adresse, as written in the From header.
+    pub fn mailbox(&self) -> Result<Mailbox> {
+        Ok(Mailbox::new(
+            self.name.clone(),
+            self.adresse.as_str().parse()?,
+        ))
+    }
+
+    pub fn reply_to(&self) -> Option<&Mailbox> {
+        self.reply_to.as_ref()
+    }
+
+    pub fn signature(&self) -> Option<&str> {
+        self.signature.as_deref()
+    }
+
+    pub fn smtp_login(&self) -> Option<&SmtpLogin> {
+        self.smtp_login.as_ref()
+    }
+
+    /// The credentials to log in to the SMTP relay as the sender, with the password read
+    /// from the environment. `None` to use the default ones.
+    pub fn credentials(&self) -> Result<Option<SmtpCredentials>> {
+        self.smtp_login
+            .as_ref()
+            .map(SmtpLogin::credentials)
+            .transpose()
+    }
+
+    pub async fn set_name(&mut self, name: Option<&str>, db: &DB) -> Result<()> {
+        db.connection(|conn| {
+            conn.execute("UPDATE Sender SET name = ? WHERE ID = ?", (name, &self.id))?;
+
+            Ok(())
+        })
+        .await?;
+
+        self.name = name.map(str::to_owned);
+
+        Ok(())
+    }
+
+    /// Change the adresse of the sender, and the sender adresse of its emails.
+    pub async fn set_adresse(&mut self, adresse: &str, db: &DB) -> Result<()> {
+        let adresse: EmailAddress = adresse.parse()?;
+
+        db.connection(|conn| {
+            let tx = conn.unchecked_transaction()?;
+
+            tx.execute(
+                "UPDATE Sender SET adresse = ? WHERE ID = ?",
+                (adresse.as_str(), &self.id),
+            )?;
+            tx.execute(
+                "UPDATE Email SET sender_adresse = ? WHERE sender_ID = ?",
+                (adresse.as_str(), &self.id),
+            )?;
+
+            tx.commit()?;
+
+            Ok(())
+        })
+        .await?;
+
+        self.adresse = adresse;
+
+        Ok(())
+    }
+
+    pub async fn set_reply_to(&mut self, reply_to: Option<&str>, db: &DB) -> Result<()> {
+        let reply_to: Option<Mailbox> = reply_to.map(str::parse).transpose()?;
+
+        db.connection(|conn| {
+            conn.execute(
+                "UPDATE Sender SET reply_to = ? WHERE ID = ?",
+                (reply_to.as_ref().map(ToString::to_string), &self.id),
+            )?;
+
+            Ok(())
+        })
+        .await?;
+
+        self.reply_to = reply_to;
+
+        Ok(())
+    }
+
+    /// Set the HTML signature appended to the emails sent as the sender.
+    pub async fn set_signature(&mut self, signature: Option<&str>, db: &DB) -> Result<()> {
+        db.connection(|conn| {
+            conn.execute(
+                "UPDATE Sender SET signature = ? WHERE ID = ?",
+                (signature, &self.id),
+            )?;
+
+            Ok(())
+        })
+        .await?;
+
+        self.signature = signature.map(str::to_owned);
+
+        Ok(())
+    }
+
+    /// Set how to log in to the SMTP relay as the sender, `None` to use the default
+    /// credentials.
+    pub async fn set_smtp_login(&mut self, smtp_login: Option<SmtpLogin>, db: &DB) -> Result<()> {
+        db.connection(|conn| {
+            conn.execute(
+                "UPDATE Sender SET smtp_username = ?, smtp_password_env = ? WHERE ID = ?",
+                (
+                    smtp_login.as_ref().map(SmtpLogin::username),
+                    smtp_login.as_ref().map(SmtpLogin::password_env),
+                    &self.id,
+                ),
+            )?;
+
+            Ok(())
+        })
+        .await?;
+
+        self.smtp_login = smtp_login;
+
+        Ok(())
+    }
+
+    pub async fn get_one(id: &str, db: &DB) -> Result<Option<Self>> {
+        db.connection(|conn| Self::query_one(id, conn)).await
+    }
+
+    /// Every sender, in creation order.
+    pub async fn list(db: &DB) -> Result<Vec<Self>> {
+        db.connection(|conn| {
+            let mut stmt = conn.prepare_cached(&format!(
+                "SELECT {} FROM Sender ORDER BY rowid",
+                Self::SQL_COLUMNS
+            ))?;
+
+            let rows = stmt.query_and_then([], Self::from_row)?;
+
+            Result::from_iter(rows)
+        })
+        .await
+    }
+
+    /// Delete the sender. Its emails keep being sent from its adresse, without its name,
+    /// signature and SMTP login.
+    pub async fn delete(self, db: &DB) -> Result<()> {
+        db.connection(|conn| {
+            conn.execute("DELETE FROM Sender WHERE ID = ?", [&self.id])?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    pub(crate) fn query_one(id: &str, conn: &Connection) -> Result<Option<Self>> {
+        let mut stmt = conn.prepare_cached(&format!(
+            "SELECT {} FROM Sender WHERE ID = ?",
+            Self::SQL_COLUMNS
+        ))?;
+
+        let mut rows = stmt.query_and_then([id], Self::from_row)?;
+
+        rows.next().transpose()
+    }
+
+    /// The senders among `ids`, by ID.
+    pub(crate) fn query_many(ids: &[&str], conn: &Connection) -> Result<HashMap<String, Self>> {
+        if ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let placeholders = vec!["?"; ids.len()].join(", ");
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM Sender WHERE ID IN ({placeholders})",
+            Self::SQL_COLUMNS
+        ))?;
+
+        let senders = stmt.query_and_then(params_from_iter(ids), Self::from_row)?;
+
+        senders
+            .map(|sender| sender.map(|sender| (sender.id.clone(), sender)))
+            .collect()
+    }
+
+    fn from_row(row: &Row) -> Result<Self> {
+        let id: String = row.get("ID")?;
+
+        let smtp_login = match (
+            row.get::<_, Option<String>>("smtp_username")?,
+            row.get::<_, Option<String>>("smtp_password_env")?,
+        ) {
+            (Some(username), Some(password_env)) => Some(SmtpLogin {
+                username,
+                password_env,
+            }),
+            _ => None,
+        };
+
+        Ok(Self {
+            name: row.get("name")?,
+            adresse: row
+                .get::<_, String>("adresse")?
+                .parse()
+                .with_context(|| format!("Parsing adresse of sender {id}"))?,
+            reply_to: row
+                .get::<_, Option<String>>("reply_to")?
+                .map(|reply_to| reply_to.parse())
+                .transpose()
+                .with_context(|| format!("Parsing reply-to of sender {id}"))?,
+            signature: row.get("signature")?,
+            smtp_login,
+            id,
+        })
+    }
+}
@@ -0,0 +1,479 @@
+use std::sync::LazyLock;
+
+use color_eyre::eyre::Result;
+use cuid2::create_id;
+use regex::Regex;
+use rusqlite::{Connection, OptionalExtension};
+use tracing::debug;
+
+use crate::client::Client;
+use crate::db::DB;
+use crate::email::html::{append_to_body, rewrite_a_href, unescape_attribute};
+use crate::email::Email;
+
+mod server;
+
+pub use server::serve;
+
+/// URLs in plain text, up to the first character that can't be part of one
+static TEXT_URL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?i)https?://[^\s<>()\[\]"']+"#).unwrap());
+
+/// An email sent to a client with tracking enabled.
+///
+/// Each delivery has a random token, which the tracked links and the tracking pixel carry
+/// so that a click or an open can be traced back to the email and to the client without
+/// exposing their IDs.
+#[derive(Debug)]
+pub struct Delivery {
+    token: String,
+    email_id: String,
+    revision: u32,
+    client_id: String,
+    /// Original URLs of the tracked links, by position
+    links: Vec<String>,
+    /// Whether the email embeds the tracking pixel
+    track_opens: bool,
+}
+
+/// Clicks on the links of an email.
+#[derive(Debug)]
+pub struct ClickReport {
+    email_id: String,
+    clicks: usize,
+    clickers: usize,
+    links: Vec<LinkClicks>,
+}
+
+/// Opens of an email, as recorded by its tracking pixel.
+///
+/// Images are often blocked, or loaded by a proxy of the mail provider when the email is
+/// received rather than opened, so opens are only an estimate.
+#[derive(Debug)]
+pub struct OpenReport {
+    email_id: String,
+    recipients: usize,
+    opens: usize,
+    openers: usize,
+}
+
+/// Clicks on one of the links of an email.
+#[derive(Debug)]
+pub struct LinkClicks {
+    url: String,
+    clicks: usize,
+    clickers: usize,
+}
+
+impl Delivery {
+    pub(crate) const CREATE_TABLES: &'static str = r#"
+        CREATE TABLE IF NOT EXISTS Delivery (
+            ID         TEXT PRIMARY KEY,
+            email_ID   TEXT NOT NULL,
+            revision   INTEGER NOT NULL,
+            client_ID  TEXT NOT NULL,
+            timestamp  INTEGER NOT NULL,
+            track_opens  INTEGER NOT NULL DEFAULT 0 CHECK(track_opens IN (0, 1)),
+            FOREIGN KEY (email_ID)  REFERENCES Email(ID)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE,
+            FOREIGN KEY (client_ID)  REFERENCES Client(ID)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE
+        ) STRICT;
+
+        CREATE TABLE IF NOT EXISTS TrackedLink (
+            delivery_ID  TEXT NOT NULL,
+            position     INTEGER NOT NULL,
+            url          TEXT NOT NULL,
+            PRIMARY KEY (delivery_ID, position),
+            FOREIGN KEY (delivery_ID)  REFERENCES Delivery(ID)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE
+        ) STRICT;
+
+        CREATE TABLE IF NOT EXISTS Click (
+            delivery_ID  TEXT NOT NULL,
+            position     INTEGER NOT NULL,
+            timestamp    INTEGER NOT NULL,
+            FOREIGN KEY (delivery_ID, position)  REFERENCES TrackedLink(delivery_ID, position)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE
+        ) STRICT;
+
+        CREATE TABLE IF NOT EXISTS Open (
+            delivery_ID  TEXT NOT NULL,
+            timestamp    INTEGER NOT NULL,
+            FOREIGN KEY (delivery_ID)  REFERENCES Delivery(ID)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE
+        ) STRICT;
+    "#;
+
+    /// A transparent GIF of 1x1 pixel.
+    pub(crate) const PIXEL: &'static [u8] = b"GIF89a\x01\x00\x01\x00\x80\x00\x00\x00\x00\x00\xff\xff\xff!\xf9\x04\x01\x00\x00\x00\x00,\x00\x00\x00\x00\x01\x00\x01\x00\x00\x02\x02D\x01\x00;";
+
+    /// A delivery of `email` to `client`, with a new token.
+    pub fn new(email: &Email, client: &Client) -> Self {
+        Self {
+            token: create_id(),
+            email_id: email.id().to_owned(),
+            revision: email.revision(),
+            client_id: client.id().to_owned(),
+            links: Vec::new(),
+            track_opens: false,
+        }
+    }
+
+    /// Record the delivery with the revision `revision` of the email rather than its
+    /// current one, such as the revision the template files sent were recorded in.
+    pub(crate) fn revision(mut self, revision: u32) -> Self {
+        self.revision = revision;
+        self
+    }
+
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// Original URLs of the links tracked so far, by position.
+    pub fn links(&self) -> &[String] {
+        &self.links
+    }
+
+    /// Replace the HTTP links of `html` with redirects through the tracking server at
+    /// `base_url`, such as `https://t.example.com`. Other links, such as `mailto:` ones,
+    /// are kept as is.
+    pub fn track_links(&mut self, html: &str, base_url: &str) -> Result<String> {
+        let base_url = base_url.trim_end_matches('/');
+
+        rewrite_a_href(html, |href| {
+            let url = unescape_attribute(href.trim());
+            let is_http = ["http://", "https://"].iter().any(|scheme| {
+                url.get(..scheme.len())
+                    .is_some_and(|start| start.eq_ignore_ascii_case(scheme))
+            });
+
+            if !is_http || url.starts_with(base_url) {
+                return Ok(None);
+            }
+
+            let position = self.links.len();
+            self.links.push(url);
+
+            Ok(Some(format!("{base_url}/c/{}/{position}", self.token)))
+        })
+    }
+
+    /// Redirect the URLs of the plain text `text` through the tracking server at
+    /// `base_url`. A URL already tracked in the HTML body keeps its position, so that both
+    /// parts count as the same link.
+    pub fn track_text_links(&mut self, text: &str, base_url: &str) -> String {
+        let base_url = base_url.trim_end_matches('/');
+
+        TEXT_URL
+            .replace_all(text, |captures: &regex::Captures| {
+                let matched = &captures[0];
+                // Punctuation ending a sentence isn't part of the URL
+                let url = matched.trim_end_matches(['.', ',', ';', ':', '!', '?']);
+                let rest = &matched[url.len()..];
+
+                if url.starts_with(base_url) {
+                    return matched.to_owned();
+                }
+
+                let position = match self.links.iter().position(|link| link == url) {
+                    Some(position) => position,
+                    None => {
+                        self.links.push(url.to_owned());
+                        self.links.len() - 1
+                    }
+                };
+
+                format!("{base_url}/c/{}/{position}{rest}", self.token)
+            })
+            .into_owned()
+    }
+
+    /// Append to the body of `html` a tracking pixel served by the tracking server at
+    /// `base_url`.
+    pub fn track_opens(&mut self, html: &str, base_url: &str) -> String {
+        self.track_opens = true;
+
+        append_to_body(
+            html,
+            &format!(
+                r#"<img src="{}/o/{}.gif" width="1" height="1" alt="" style="display:block;border:0;width:1px;height:1px">"#,
+                base_url.trim_end_matches('/'),
+                self.token
+            ),
+        )
+    }
+
+    /// Save the delivery and its links, which must happen before the email is sent so that
+    /// every click finds its link.
+    pub async fn write(&self, timestamp: u64, db: &DB) -> Result<()> {
+        debug!(
+            "Write to database tracked delivery. email = {}, client = {}, links = {}",
+            self.email_id,
+            self.client_id,
+            self.links.len()
+        );
+
+        db.connection(|conn| {
+            let tx = conn.unchecked_transaction()?;
+
+            tx.execute(
+                "INSERT INTO Delivery (ID, email_ID, revision, client_ID, timestamp, track_opens) VALUES (?, ?, ?, ?, ?, ?)",
+                (&self.token, &self.email_id, self.revision, &self.client_id, timestamp, self.track_opens),
+            )?;
+
+            {
+                let mut stmt = tx.prepare_cached(
+                    "INSERT INTO TrackedLink (delivery_ID, position, url) VALUES (?, ?, ?)",
+                )?;
+
+                for (position, url) in self.links.iter().enumerate() {
+                    stmt.execute((&self.token, position, url))?;
+                }
+            }
+
+            tx.commit()?;
+
+            Ok(())
+        })
+        .await
+    }
+}
+
+/// Record a click on the link at `position` of the delivery `token`, and return the URL
+/// to redirect to. Returns `None` for an unknown link.
+pub async fn record_click(
+    token: &str,
+    position: usize,
+    timestamp: u64,
+    db: &DB,
+) -> Result<Option<String>> {
+    db.connection(|conn| {
+        let url = query_link(token, position, conn)?;
+
+        if url.is_some() {
+            conn.execute(
+                "INSERT INTO Click (delivery_ID, position, timestamp) VALUES (?, ?, ?)",
+                (token, position, timestamp),
+            )?;
+        }
+
+        Ok(url)
+    })
+    .await
+}
+
+/// Record an open of the delivery `token`. Returns whether the delivery exists and embeds
+/// the tracking pixel.
+pub async fn record_open(token: &str, timestamp: u64, db: &DB) -> Result<bool> {
+    db.connection(|conn| {
+        let inserted = conn.execute(
+            r"
+            INSERT INTO Open (delivery_ID, timestamp)
+                SELECT ID, ? FROM Delivery WHERE ID = ? AND track_opens = 1
+        ",
+            (timestamp, token),
+        )?;
+
+        Ok(inserted > 0)
+    })
+    .await
+}
+
+/// The URL of the link at `position` of the delivery `token`, without recording a click.
+pub(crate) async fn link_url(token: &str, position: usize, db: &DB) -> Result<Option<String>> {
+    db.connection(|conn| query_link(token, position, conn))
+        .await
+}
+
+fn query_link(token: &str, position: usize, conn: &Connection) -> Result<Option<String>> {
+    Ok(conn
+        .query_row(
+            "SELECT url FROM TrackedLink WHERE delivery_ID = ? AND position = ?",
+            (token, position),
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+impl ClickReport {
+    /// Clicks on the links of the email `email_id`, over every revision.
+    pub async fn for_email(email_id: &str, db: &DB) -> Result<Self> {
+        db.connection(|conn| Self::query(email_id, conn)).await
+    }
+
+    /// Clicks on the links of every email sent with tracked links, in order of first
+    /// delivery.
+    pub async fn list(db: &DB) -> Result<Vec<Self>> {
+        db.connection(|conn| {
+            let mut stmt = conn.prepare_cached(
+                r"
+                SELECT email_ID FROM Delivery
+                    WHERE EXISTS (SELECT 1 FROM TrackedLink l WHERE l.delivery_ID = Delivery.ID)
+                    GROUP BY email_ID
+                    ORDER BY MIN(rowid)
+            ",
+            )?;
+
+            let email_ids: Vec<String> = Result::from_iter(stmt.query_map([], |row| row.get(0))?)?;
+
+            email_ids
+                .iter()
+                .map(|email_id| Self::query(email_id, conn))
+                .collect()
+        })
+        .await
+    }
+
+    pub fn email_id(&self) -> &str {
+        &self.email_id
+    }
+
+    /// Number of clicks on any link of the email.
+    pub fn clicks(&self) -> usize {
+        self.clicks
+    }
+
+    /// Number of clients who clicked at least one link of the email.
+    pub fn clickers(&self) -> usize {
+        self.clickers
+    }
+
+    /// Clicks by link, the most clicked first. Links with the same URL are counted
+    /// together.
+    pub fn links(&self) -> &[LinkClicks] {
+        &self.links
+    }
+
+    fn query(email_id: &str, conn: &Connection) -> Result<Self> {
+        let (clicks, clickers) = conn.query_row(
+            r"
+            SELECT COUNT(*), COUNT(DISTINCT d.client_ID)
+                FROM Click c
+                JOIN Delivery d ON d.ID = c.delivery_ID
+                WHERE d.email_ID = ?
+        ",
+            [email_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+
+        let mut stmt = conn.prepare_cached(
+            r"
+            SELECT l.url, COUNT(c.timestamp) AS clicks, COUNT(DISTINCT iif(c.timestamp IS NULL, NULL, d.client_ID))
+                FROM TrackedLink l
+                JOIN Delivery d ON d.ID = l.delivery_ID
+                LEFT JOIN Click c ON c.delivery_ID = l.delivery_ID AND c.position = l.position
+                WHERE d.email_ID = ?
+                GROUP BY l.url
+                ORDER BY clicks DESC, MIN(l.position), l.url
+        ",
+        )?;
+
+        let links = Result::from_iter(stmt.query_map([email_id], |row| {
+            Ok(LinkClicks {
+                url: row.get(0)?,
+                clicks: row.get(1)?,
+                clickers: row.get(2)?,
+            })
+        })?)?;
+
+        Ok(Self {
+            email_id: email_id.to_owned(),
+            clicks,
+            clickers,
+            links,
+        })
+    }
+}
+
+impl LinkClicks {
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn clicks(&self) -> usize {
+        self.clicks
+    }
+
+    /// Number of clients who clicked the link.
+    pub fn clickers(&self) -> usize {
+        self.clickers
+    }
+}
+
+impl OpenReport {
+    /// Opens of the email `email_id`, over every revision.
+    pub async fn for_email(email_id: &str, db: &DB) -> Result<Self> {
+        db.connection(|conn| Self::query(email_id, conn)).await
+    }
+
+    /// Opens of every email sent with the tracking pixel, in order of first delivery.
+    pub async fn list(db: &DB) -> Result<Vec<Self>> {
+        db.connection(|conn| {
+            let mut stmt = conn.prepare_cached(
+                r"
+                SELECT email_ID FROM Delivery
+                    WHERE track_opens = 1
+                    GROUP BY email_ID
+                    ORDER BY MIN(rowid)
+            ",
+            )?;
+
+            let email_ids: Vec<String> = Result::from_iter(stmt.query_map([], |row| row.get(0))?)?;
+
+            email_ids
+                .iter()
+                .map(|email_id| Self::query(email_id, conn))
+                .collect()
+        })
+        .await
+    }
+
+    pub fn email_id(&self) -> &str {
+        &self.email_id
+    }
+
+    /// Number of sends of the email that embedded the tracking pixel.
+    pub fn recipients(&self) -> usize {
+        self.recipients
+    }
+
+    /// Number of times the email was opened, including repeated opens by a client.
+    pub fn opens(&self) -> usize {
+        self.opens
+    }
+
+    /// Number of clients who opened the email at least once.
+    pub fn openers(&self) -> usize {
+        self.openers
+    }
+
+    fn query(email_id: &str, conn: &Connection) -> Result<Self> {
+        let (recipients, opens, openers) = conn.query_row(
+            r"
+            SELECT
+                (SELECT COUNT(*) FROM Delivery WHERE email_ID = ?1 AND track_opens = 1),
+                COUNT(*),
+                COUNT(DISTINCT d.client_ID)
+                FROM Open o
+                JOIN Delivery d ON d.ID = o.delivery_ID
+                WHERE d.email_ID = ?1
+        ",
+            [email_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+
+        Ok(Self {
+            email_id: email_id.to_owned(),
+            recipients,
+            opens,
+            openers,
+        })
+    }
+}
@@ -0,0 +1,180 @@
+use std::time::Duration;
+
+use color_eyre::eyre::{bail, Result};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, warn};
+
+use super::{link_url, record_click, record_open, Delivery};
+use crate::db::DB;
+use crate::mailer::now;
+
+/// Longest request head read, requests being only a request line and a few headers.
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+
+/// Time a client has to send its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Response of the tracking server.
+#[derive(Debug)]
+enum Response {
+    Redirect(String),
+    Pixel,
+    BadRequest,
+    NotFound,
+    MethodNotAllowed,
+    InternalError,
+}
+
+/// Serve the tracking endpoints on `listener` until an error occurs while accepting a
+/// connection.
+///
+/// `GET /c/<token>/<position>` records a click on a tracked link and redirects to its URL,
+/// and `GET /o/<token>.gif` records an open and answers with the tracking pixel. This is
+/// meant to run behind a reverse proxy handling TLS, at the base URL given to
+/// [`Mailer::track_clicks`](crate::mailer::Mailer::track_clicks) and
+/// [`Mailer::track_opens`](crate::mailer::Mailer::track_opens).
+pub async fn serve(listener: TcpListener, db: &'static DB) -> Result<()> {
+    loop {
+        let (stream, peer) = listener.accept().await?;
+
+        tokio::spawn(async move {
+            if let Err(err) = handle(stream, db).await {
+                debug!("Tracking connection from {peer} failed: {err}");
+            }
+        });
+    }
+}
+
+async fn handle(mut stream: TcpStream, db: &DB) -> Result<()> {
+    let head = tokio::time::timeout(READ_TIMEOUT, read_head(&mut stream)).await??;
+
+    let request = head
+        .lines()
+        .next()
+        .and_then(|line| line.split_once(' '))
+        .and_then(|(method, rest)| Some((method, rest.split(' ').next()?)));
+
+    let response = match request {
+        Some((method, target)) => match respond(method, target, db).await {
+            Ok(response) => response,
+            Err(err) => {
+                warn!("Tracking request {method} {target} failed: {err}");
+                Response::InternalError
+            }
+        },
+        None => Response::BadRequest,
+    };
+
+    let with_body = !matches!(request, Some(("HEAD", _)));
+    stream.write_all(&response.to_bytes(with_body)).await?;
+    stream.shutdown().await?;
+
+    Ok(())
+}
+
+/// Read the request up to the end of its headers. The body, if any, is ignored.
+async fn read_head(stream: &mut TcpStream) -> Result<String> {
+    let mut head = Vec::new();
+    let mut buffer = [0; 1024];
+
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+
+        head.extend_from_slice(&buffer[..read]);
+        if head.len() > MAX_REQUEST_SIZE {
+            bail!("Request head is larger than {MAX_REQUEST_SIZE} bytes");
+        }
+    }
+
+    Ok(String::from_utf8_lossy(&head).into_owned())
+}
+
+/// Answer the request `method` `target`. `HEAD` requests, which link scanners send to
+/// check where a link leads, are answered without being recorded.
+///
+/// The pixel is served whether or not the open was recorded, so that an unknown token
+/// doesn't show as a broken image.
+async fn respond(method: &str, target: &str, db: &DB) -> Result<Response> {
+    let record = match method {
+        "GET" => true,
+        "HEAD" => false,
+        _ => return Ok(Response::MethodNotAllowed),
+    };
+
+    // The query string is never part of a tracked URL
+    let path = target.split(['?', '#']).next().unwrap_or_default();
+    let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+
+    match segments.as_slice() {
+        ["c", token, position] => {
+            let Ok(position) = position.parse() else {
+                return Ok(Response::NotFound);
+            };
+
+            let url = if record {
+                record_click(token, position, now(), db).await?
+            } else {
+                link_url(token, position, db).await?
+            };
+
+            Ok(match url {
+                // A URL with a line break would let the rest of it be read as headers
+                Some(url) if !url.contains(['\r', '\n']) => Response::Redirect(url),
+                _ => Response::NotFound,
+            })
+        }
+        ["o", pixel] => {
+            let Some(token) = pixel.strip_suffix(".gif") else {
+                return Ok(Response::NotFound);
+            };
+
+            if record {
+                record_open(token, now(), db).await?;
+            }
+
+            Ok(Response::Pixel)
+        }
+        _ => Ok(Response::NotFound),
+    }
+}
+
+impl Response {
+    /// The response, with its body unless it answers a `HEAD` request.
+    fn to_bytes(&self, with_body: bool) -> Vec<u8> {
+        let (status, location) = match self {
+            Self::Redirect(url) => ("302 Found", Some(url)),
+            Self::Pixel => ("200 OK", None),
+            Self::BadRequest => ("400 Bad Request", None),
+            Self::NotFound => ("404 Not Found", None),
+            Self::MethodNotAllowed => ("405 Method Not Allowed", None),
+            Self::InternalError => ("500 Internal Server Error", None),
+        };
+
+        let mut response = format!("HTTP/1.1 {status}\r\n");
+        if let Some(location) = location {
+            response.push_str(&format!("Location: {location}\r\n"));
+        }
+        let body = match self {
+            Self::Pixel => {
+                response.push_str("Content-Type: image/gif\r\n");
+                Delivery::PIXEL
+            }
+            _ => &[],
+        };
+        response.push_str(&format!(
+            "Cache-Control: no-store\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        ));
+
+        let mut response = response.into_bytes();
+        if with_body {
+            response.extend_from_slice(body);
+        }
+
+        response
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::HashSet;
+
+use color_eyre::eyre::{bail, Result};
+use lettre::Message;
+use sequoia::client::{Client, Group};
+use sequoia::db::DB;
+use sequoia::email::{Email, EmailBuilder};
+use sequoia::mailer::{AbTest, MailTransport, Mailer, MemoryTransport};
+use sequoia::sender::SmtpCredentials;
+
+async fn db() -> Result<DB> {
+    DB::connect_to(":memory:").await
+}
+
+async fn email(subject: &str, db: &DB) -> Result<Email> {
+    EmailBuilder::new()
+        .sender_adresse("sender@example.com")?
+        .subject(subject)
+        .plain_body("Hello")
+        .create(db)
+        .await
+}
+
+#[tokio::test]
+async fn assignments_are_reproducible() -> Result<()> {
+    let db = db().await?;
+
+    let mut group = Group::create("Newsletter".to_owned(), &db).await?;
+    let mut client_ids = Vec::new();
+    for i in 0..200 {
+        client_ids.push(
+            Client::create(&format!("client{i}@example.com"), &db)
+                .await?
+                .id()
+                .to_owned(),
+        );
+    }
+    group.add_clients(&client_ids, &db).await?;
+
+    let variants = vec![email("A", &db).await?, email("B", &db).await?];
+    let ab_test = AbTest::create(group, variants, 42, 50, None, &db).await?;
+
+    let loaded = AbTest::get_one(ab_test.id(), &db)
+        .await?
+        .expect("A/B test is saved");
+    assert_eq!(loaded.seed(), 42);
+    assert_eq!(loaded.sample_percent(), 50);
+    assert_eq!(loaded.group().id(), ab_test.group().id());
+    assert_eq!(loaded.variants()[1].id(), ab_test.variants()[1].id());
+
+    let assignments: Vec<_> = client_ids.iter().map(|id| ab_test.assign(id)).collect();
+    let loaded_assignments: Vec<_> = client_ids.iter().map(|id| loaded.assign(id)).collect();
+    assert_eq!(assignments, loaded_assignments);
+
+    // Roughly half of the group is in the sample, split between both variants
+    let sample = assignments.iter().flatten().count();
+    assert!(
+        (60..=140).contains(&sample),
+        "{sample} clients in the sample"
+    );
+    assert!(assignments.contains(&Some(0)));
+    assert!(assignments.contains(&Some(1)));
+
+    let results = loaded.results(&db).await?;
+    assert_eq!(results.len(), 2);
+    assert!(results.iter().all(|result| result.recipients() == 0));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn invalid_ab_tests() -> Result<()> {
+    let db = db().await?;
+
+    let a = email("A", &db).await?;
+    let group = Group::create("Newsletter".to_owned(), &db).await?;
+    assert!(AbTest::create(group, vec![a], 0, 10, None, &db)
+        .await
+        .is_err());
+
+    let (a, b) = (email("A", &db).await?, email("B", &db).await?);
+    let group = Group::create("Customers".to_owned(), &db).await?;
+    assert!(AbTest::create(group, vec![a, b], 0, 0, None, &db)
+        .await
+        .is_err());
+
+    let a = email("A", &db).await?;
+    let same = Email::get_one(a.id(), &db).await?.expect("email is saved");
+    let group = Group::create("Partners".to_owned(), &db).await?;
+    assert!(AbTest::create(group, vec![a, same], 0, 10, None, &db)
+        .await
+        .is_err());
+
+    Ok(())
+}
+
+/// Failing once `limit` messages were sent.
+struct FailingTransport {
+    sent: MemoryTransport,
+    limit: usize,
+}
+
+impl MailTransport for FailingTransport {
+    fn send(&self, message: &Message, credentials: Option<&SmtpCredentials>) -> Result<()> {
+        if self.sent.messages().len() >= self.limit {
+            bail!("Connection lost");
+        }
+
+        self.sent.send(message, credentials)
+    }
+}
+
+#[tokio::test]
+async fn interrupted_samples_are_resumed() -> Result<()> {
+    let db = db().await?;
+
+    let mut group = Group::create("Newsletter".to_owned(), &db).await?;
+    let mut client_ids = Vec::new();
+    for i in 0..40 {
+        client_ids.push(
+            Client::create(&format!("client{i}@example.com"), &db)
+                .await?
+                .id()
+                .to_owned(),
+        );
+    }
+    group.add_clients(&client_ids, &db).await?;
+
+    let variants = vec![email("A", &db).await?, email("B", &db).await?];
+    let mut ab_test = AbTest::create(group, variants, 42, 50, None, &db).await?;
+    let sample = client_ids
+        .iter()
+        .filter(|id| ab_test.assign(id).is_some())
+        .count();
+    assert!(sample > 5, "{sample} clients in the sample");
+
+    let sent = MemoryTransport::new();
+    let failing = FailingTransport {
+        sent: sent.clone(),
+        limit: 5,
+    };
+    assert!(Mailer::with_transport(failing, &db)?
+        .send_ab_test(&mut ab_test)
+        .await
+        .is_err());
+    assert_eq!(sent.messages().len(), 5);
+    assert!(ab_test.sent_timestamp().is_none());
+
+    Mailer::with_transport(sent.clone(), &db)?
+        .send_ab_test(&mut ab_test)
+        .await?;
+    assert!(ab_test.sent_timestamp().is_some());
+
+    // Every client of the sample was emailed once
+    let recipients: HashSet<_> = sent
+        .messages()
+        .iter()
+        .map(|message| message.recipients().to_vec())
+        .collect();
+    assert_eq!(sent.messages().len(), sample);
+    assert_eq!(recipients.len(), sample);
+
+    let results = ab_test.results(&db).await?;
+    let recipients: usize = results.iter().map(|result| result.recipients()).sum();
+    assert_eq!(recipients, sample);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn interrupted_remainders_are_resumed() -> Result<()> {
+    let db = db().await?;
+
+    let mut group = Group::create("Newsletter".to_owned(), &db).await?;
+    let mut client_ids = Vec::new();
+    for i in 0..40 {
+        client_ids.push(
+            Client::create(&format!("client{i}@example.com"), &db)
+                .await?
+                .id()
+                .to_owned(),
+        );
+    }
+    group.add_clients(&client_ids, &db).await?;
+
+    let variants = vec![email("A", &db).await?, email("B", &db).await?];
+    let winner = variants[1].id().to_owned();
+    let mut ab_test = AbTest::create(group, variants, 42, 50, None, &db).await?;
+    let remainder = client_ids
+        .iter()
+        .filter(|id| ab_test.assign(id).is_none())
+        .count();
+    assert!(remainder > 5, "{remainder} clients in the remainder");
+
+    let sent = MemoryTransport::new();
+    Mailer::with_transport(sent.clone(), &db)?
+        .send_ab_test(&mut ab_test)
+        .await?;
+    let sample = sent.messages().len();
+
+    // The remainder fits in one page, so the sending fails halfway through it
+    let failing = FailingTransport {
+        sent: sent.clone(),
+        limit: sample + 5,
+    };
+    assert!(Mailer::with_transport(failing, &db)?
+        .send_ab_test_remainder(&mut ab_test, &winner)
+        .await
+        .is_err());
+    assert_eq!(sent.messages().len(), sample + 5);
+
+    Mailer::with_transport(sent.clone(), &db)?
+        .send_ab_test_remainder(&mut ab_test, &winner)
+        .await?;
+
+    // Every client of the group was emailed once
+    let recipients: HashSet<_> = sent
+        .messages()
+        .iter()
+        .map(|message| message.recipients().to_vec())
+        .collect();
+    assert_eq!(sent.messages().len(), client_ids.len());
+    assert_eq!(recipients.len(), client_ids.len());
+
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use color_eyre::eyre::Result;
+use sequoia::db::DB;
+use sequoia::email::{Email, EmailChange, EmailDefinition, EmailStatus};
+
+async fn db() -> Result<DB> {
+    DB::connect_to(":memory:").await
+}
+
+fn write(dir: &std::path::Path, name: &str, contents: &str) -> Result<()> {
+    std::fs::write(dir.join(name), contents)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn sync_definitions() -> Result<()> {
+    let db = db().await?;
+    let dir = std::env::temp_dir().join(format!("sequoia-definitions-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+
+    write(
+        &dir,
+        "welcome.yaml",
+        "sender: bureau@example.com\nsubject: Welcome\nbody: <p>Hello</p>\ntags: [onboarding]\ndraft: true\n",
+    )?;
+    write(
+        &dir,
+        "news.toml",
+        "key = \"newsletter\"\nsender = \"bureau@example.com\"\nsubject = \"News\"\nmarkdown = \"# News\"\n\n[headers]\nX-Campaign = \"spring\"\n",
+    )?;
+    write(&dir, "notes.txt", "ignored")?;
+
+    let definitions = EmailDefinition::load_dir(&dir)?;
+    let keys: Vec<_> = definitions.iter().map(EmailDefinition::key).collect();
+    assert_eq!(keys, ["newsletter", "welcome"]);
+
+    for definition in &definitions {
+        assert!(matches!(
+            definition.diff(&db).await?.change(),
+            EmailChange::Create
+        ));
+        assert!(matches!(
+            definition.sync(&db).await?.change(),
+            EmailChange::Create
+        ));
+        // Syncing is idempotent
+        assert!(definition.sync(&db).await?.is_unchanged());
+    }
+    assert_eq!(Email::list(&db).await?.len(), 2);
+
+    let newsletter = Email::get_by_key("newsletter", &db)
+        .await?
+        .expect("email is synced");
+    assert_eq!(newsletter.headers().custom()[0].value(), "spring");
+
+    write(
+        &dir,
+        "welcome.yaml",
+        "sender: bureau@example.com\nsubject: Welcome!\nbody: <p>Hello</p>\ntags: [onboarding, new]\n",
+    )?;
+    let welcome = EmailDefinition::from_file(dir.join("welcome.yaml"))?;
+
+    let diff = welcome.diff(&db).await?;
+    let EmailChange::Update(changes) = diff.change() else {
+        panic!("welcome should be updated, not {diff}");
+    };
+    let fields: Vec<_> = changes.iter().map(|change| change.field()).collect();
+    assert_eq!(fields, ["subject", "tags", "status"]);
+
+    // Diffing changes nothing
+    let email = Email::get_by_key("welcome", &db)
+        .await?
+        .expect("email is synced");
+    assert_eq!(email.subject(), "Welcome");
+
+    welcome.sync(&db).await?;
+    let email = Email::get_by_key("welcome", &db)
+        .await?
+        .expect("email is synced");
+    assert_eq!(email.subject(), "Welcome!");
+    assert_eq!(email.tags(), ["onboarding", "new"]);
+    assert_eq!(email.status(), EmailStatus::Published);
+    assert_eq!(email.revision(), 2);
+    assert!(welcome.sync(&db).await?.is_unchanged());
+
+    std::fs::remove_dir_all(&dir)?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn markdown_only_fields_are_rejected() -> Result<()> {
+    let db = db().await?;
+    let dir = std::env::temp_dir().join(format!("sequoia-invalid-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+
+    write(
+        &dir,
+        "styled.yaml",
+        "sender: bureau@example.com\nsubject: News\ntemplate: <p>{{ client.adresse }}</p>\nstylesheet: 'p { color: red; }'\n",
+    )?;
+    write(
+        &dir,
+        "text.yaml",
+        "sender: bureau@example.com\nsubject: News\nmarkdown: '# News'\ntext_body: News\n",
+    )?;
+
+    let styled = EmailDefinition::from_file(dir.join("styled.yaml"))?;
+    let err = styled.sync(&db).await.unwrap_err().to_string();
+    assert_eq!(
+        err,
+        "Email definition styled: stylesheet is only for markdown bodies, which are compiled to HTML"
+    );
+
+    let text = EmailDefinition::from_file(dir.join("text.yaml"))?;
+    let err = text.sync(&db).await.unwrap_err().to_string();
+    assert_eq!(
+        err,
+        "Email definition text: text_body isn't for markdown bodies, which are their own text body"
+    );
+
+    std::fs::remove_dir_all(&dir)?;
+
+    Ok(())
+}
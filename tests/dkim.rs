@@ -0,0 +1,75 @@
+use color_eyre::eyre::Result;
+use lettre::Message;
+use sequoia::client::Client;
+use sequoia::db::DB;
+use sequoia::email::{EmailBuilder, TemplateStore};
+use sequoia::mailer::{dkim, Dkim, DkimAlgorithm};
+
+const RSA_KEY: &str = include_str!("fixtures/dkim/rsa.pem");
+const RSA_PKCS1_KEY: &str = include_str!("fixtures/dkim/rsa_pkcs1.pem");
+const ED25519_KEY: &str = include_str!("fixtures/dkim/ed25519.pem");
+
+async fn message() -> Result<Message> {
+    let db = DB::connect_to(":memory:").await?;
+
+    let email = EmailBuilder::new()
+        .sender_adresse("Bureau <bureau@example.com>")?
+        .subject("Welcome   aboard")
+        .plain_body("<html><body><p>Hello \t there</p></body></html>")
+        .create(&db)
+        .await?;
+    let client = Client::create("client@example.com", &db).await?;
+
+    email.render_for(&client, &TemplateStore::new("templates"))
+}
+
+#[tokio::test]
+async fn signatures_verify() -> Result<()> {
+    for (algorithm, key) in [
+        (DkimAlgorithm::Rsa, RSA_KEY),
+        (DkimAlgorithm::Rsa, RSA_PKCS1_KEY),
+        (DkimAlgorithm::Ed25519, ED25519_KEY),
+    ] {
+        let dkim = Dkim::new("mail", "example.com", algorithm, key)?;
+        assert_eq!(dkim.dns_name(), "mail._domainkey.example.com");
+
+        let mut message = message().await?;
+        dkim.sign(&mut message);
+
+        let formatted = String::from_utf8(message.formatted())?;
+        assert!(formatted.contains("DKIM-Signature: v=1;"));
+        assert!(formatted.contains("c=relaxed/relaxed;"));
+        dkim::verify(formatted.as_bytes(), &dkim.dns_record())?;
+
+        // Relays may rewrap and respace headers
+        let rewrapped =
+            formatted.replace("Subject: Welcome   aboard", "Subject: Welcome\r\n aboard");
+        dkim::verify(rewrapped.as_bytes(), &dkim.dns_record())?;
+
+        let altered = formatted.replace("Hello", "Hullo");
+        assert!(dkim::verify(altered.as_bytes(), &dkim.dns_record()).is_err());
+
+        let altered = formatted.replace("Subject: Welcome", "Subject: Goodbye");
+        assert!(dkim::verify(altered.as_bytes(), &dkim.dns_record()).is_err());
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn wrong_key_is_rejected() -> Result<()> {
+    let rsa = Dkim::new("mail", "example.com", DkimAlgorithm::Rsa, RSA_KEY)?;
+    let ed25519 = Dkim::new("mail", "example.com", DkimAlgorithm::Ed25519, ED25519_KEY)?;
+
+    let mut message = message().await?;
+    rsa.sign(&mut message);
+
+    assert!(dkim::verify(&message.formatted(), &ed25519.dns_record()).is_err());
+    assert!(dkim::verify(&message.formatted(), &rsa.dns_record()).is_ok());
+
+    assert!(Dkim::new("mail", "example.com", DkimAlgorithm::Ed25519, RSA_KEY).is_err());
+    assert!(Dkim::new("mail;", "example.com", DkimAlgorithm::Rsa, RSA_KEY).is_err());
+    assert!(!format!("{rsa:?}").contains("PRIVATE"));
+
+    Ok(())
+}
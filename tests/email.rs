@@ -0,0 +1,699 @@
+use color_eyre::eyre::Result;
+use sequoia::client::Client;
+use sequoia::db::DB;
+use sequoia::email::{Email, EmailBuilder, EmailModel, LintKind, Tag, TagMatch, TemplateStore};
+use sequoia::mailer::Receiver;
+use sequoia::sender::Sender;
+
+async fn db() -> Result<DB> {
+    DB::connect_to(":memory:").await
+}
+
+fn assert_same(email: &Email, loaded: &Email) {
+    assert_eq!(email.id(), loaded.id());
+    assert_eq!(email.status(), loaded.status());
+    assert_eq!(email.revision(), loaded.revision());
+    assert_eq!(email.sender_adresse(), loaded.sender_adresse());
+    assert_eq!(email.tags(), loaded.tags());
+    assert_eq!(email.subject(), loaded.subject());
+    assert_eq!(email.body(), loaded.body());
+    assert_eq!(email.text_body(), loaded.text_body());
+
+    let (headers, loaded_headers) = (email.headers(), loaded.headers());
+    assert_eq!(headers.reply_to(), loaded_headers.reply_to());
+    assert_eq!(headers.cc(), loaded_headers.cc());
+    assert_eq!(headers.bcc(), loaded_headers.bcc());
+    assert_eq!(headers.custom().len(), loaded_headers.custom().len());
+    for (header, loaded_header) in headers.custom().iter().zip(loaded_headers.custom()) {
+        assert_eq!(header.name(), loaded_header.name());
+        assert_eq!(header.value(), loaded_header.value());
+    }
+
+    let attachments: Vec<_> = email.attachments().chain(email.inline_images()).collect();
+    let loaded_attachments: Vec<_> = loaded.attachments().chain(loaded.inline_images()).collect();
+    assert_eq!(attachments.len(), loaded_attachments.len());
+    for (attachment, loaded_attachment) in attachments.into_iter().zip(loaded_attachments) {
+        assert_eq!(attachment.id(), loaded_attachment.id());
+        assert_eq!(attachment.filename(), loaded_attachment.filename());
+        assert_eq!(attachment.content_type(), loaded_attachment.content_type());
+        assert_eq!(attachment.data(), loaded_attachment.data());
+        assert_eq!(attachment.content_id(), loaded_attachment.content_id());
+    }
+}
+
+async fn assert_round_trip(email: &Email, db: &DB) -> Result<()> {
+    let loaded = Email::get_one(email.id(), db)
+        .await?
+        .expect("email was saved");
+    assert_same(email, &loaded);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn plain_email_round_trip() -> Result<()> {
+    let db = db().await?;
+
+    let email = EmailBuilder::new()
+        .sender_adresse("sender@example.com")?
+        .subject("Hello")
+        .plain_body("<p>Hello <b>world</b></p>")
+        .tags(vec!["JE".to_owned(), "MRI".to_owned()])?
+        .create(&db)
+        .await?;
+
+    assert_round_trip(&email, &db).await?;
+    assert_eq!(email.text_body(), Some("Hello **world**\n"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn template_email_round_trip() -> Result<()> {
+    let db = db().await?;
+
+    let email = EmailBuilder::new()
+        .sender_adresse("sender@example.com")?
+        .subject("Hello {{ client.adresse }}")
+        .template_body("<p>Sent on {{ date | format_date(\"%d/%m/%Y\") }}</p>")
+        .text_body("Sent on {{ date }}")
+        .tags(vec!["newsletter".to_owned()])?
+        .create(&db)
+        .await?;
+
+    assert_round_trip(&email, &db).await?;
+
+    let from_file = EmailBuilder::new()
+        .sender_adresse("sender@example.com")?
+        .subject("Newsletter")
+        .template_path("newsletter/body.html")
+        .create(&db)
+        .await?;
+
+    assert_round_trip(&from_file, &db).await?;
+    assert_eq!(from_file.text_body(), None);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn markdown_email_round_trip() -> Result<()> {
+    let db = db().await?;
+
+    let email = EmailBuilder::new()
+        .sender_adresse("sender@example.com")?
+        .subject("News")
+        .markdown_body("# News\n\nRead *this* <script>alert(1)</script>\n")
+        .stylesheet("h1 { color: green; }")
+        .create(&db)
+        .await?;
+
+    assert_round_trip(&email, &db).await?;
+    assert!(email.body().contains("<h1>News</h1>"));
+    assert!(email.body().contains("<em>this</em>"));
+    assert!(email.body().contains("h1 { color: green; }"));
+    assert!(!email.body().contains("<script>"));
+    assert_eq!(
+        email.text_body(),
+        Some("# News\n\nRead *this* <script>alert(1)</script>\n")
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn headers_and_attachments_round_trip() -> Result<()> {
+    let db = db().await?;
+
+    let email = EmailBuilder::new()
+        .sender_adresse("sender@example.com")?
+        .subject("Report")
+        .plain_body("<img src=\"cid:logo\">")
+        .reply_to("Support <support@example.com>")?
+        .cc("a@example.com")?
+        .cc("b@example.com")?
+        .bcc("archive@example.com")?
+        .header("X-Campaign", "spring")?
+        .attachment("report.csv", "text/csv", b"a,b\n1,2\n".to_vec())?
+        .inline_image("logo", "image/png", vec![0x89, b'P', b'N', b'G'])?
+        .create(&db)
+        .await?;
+
+    assert_round_trip(&email, &db).await
+}
+
+#[tokio::test]
+async fn inline_images_have_a_content_id() -> Result<()> {
+    let db = db().await?;
+
+    let email = EmailBuilder::new()
+        .sender_adresse("sender@example.com")?
+        .subject("Report")
+        .plain_body("<img src=\"cid:logo\">")
+        .attachment("report.csv", "text/csv", b"a,b\n1,2\n".to_vec())?
+        .inline_image("logo", "image/png", vec![0x89, b'P', b'N', b'G'])?
+        .create(&db)
+        .await?;
+    let client = Client::create("client@example.com", &db).await?;
+
+    let message = String::from_utf8(
+        email
+            .render_for(&client, &TemplateStore::new("templates"))?
+            .formatted(),
+    )?;
+
+    assert!(message.contains("Content-ID: <logo>\r\n"));
+    assert!(message.contains("Content-Disposition: inline\r\n"));
+    assert!(message.contains("Content-Disposition: attachment; filename=\"report.csv\"\r\n"));
+
+    Ok(())
+}
+
+#[test]
+fn duplicate_content_ids_are_rejected() -> Result<()> {
+    let builder = EmailBuilder::new().inline_image("logo", "image/png", vec![0x89])?;
+
+    assert!(builder
+        .inline_image("logo", "image/png", vec![0x89])
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn local_images_are_embedded_from_the_root() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("sequoia-images-{}", std::process::id()));
+    let root = dir.join("images");
+    std::fs::create_dir_all(&root)?;
+    std::fs::write(root.join("a&b.png"), [0x89, b'P', b'N', b'G'])?;
+    std::fs::write(dir.join("secret.png"), [0x89, b'P', b'N', b'G'])?;
+
+    let embed = |body: &str| {
+        EmailBuilder::new()
+            .sender_adresse("sender@example.com")?
+            .subject("Report")
+            .plain_body(body)
+            .embed_local_images(&root)
+            .build()
+    };
+
+    // Both sources point to the same file once unescaped and resolved
+    let email = embed(r#"<img src="a&amp;b.png"><img src="./a&amp;b.png">"#)?;
+    assert_eq!(email.inline_images().count(), 1);
+    assert_eq!(email.body().matches("cid:").count(), 2);
+
+    let absolute = dir.join("secret.png");
+    for src in ["../secret.png", absolute.to_str().unwrap()] {
+        assert!(embed(&format!(r#"<img src="{src}">"#)).is_err(), "{src}");
+    }
+
+    std::fs::remove_dir_all(dir)?;
+
+    Ok(())
+}
+
+#[test]
+fn duplicate_headers_are_rejected() -> Result<()> {
+    let builder = EmailBuilder::new().header("X-Campaign", "spring")?;
+
+    assert!(builder.header("x-campaign", "summer").is_err());
+    assert!(EmailBuilder::new()
+        .header("Reply-To", "a@example.com")
+        .is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn rename_tags() -> Result<()> {
+    let db = db().await?;
+    EmailBuilder::new()
+        .sender_adresse("sender@example.com")?
+        .subject("News")
+        .plain_body("<p>Hello</p>")
+        .tags(vec!["news".to_owned(), "spring".to_owned()])?
+        .create(&db)
+        .await?;
+
+    Tag::rename("news", "news", &db).await?;
+    assert!(Tag::rename("news", "spring", &db).await.is_err());
+
+    Tag::rename("news", "newsletter", &db).await?;
+    let counts = Tag::counts(&db).await?;
+    let tags: Vec<_> = counts.iter().map(Tag::name).collect();
+    assert_eq!(tags, ["newsletter", "spring"]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn draft_round_trip() -> Result<()> {
+    let db = db().await?;
+
+    let mut email = EmailBuilder::new()
+        .sender_adresse("sender@example.com")?
+        .subject("Draft")
+        .plain_body("<p>First</p>")
+        .draft()
+        .create(&db)
+        .await?;
+
+    email
+        .update(
+            EmailModel::template("Second", "<p>{{ client.adresse }}</p>", "", None),
+            &db,
+        )
+        .await?;
+
+    assert_round_trip(&email, &db).await?;
+    assert_eq!(email.revisions(&db).await?.len(), 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn list_and_delete() -> Result<()> {
+    let db = db().await?;
+
+    let plain = EmailBuilder::new()
+        .sender_adresse("sender@example.com")?
+        .subject("Plain")
+        .plain_body("<p>Plain</p>")
+        .attachment("notes.txt", "text/plain", b"notes".to_vec())?
+        .create(&db)
+        .await?;
+    let template = EmailBuilder::new()
+        .sender_adresse("sender@example.com")?
+        .subject("Template")
+        .template_body("<p>{{ client.adresse }}</p>")
+        .create(&db)
+        .await?;
+
+    let emails = Email::list(&db).await?;
+    assert_eq!(emails.len(), 2);
+    assert_same(&plain, &emails[0]);
+    assert_same(&template, &emails[1]);
+
+    let plain_id = plain.id().to_owned();
+    plain.delete(&db).await?;
+
+    assert!(Email::get_one(&plain_id, &db).await?.is_none());
+
+    let emails = Email::list(&db).await?;
+    assert_eq!(emails.len(), 1);
+    assert_same(&template, &emails[0]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn listed_emails_get_their_own_relations() -> Result<()> {
+    let db = db().await?;
+    let sender = Sender::create(Some("Bureau"), "bureau@example.com", &db).await?;
+
+    let news = EmailBuilder::new()
+        .sender(&sender)?
+        .subject("News")
+        .plain_body("<p>News</p>")
+        .tags(vec!["news".to_owned()])?
+        .header("X-Campaign", "spring")?
+        .attachment("notes.txt", "text/plain", b"notes".to_vec())?
+        .variant(
+            "fr",
+            EmailModel::plain("Nouvelles", "<p>Nouvelles</p>", None)?,
+        )?
+        .create(&db)
+        .await?;
+    let untagged = EmailBuilder::new()
+        .sender(&sender)?
+        .subject("Untagged")
+        .plain_body("<p>Untagged</p>")
+        .create(&db)
+        .await?;
+    let digest = EmailBuilder::new()
+        .sender_adresse("other@example.com")?
+        .subject("Digest")
+        .plain_body("<p>Digest</p>")
+        .tags(vec!["news".to_owned(), "digest".to_owned()])?
+        .inline_image("logo", "image/png", vec![0x89, b'P', b'N', b'G'])?
+        .create(&db)
+        .await?;
+
+    let emails = Email::list(&db).await?;
+    assert_eq!(emails.len(), 3);
+    for (email, loaded) in [&news, &untagged, &digest].into_iter().zip(&emails) {
+        assert_same(email, loaded);
+        assert_eq!(
+            email.sender().map(Sender::id),
+            loaded.sender().map(Sender::id)
+        );
+        assert_eq!(email.variants().len(), loaded.variants().len());
+    }
+
+    let tagged = Email::list_by_tags(&["news"], TagMatch::Any, &db).await?;
+    assert_eq!(tagged.len(), 2);
+    assert_same(&news, &tagged[0]);
+    assert_same(&digest, &tagged[1]);
+    assert_eq!(tagged[0].variants()[0].locale(), "fr");
+    assert!(tagged[1].sender().is_none());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn render_for_client() -> Result<()> {
+    let db = db().await?;
+
+    let email = EmailBuilder::new()
+        .sender_adresse("sender@example.com")?
+        .subject("Hello {{ client.adresse }}")
+        .template_body("<p>Hello {{ client.adresse }}</p>")
+        .attachment("notes.txt", "text/plain", b"notes".to_vec())?
+        .create(&db)
+        .await?;
+    let client = Client::create("client@example.com", &db).await?;
+
+    let message = email.render_for(&client, &TemplateStore::new("templates"))?;
+    let formatted = String::from_utf8(message.formatted())?;
+
+    assert!(formatted.contains("To: client@example.com"));
+    assert!(formatted.contains("Subject: Hello client@example.com"));
+    assert!(formatted.contains("<p>Hello client@example.com</p>"));
+    assert!(formatted.contains("filename=\"notes.txt\""));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn messages_have_a_plain_text_alternative() -> Result<()> {
+    let db = db().await?;
+    let client = Client::create("client@example.com", &db).await?;
+    let templates = TemplateStore::new("templates");
+
+    let derived = EmailBuilder::new()
+        .sender_adresse("sender@example.com")?
+        .subject("Hello")
+        .plain_body("<p>Hello <b>world</b></p>")
+        .create(&db)
+        .await?;
+    let formatted = String::from_utf8(derived.render_for(&client, &templates)?.formatted())?;
+
+    assert!(formatted.contains("Content-Type: multipart/alternative"));
+    // Clients display the last part they support, so the HTML comes last
+    let text = formatted.find("Content-Type: text/plain").unwrap();
+    let html = formatted.find("Content-Type: text/html").unwrap();
+    assert!(text < html);
+    assert!(formatted[text..html].contains("Hello"));
+    assert!(!formatted[text..html].contains("<p>"));
+
+    let rendered = EmailBuilder::new()
+        .sender_adresse("sender@example.com")?
+        .subject("Hello")
+        .template_body("<p>Hello</p>")
+        .text_body("Hello {{ client.adresse }}")
+        .create(&db)
+        .await?;
+    let formatted = String::from_utf8(rendered.render_for(&client, &templates)?.formatted())?;
+
+    assert!(formatted.contains("Hello client@example.com"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn attachments_are_sent_as_mixed_parts() -> Result<()> {
+    let db = db().await?;
+    let client = Client::create("client@example.com", &db).await?;
+    let templates = TemplateStore::new("templates");
+
+    let without = EmailBuilder::new()
+        .sender_adresse("sender@example.com")?
+        .subject("Report")
+        .plain_body("<p>Report</p>")
+        .create(&db)
+        .await?;
+    let formatted = String::from_utf8(without.render_for(&client, &templates)?.formatted())?;
+    assert!(!formatted.contains("multipart/mixed"));
+
+    let with = EmailBuilder::new()
+        .sender_adresse("sender@example.com")?
+        .subject("Report")
+        .plain_body("<p>Report</p>")
+        .attachment("report.csv", "text/csv", b"a,b\n1,2\n".to_vec())?
+        .create(&db)
+        .await?;
+    let formatted = String::from_utf8(with.render_for(&client, &templates)?.formatted())?;
+
+    // The body and its alternative come first, then the attachment
+    let mixed = formatted.find("Content-Type: multipart/mixed").unwrap();
+    let alternative = formatted
+        .find("Content-Type: multipart/alternative")
+        .unwrap();
+    let attachment = formatted
+        .find("Content-Disposition: attachment; filename=\"report.csv\"")
+        .unwrap();
+    assert!(mixed < alternative && alternative < attachment);
+    assert!(formatted.contains("Content-Type: text/csv"));
+
+    assert!(EmailBuilder::new()
+        .sender_adresse("sender@example.com")?
+        .subject("Report")
+        .plain_body("<p>Report</p>")
+        .attachment("report.csv", "text/csv", b"a,b\n1,2\n".to_vec())?
+        .max_attachments_size(4)
+        .build()
+        .is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn localized_variants() -> Result<()> {
+    let db = db().await?;
+
+    let email = EmailBuilder::new()
+        .sender_adresse("sender@example.com")?
+        .subject("Hello")
+        .plain_body("<p>Hello</p>")
+        .locale("en")?
+        .variant("fr", EmailModel::plain("Bonjour", "<p>Bonjour</p>", None)?)?
+        .variant(
+            "de_DE",
+            EmailModel::template("Hallo {{ client.adresse }}", "<p>Hallo</p>", "", None),
+        )?
+        .create(&db)
+        .await?;
+
+    let loaded = Email::get_one(email.id(), &db)
+        .await?
+        .expect("email was saved");
+    assert_same(&email, &loaded);
+    assert_eq!(loaded.locale(), Some("en"));
+    let locales: Vec<_> = loaded.variants().iter().map(|v| v.locale()).collect();
+    assert_eq!(locales, ["fr", "de-de"]);
+
+    let subject_for = |locale: Option<&str>| {
+        loaded
+            .variant_for(locale)
+            .map_or(loaded.subject(), |variant| variant.subject())
+    };
+    assert_eq!(subject_for(None), "Hello");
+    assert_eq!(subject_for(Some("en-GB")), "Hello");
+    assert_eq!(subject_for(Some("fr")), "Bonjour");
+    assert_eq!(subject_for(Some("fr-CA")), "Bonjour");
+    assert_eq!(subject_for(Some("es")), "Hello");
+
+    let mut french = Client::create("fr@example.com", &db).await?;
+    french.set_locale(Some("fr"), &db).await?;
+    let mut german = Client::create("de@example.com", &db).await?;
+    german.set_locale(Some("de-DE"), &db).await?;
+    let mut spanish = Client::create("es@example.com", &db).await?;
+    spanish.set_locale(Some("es"), &db).await?;
+    let unknown = Client::create("unknown@example.com", &db).await?;
+
+    let templates = TemplateStore::new("templates");
+    let message = String::from_utf8(loaded.render_for(&german, &templates)?.formatted())?;
+    assert!(message.contains("Subject: Hallo de@example.com"));
+
+    let receiver = Receiver::union([french.into(), german.into(), spanish.into(), unknown.into()]);
+    let missing = loaded.missing_translations(&receiver, &db).await?;
+    assert_eq!(
+        missing.into_iter().collect::<Vec<_>>(),
+        [("es".to_owned(), 1)]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn variant_edits_add_revisions() -> Result<()> {
+    let db = db().await?;
+
+    let mut email = EmailBuilder::new()
+        .sender_adresse("sender@example.com")?
+        .subject("Hello")
+        .plain_body("<p>Hello</p>")
+        .locale("en")?
+        .variant("fr", EmailModel::plain("Bonjour", "<p>Bonjour</p>", None)?)?
+        .draft()
+        .create(&db)
+        .await?;
+
+    email
+        .set_variant("fr", EmailModel::plain("Salut", "<p>Salut</p>", None)?, &db)
+        .await?;
+    assert!(email.remove_variant("fr", &db).await?);
+    assert_eq!(email.revision(), 3);
+
+    let loaded = Email::get_one(email.id(), &db)
+        .await?
+        .expect("email was saved");
+    assert_eq!(loaded.revision(), 3);
+
+    let revisions = email.revisions(&db).await?;
+    let subjects: Vec<Vec<_>> = revisions
+        .iter()
+        .map(|revision| {
+            revision
+                .variants()
+                .iter()
+                .map(|variant| (variant.locale(), variant.subject().to_owned()))
+                .collect()
+        })
+        .collect();
+    assert_eq!(
+        subjects,
+        [
+            vec![("fr", "Bonjour".to_owned())],
+            vec![("fr", "Salut".to_owned())],
+            vec![]
+        ]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn lint() -> Result<()> {
+    let db = db().await?;
+    let templates = TemplateStore::new("templates");
+
+    let clean = EmailBuilder::new()
+        .sender_adresse("sender@example.com")?
+        .subject("News")
+        .plain_body(
+            r#"<p>Hello</p><img src="cid:logo" alt="Logo"><a href="https://example.com/unsubscribe">Unsubscribe</a>"#,
+        )
+        .create(&db)
+        .await?;
+    assert!(clean.lint(&templates).is_empty());
+
+    let broken = EmailBuilder::new()
+        .sender_adresse("sender@example.com")?
+        .plain_body(
+            r##"<p>Hello {{ name }}</p><img src="https://example.com/a.png"><a href="/news">News</a><a href="#">Top</a>"##,
+        )
+        .create(&db)
+        .await?;
+    let report = broken.lint(&templates);
+    let kinds: Vec<_> = report
+        .lints()
+        .iter()
+        .map(|lint| lint.kind().clone())
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            LintKind::EmptySubject,
+            LintKind::UnresolvedVariable {
+                name: "name".to_owned()
+            },
+            LintKind::RelativeLink {
+                href: "/news".to_owned()
+            },
+            LintKind::BrokenLink {
+                href: "#".to_owned()
+            },
+            LintKind::MissingAltText {
+                src: "https://example.com/a.png".to_owned()
+            },
+            LintKind::MissingUnsubscribeLink,
+        ]
+    );
+    assert_eq!(report.errors().count(), 4);
+    assert_eq!(report.warnings().count(), 2);
+
+    let template = EmailBuilder::new()
+        .sender_adresse("sender@example.com")?
+        .subject("Hello {{ client.adresse }}")
+        .template_body(
+            r#"{% for i in range(3) %}{{ i }}{% endfor %}{{ client.name }} {{ discount }}{% if not client.do_not_track %}<a href="{{ link }}">Se désabonner</a>{% endif %}"#,
+        )
+        .header("List-Unsubscribe", "<mailto:unsubscribe@example.com>")?
+        .create(&db)
+        .await?;
+    let kinds: Vec<_> = template
+        .lint(&templates)
+        .lints()
+        .iter()
+        .map(|lint| lint.kind().clone())
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            LintKind::UnresolvedVariable {
+                name: "client.name".to_owned()
+            },
+            LintKind::UnresolvedVariable {
+                name: "discount".to_owned()
+            },
+            LintKind::UnresolvedVariable {
+                name: "link".to_owned()
+            },
+        ]
+    );
+
+    // The HTML body is checked even when a text body is set
+    let with_text_body = EmailBuilder::new()
+        .sender_adresse("sender@example.com")?
+        .subject("News")
+        .plain_body(
+            r#"<p>Hello {{ name }}</p><a href="https://example.com/unsubscribe">Unsubscribe</a>"#,
+        )
+        .text_body("Hello")
+        .create(&db)
+        .await?;
+    let kinds: Vec<_> = with_text_body
+        .lint(&templates)
+        .lints()
+        .iter()
+        .map(|lint| lint.kind().clone())
+        .collect();
+    assert_eq!(
+        kinds,
+        [LintKind::UnresolvedVariable {
+            name: "name".to_owned()
+        }]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn build_then_create() -> Result<()> {
+    let db = db().await?;
+
+    let email = EmailBuilder::new()
+        .subject("Hello")
+        .markdown_body("# Hello")
+        .stylesheet("body { color: red; }")
+        .sender_adresse("sender@example.com")?
+        .build()?;
+
+    assert!(Email::get_one(email.id(), &db).await?.is_none());
+
+    email.create(&db).await?;
+    assert_round_trip(&email, &db).await?;
+
+    Ok(())
+}
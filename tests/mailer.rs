@@ -0,0 +1,299 @@
+use color_eyre::eyre::Result;
+use sequoia::client::{Client, Group};
+use sequoia::db::DB;
+use sequoia::email::{Email, EmailBuilder};
+use sequoia::mailer::{
+    dkim, Dkim, DkimAlgorithm, FileTransport, Mailer, MemoryTransport, SentMessage,
+};
+use sequoia::sender::{Sender, SmtpLogin};
+use sequoia::tracking::{ClickReport, OpenReport};
+
+async fn db() -> Result<DB> {
+    DB::connect_to(":memory:").await
+}
+
+async fn email(body: &str, db: &DB) -> Result<Email> {
+    EmailBuilder::new()
+        .sender_adresse("Bureau <bureau@example.com>")?
+        .subject("News")
+        .plain_body(body)
+        .track_opens()
+        .create(db)
+        .await
+}
+
+/// The message, with its quoted-printable parts decoded enough to look for tags.
+fn decoded(message: &SentMessage) -> String {
+    message.as_str().replace("=\r\n", "").replace("=3D", "=")
+}
+
+#[tokio::test]
+async fn send_to_group() -> Result<()> {
+    let db = db().await?;
+    let transport = MemoryTransport::new();
+    let mailer = Mailer::with_transport(transport.clone(), &db)?;
+
+    let mut group = Group::create("Newsletter".to_owned(), &db).await?;
+    for adresse in ["alice@example.com", "bob@example.com"] {
+        let client = Client::create(adresse, &db).await?;
+        group.add_client(client.id().to_owned(), &db).await?;
+    }
+
+    let email = email("<p>Hello</p>", &db).await?;
+    mailer.send(&email, &mut group.into()).await?;
+
+    let messages = transport.messages();
+    let mut recipients: Vec<_> = messages
+        .iter()
+        .flat_map(|message| message.recipients().to_vec())
+        .collect();
+    recipients.sort();
+    assert_eq!(recipients, ["alice@example.com", "bob@example.com"]);
+    assert!(messages[0]
+        .as_str()
+        .contains("From: Bureau <bureau@example.com>\r\n"));
+    assert!(messages[0].username().is_none());
+
+    // Without tracking, the body is left as is
+    assert!(!messages[0].as_str().contains("<img"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn group_members_are_emailed_once() -> Result<()> {
+    let db = db().await?;
+    let transport = MemoryTransport::new();
+    let mailer = Mailer::with_transport(transport.clone(), &db)?;
+
+    let mut group = Group::create("Newsletter".to_owned(), &db).await?;
+    let client = Client::create("alice@example.com", &db).await?;
+    group.add_client(client.id().to_owned(), &db).await?;
+    group
+        .add_clients(&[client.id().to_owned(), client.id().to_owned()], &db)
+        .await?;
+
+    let email = email("<p>Hello</p>", &db).await?;
+    mailer.send(&email, &mut group.into()).await?;
+
+    assert_eq!(transport.messages().len(), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn tracking_respects_do_not_track() -> Result<()> {
+    let db = db().await?;
+    let transport = MemoryTransport::new();
+    let mailer = Mailer::with_transport(transport.clone(), &db)?
+        .track_clicks("https://t.example.com")
+        .track_opens("https://t.example.com");
+
+    let tracked = Client::create("tracked@example.com", &db).await?;
+    let mut untracked = Client::create("untracked@example.com", &db).await?;
+    untracked.set_do_not_track(true, &db).await?;
+
+    let email = email(r#"<p><a href="https://example.com/">Shop</a></p>"#, &db).await?;
+    mailer.send(&email, &mut tracked.into()).await?;
+    mailer.send(&email, &mut untracked.into()).await?;
+
+    let messages: Vec<_> = transport.messages().iter().map(decoded).collect();
+    assert!(messages[0].contains(r#"<a href="https://t.example.com/c/"#));
+    assert!(messages[0].contains(r#"<img src="https://t.example.com/o/"#));
+    assert!(messages[1].contains(r#"<a href="https://example.com/">"#));
+    assert!(!messages[1].contains("t.example.com"));
+
+    assert_eq!(
+        OpenReport::for_email(email.id(), &db).await?.recipients(),
+        1
+    );
+    assert_eq!(
+        ClickReport::for_email(email.id(), &db).await?.links()[0].url(),
+        "https://example.com/"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn signature_and_text_links_are_tracked() -> Result<()> {
+    let db = db().await?;
+    let transport = MemoryTransport::new();
+    let mailer =
+        Mailer::with_transport(transport.clone(), &db)?.track_clicks("https://t.example.com");
+
+    let mut sender = Sender::create(Some("Bureau"), "bureau@example.com", &db).await?;
+    sender
+        .set_signature(
+            Some(r#"<p><a href="https://example.com/bureau">Le Bureau</a></p>"#),
+            &db,
+        )
+        .await?;
+    let email = EmailBuilder::new()
+        .sender(&sender)?
+        .subject("News")
+        .plain_body(r#"<p><a href="https://example.com/shop">Shop</a></p>"#)
+        .create(&db)
+        .await?;
+    let client = Client::create("client@example.com", &db).await?;
+    mailer.send(&email, &mut client.into()).await?;
+
+    // Neither the HTML nor the text part carries the original URLs
+    let message = decoded(&transport.messages()[0]);
+    assert!(!message.contains("https://example.com/"));
+    assert_eq!(message.matches("https://t.example.com/c/").count(), 4);
+
+    let report = ClickReport::for_email(email.id(), &db).await?;
+    let urls: Vec<_> = report.links().iter().map(|link| link.url()).collect();
+    assert_eq!(
+        urls,
+        ["https://example.com/shop", "https://example.com/bureau"]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn messages_are_signed() -> Result<()> {
+    let db = db().await?;
+    let transport = MemoryTransport::new();
+    let dkim = Dkim::new(
+        "mail",
+        "example.com",
+        DkimAlgorithm::Ed25519,
+        include_str!("fixtures/dkim/ed25519.pem"),
+    )?;
+    let record = dkim.dns_record();
+    let mailer = Mailer::with_transport(transport.clone(), &db)?.dkim(Some(dkim));
+
+    let client = Client::create("client@example.com", &db).await?;
+    let email = email("<p>Hello</p>", &db).await?;
+    mailer.send(&email, &mut client.into()).await?;
+
+    dkim::verify(transport.messages()[0].formatted(), &record)?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn sender_credentials_are_used() -> Result<()> {
+    let db = db().await?;
+    let transport = MemoryTransport::new();
+    let mailer = Mailer::with_transport(transport.clone(), &db)?;
+
+    let mut sender = Sender::create(Some("Bureau"), "bureau@example.com", &db).await?;
+    sender
+        .set_smtp_login(
+            Some(SmtpLogin::new("bureau", "SEQUOIA_TEST_MAILER_PASSWORD")),
+            &db,
+        )
+        .await?;
+    std::env::set_var("SEQUOIA_TEST_MAILER_PASSWORD", "secret");
+
+    let email = EmailBuilder::new()
+        .sender(&sender)?
+        .subject("News")
+        .plain_body("<p>Hello</p>")
+        .create(&db)
+        .await?;
+    let client = Client::create("client@example.com", &db).await?;
+    mailer.send(&email, &mut client.into()).await?;
+
+    assert_eq!(transport.messages()[0].username(), Some("bureau"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn messages_are_written_to_files() -> Result<()> {
+    let db = db().await?;
+    let root = std::env::temp_dir().join(format!("sequoia-{}", std::process::id()));
+    let client = Client::create("client@example.com", &db).await?;
+    let email = email("<p>Hello</p>", &db).await?;
+
+    let mailer = Mailer::with_transport(FileTransport::new(root.join("eml")), &db)?;
+    mailer.send(&email, &mut client.into()).await?;
+
+    let files: Vec<_> = std::fs::read_dir(root.join("eml"))?.collect::<Result<_, _>>()?;
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].path().extension().unwrap(), "eml");
+    let content = std::fs::read_to_string(files[0].path())?;
+    assert!(content.contains("To: client@example.com\r\n"));
+
+    let client = Client::create("maildir@example.com", &db).await?;
+    let mailer = Mailer::with_transport(FileTransport::maildir(root.join("Maildir")), &db)?;
+    mailer.send(&email, &mut client.into()).await?;
+
+    assert_eq!(std::fs::read_dir(root.join("Maildir/new"))?.count(), 1);
+    assert_eq!(std::fs::read_dir(root.join("Maildir/tmp"))?.count(), 0);
+    assert!(root.join("Maildir/cur").is_dir());
+
+    std::fs::remove_dir_all(root)?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn lint_errors_can_be_denied() -> Result<()> {
+    let db = db().await?;
+    let transport = MemoryTransport::new();
+    let alice = Client::create("alice@example.com", &db).await?;
+    let bob = Client::create("bob@example.com", &db).await?;
+    let email = email("<p>Hello {{ name }}</p>", &db).await?;
+
+    let mailer = Mailer::with_transport(transport.clone(), &db)?.deny_lint_errors(true);
+    assert!(mailer.send(&email, &mut alice.into()).await.is_err());
+    assert!(transport.messages().is_empty());
+
+    // Lint errors are only logged by default
+    let mailer = Mailer::with_transport(transport.clone(), &db)?;
+    mailer.send(&email, &mut bob.into()).await?;
+    assert_eq!(transport.messages().len(), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn copies_are_sent_once_and_untracked() -> Result<()> {
+    let db = db().await?;
+    let transport = MemoryTransport::new();
+    let mailer =
+        Mailer::with_transport(transport.clone(), &db)?.track_clicks("https://t.example.com");
+
+    let email = EmailBuilder::new()
+        .sender_adresse("Bureau <bureau@example.com>")?
+        .subject("News")
+        .plain_body(r#"<a href="https://example.com">Read</a>"#)
+        .cc("boss@example.com")?
+        .bcc("archive@example.com")?
+        .create(&db)
+        .await?;
+
+    let mut group = Group::create("Newsletter".to_owned(), &db).await?;
+    for adresse in ["alice@example.com", "bob@example.com"] {
+        let client = Client::create(adresse, &db).await?;
+        group.add_client(client.id().to_owned(), &db).await?;
+    }
+
+    // The CC and BCC would get a copy per member
+    assert!(mailer.send(&email, &mut group.into()).await.is_err());
+    assert!(transport.messages().is_empty());
+
+    let client = Client::create("carol@example.com", &db).await?;
+    mailer.send(&email, &mut client.into()).await?;
+
+    let messages = transport.messages();
+    let copies = messages
+        .iter()
+        .flat_map(|message| message.recipients().to_vec())
+        .filter(|recipient| recipient == "boss@example.com")
+        .count();
+    assert_eq!(copies, 1);
+    assert!(decoded(&messages[0]).contains(r#"href="https://example.com""#));
+    assert!(ClickReport::for_email(email.id(), &db)
+        .await?
+        .links()
+        .is_empty());
+
+    Ok(())
+}
@@ -0,0 +1,162 @@
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::Result;
+use rusqlite::Connection;
+use sequoia::db::DB;
+use sequoia::email::{Email, TagMatch};
+
+/// The schema of the first release, before any migration.
+const BASELINE_SCHEMA: &str = r"
+    CREATE TABLE Client (
+        ID       TEXT PRIMARY KEY,
+        adresse  TEXT NOT NULL
+        ) STRICT;
+
+    CREATE TABLE ClientGroup (
+        ID    TEXT PRIMARY KEY,
+        name  TEXT UNIQUE NOT NULL
+    ) STRICT;
+
+    CREATE TABLE MM_ClientGroupClient (
+        client_group_ID  TEXT,
+        client_ID        TEXT,
+        FOREIGN KEY(client_group_ID)  REFERENCES ClientGroup(ID)
+            ON UPDATE CASCADE
+            ON DELETE CASCADE,
+        FOREIGN KEY(client_ID)        REFERENCES Client(ID)
+            ON UPDATE CASCADE
+            ON DELETE CASCADE
+    ) STRICT;
+
+    CREATE TABLE PlainEmail (
+        ID       TEXT PRIMARY KEY,
+        subject  TEXT,
+        body     TEXT
+    ) STRICT;
+
+    CREATE TABLE TemplateEmail (
+        ID           TEXT PRIMARY KEY,
+        subject      TEXT,
+        body         TEXT,
+        source_path  TEXT
+    ) STRICT;
+
+    CREATE TABLE Email (
+        ID                  TEXT PRIMARY KEY,
+        sender_adresse      TEXT,
+        tags                TEXT,
+        email_discriminant  INTEGER CHECK(email_discriminant IN (0, 1)),
+        plain_email_ID      TEXT,
+        template_email_ID   TEXT,
+        FOREIGN KEY (plain_email_ID)     REFERENCES PlainEmail(ID)
+            ON UPDATE CASCADE
+            ON DELETE CASCADE,
+        FOREIGN KEY (template_email_ID)  REFERENCES TemplateEmail(ID)
+            ON UPDATE CASCADE
+            ON DELETE CASCADE
+    ) STRICT;
+
+    CREATE TABLE MM_EmailClient (
+        email_ID   TEXT,
+        client_ID  TEXT,
+        timestamp  INTEGER,
+        FOREIGN KEY(email_ID)  REFERENCES Email(ID)
+            ON UPDATE CASCADE
+            ON DELETE CASCADE,
+        FOREIGN KEY(client_ID)  REFERENCES Client(ID)
+            ON UPDATE CASCADE
+            ON DELETE CASCADE
+    ) STRICT;
+
+    CREATE TABLE MM_EmailClientGroup (
+        email_ID         TEXT,
+        client_group_ID  TEXT,
+        timestamp        INTEGER,
+        FOREIGN KEY(email_ID)  REFERENCES Email(ID)
+            ON UPDATE CASCADE
+            ON DELETE CASCADE,
+        FOREIGN KEY(client_group_ID)  REFERENCES ClientGroup(ID)
+            ON UPDATE CASCADE
+            ON DELETE CASCADE
+    ) STRICT;
+";
+
+fn db_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("sequoia-{name}-{}.db", std::process::id()))
+}
+
+fn user_version(path: &Path) -> Result<u32> {
+    Ok(Connection::open(path)?.query_row("PRAGMA user_version", [], |row| row.get(0))?)
+}
+
+#[tokio::test]
+async fn baseline_databases_are_migrated() -> Result<()> {
+    let path = db_path("baseline");
+    let _ = std::fs::remove_file(&path);
+
+    let conn = Connection::open(&path)?;
+    conn.execute_batch(BASELINE_SCHEMA)?;
+    conn.execute_batch(
+        r"
+        INSERT INTO Client (ID, adresse) VALUES ('alice', 'alice@example.com');
+        INSERT INTO PlainEmail (ID, subject, body) VALUES ('plain', 'News', '<p>Hello</p>');
+        INSERT INTO TemplateEmail (ID, subject, body, source_path) VALUES ('template', 'Welcome', '', 'welcome.html');
+        INSERT INTO Email (ID, sender_adresse, tags, email_discriminant, plain_email_ID)
+            VALUES ('news', 'bureau@example.com', 'a$b', 0, 'plain');
+        INSERT INTO Email (ID, sender_adresse, tags, email_discriminant, template_email_ID)
+            VALUES ('welcome', 'bureau@example.com', 'b', 1, 'template');
+        INSERT INTO MM_EmailClient (email_ID, client_ID, timestamp) VALUES ('news', 'alice', 0);
+    ",
+    )?;
+    drop(conn);
+
+    let db = DB::connect_to(path.to_str().unwrap()).await?;
+
+    let news = Email::get_one("news", &db).await?.unwrap();
+    assert_eq!(news.tags(), ["a", "b"]);
+    assert_eq!(news.body(), "<p>Hello</p>");
+    assert_eq!(news.revision(), 1);
+
+    let mut tagged: Vec<_> = Email::list_by_tags(&["b"], TagMatch::Any, &db)
+        .await?
+        .into_iter()
+        .map(|email| email.id().to_owned())
+        .collect();
+    tagged.sort();
+    assert_eq!(tagged, ["news", "welcome"]);
+    drop(db);
+
+    let conn = Connection::open(&path)?;
+    let count = |sql: &str| -> Result<u32> { Ok(conn.query_row(sql, [], |row| row.get(0))?) };
+
+    assert_eq!(count("SELECT COUNT(*) FROM Tag")?, 2);
+    assert_eq!(count("SELECT COUNT(*) FROM MM_EmailTag")?, 3);
+    assert_eq!(
+        count("SELECT COUNT(*) FROM pragma_table_info('Email') WHERE name = 'tags'")?,
+        0
+    );
+
+    // The rebuilt `Email` table accepts Markdown emails and kept the rows referencing it
+    let email_schema: String = conn.query_row(
+        "SELECT sql FROM sqlite_master WHERE name = 'Email'",
+        [],
+        |row| row.get(0),
+    )?;
+    assert!(email_schema.contains("IN (0, 1, 2)"));
+    assert_eq!(count("SELECT COUNT(*) FROM Email")?, 2);
+    assert_eq!(count("SELECT COUNT(*) FROM MM_EmailClient")?, 1);
+    assert_eq!(count("SELECT COUNT(*) FROM pragma_foreign_key_check")?, 0);
+    drop(conn);
+
+    // Every migration was applied, as on a new database
+    let new_path = db_path("new");
+    let _ = std::fs::remove_file(&new_path);
+    drop(DB::connect_to(new_path.to_str().unwrap()).await?);
+    assert_eq!(user_version(&path)?, user_version(&new_path)?);
+    assert!(user_version(&path)? > 0);
+
+    std::fs::remove_file(path)?;
+    std::fs::remove_file(new_path)?;
+
+    Ok(())
+}
@@ -0,0 +1,93 @@
+use color_eyre::eyre::Result;
+use sequoia::client::{Client, Group};
+use sequoia::db::DB;
+use sequoia::mailer::Receiver;
+
+async fn db() -> Result<DB> {
+    DB::connect_to(":memory:").await
+}
+
+async fn group(name: &str, members: &[&Client], db: &DB) -> Result<Group> {
+    let mut group = Group::create(name.to_owned(), db).await?;
+    let ids: Vec<String> = members
+        .iter()
+        .map(|client| client.id().to_owned())
+        .collect();
+    group.add_clients(&ids, db).await?;
+
+    Ok(group)
+}
+
+/// Another handle on `client`, since a receiver takes ownership of it.
+async fn reload(client: &Client, db: &DB) -> Result<Client> {
+    Ok(Client::get_one(client.id().to_owned(), db).await?.unwrap())
+}
+
+/// Adresses of the clients resolved from `receiver`, in a stable order.
+async fn adresses(receiver: &Receiver, page_size: usize, db: &DB) -> Result<Vec<String>> {
+    let mut recipients = receiver.recipients(page_size, db).await?;
+    let mut adresses = Vec::new();
+
+    while let Some(page) = recipients.next_page(db).await? {
+        assert!(page.len() <= page_size);
+        adresses.extend(page.iter().map(|client| client.adresse().to_string()));
+    }
+
+    adresses.sort();
+    Ok(adresses)
+}
+
+#[tokio::test]
+async fn set_algebra() -> Result<()> {
+    let db = db().await?;
+    let alice = Client::create("alice@example.com", &db).await?;
+    let bob = Client::create("bob@example.com", &db).await?;
+    let carol = Client::create("carol@example.com", &db).await?;
+
+    let news = group("News", &[&alice, &bob], &db).await?;
+    let offers = group("Offers", &[&bob, &carol], &db).await?;
+    let unsubscribed = group("Unsubscribed", &[&carol], &db).await?;
+    let (news_id, offers_id) = (news.id().to_owned(), offers.id().to_owned());
+    let unsubscribed_id = unsubscribed.id().to_owned();
+
+    // A client in both groups is only resolved once
+    let union = Receiver::from(news) | offers | reload(&alice, &db).await?;
+    assert_eq!(
+        adresses(&union, 2, &db).await?,
+        ["alice@example.com", "bob@example.com", "carol@example.com"]
+    );
+
+    let news = Group::get_one(&news_id, &db).await?.unwrap();
+    let offers = Group::get_one(&offers_id, &db).await?.unwrap();
+    let intersection = Receiver::from(news) & offers;
+    assert_eq!(adresses(&intersection, 2, &db).await?, ["bob@example.com"]);
+
+    let offers = Group::get_one(&offers_id, &db).await?.unwrap();
+    let exclusion = Receiver::from(offers) - unsubscribed;
+    assert_eq!(adresses(&exclusion, 2, &db).await?, ["bob@example.com"]);
+    assert_eq!(
+        exclusion.to_string(),
+        format!("(group:{offers_id} - group:{unsubscribed_id})")
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn groups_are_streamed_in_pages() -> Result<()> {
+    let db = db().await?;
+    let mut clients = Vec::new();
+    for i in 0..5 {
+        clients.push(Client::create(&format!("client{i}@example.com"), &db).await?);
+    }
+    let members: Vec<&Client> = clients.iter().collect();
+    let group = group("Everyone", &members, &db).await?;
+
+    let receiver = Receiver::from(group);
+    let adresses = adresses(&receiver, 2, &db).await?;
+
+    assert_eq!(adresses.len(), 5);
+    assert_eq!(adresses[0], "client0@example.com");
+
+    Ok(())
+}
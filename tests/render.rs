@@ -0,0 +1,165 @@
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use chrono::Local;
+use color_eyre::eyre::Result;
+use sequoia::client::Client;
+use sequoia::db::DB;
+use sequoia::email::{EmailBuilder, RenderContext, TemplateStore};
+
+async fn db() -> Result<DB> {
+    DB::connect_to(":memory:").await
+}
+
+/// An empty template root, unique to the test `name`.
+fn root(name: &str) -> Result<PathBuf> {
+    let root = std::env::temp_dir().join(format!("sequoia-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root)?;
+
+    Ok(root)
+}
+
+fn write(root: &Path, name: &str, source: &str) -> Result<()> {
+    let path = root.join(name);
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(path, source)?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn layouts_and_partials_are_resolved() -> Result<()> {
+    let db = db().await?;
+    let root = root("layouts")?;
+    write(
+        &root,
+        "layouts/base.html",
+        "<main>{% block content %}{% endblock %}</main>{% include \"partials/footer.html\" %}",
+    )?;
+    write(&root, "partials/footer.html", "<footer>Bureau</footer>")?;
+    write(
+        &root,
+        "news/body.html",
+        "{% extends \"layouts/base.html\" %}{% block content %}Hello {{ client.adresse }}{% endblock %}",
+    )?;
+
+    let email = EmailBuilder::new()
+        .sender_adresse("Bureau <bureau@example.com>")?
+        .subject("News")
+        .template_path("news/body.html")
+        .create(&db)
+        .await?;
+    let client = Client::create("alice@example.com", &db).await?;
+
+    let templates = TemplateStore::new(&root);
+    let rendered = email
+        .renderer(&templates)?
+        .render(&RenderContext::new(&client, Local::now()))?;
+
+    assert_eq!(
+        rendered.body,
+        "<main>Hello alice@example.com</main><footer>Bureau</footer>"
+    );
+
+    fs::remove_dir_all(root)?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn unsubscribe_links_of_layouts_are_found() -> Result<()> {
+    let db = db().await?;
+    let root = root("lint-layouts")?;
+    write(
+        &root,
+        "layouts/base.html",
+        "<main>{% block content %}{% endblock %}</main>{% include 'partials/footer.html' %}",
+    )?;
+    write(
+        &root,
+        "partials/footer.html",
+        r#"<footer><a href="https://example.com/unsubscribe">Unsubscribe</a></footer>"#,
+    )?;
+    write(
+        &root,
+        "news/body.html",
+        "{% extends \"layouts/base.html\" %}{% block content %}Hello{% endblock %}",
+    )?;
+
+    let email = EmailBuilder::new()
+        .sender_adresse("Bureau <bureau@example.com>")?
+        .subject("News")
+        .template_path("news/body.html")
+        .create(&db)
+        .await?;
+
+    assert!(email.lint(&TemplateStore::new(&root)).is_empty());
+
+    fs::remove_dir_all(root)?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn group_and_generation_are_rendered() -> Result<()> {
+    let db = db().await?;
+    let email = EmailBuilder::new()
+        .sender_adresse("Bureau <bureau@example.com>")?
+        .subject("Issue {{ generation }}")
+        .template_body("<p>{{ group }}</p>")
+        .create(&db)
+        .await?;
+    let client = Client::create("alice@example.com", &db).await?;
+
+    let context = RenderContext::new(&client, Local::now())
+        .group(Some("Newsletter"))
+        .generation(Some(3));
+    let rendered = email
+        .renderer(&TemplateStore::new("templates"))?
+        .render(&context)?;
+
+    assert_eq!(rendered.subject, "Issue 3");
+    assert_eq!(rendered.body, "<p>Newsletter</p>");
+
+    Ok(())
+}
+
+#[test]
+fn paths_escaping_the_root_are_rejected() -> Result<()> {
+    let root = root("escape")?;
+    write(&root, "body.html", "Hello")?;
+    let templates = TemplateStore::new(root.join("emails"));
+
+    assert!(templates.load("../body.html").is_err());
+    assert!(templates
+        .load(root.join("body.html").to_str().unwrap())
+        .is_err());
+
+    fs::remove_dir_all(root)?;
+
+    Ok(())
+}
+
+#[test]
+fn edited_templates_are_reloaded() -> Result<()> {
+    let root = root("reload")?;
+    write(&root, "body.html", "First")?;
+    let templates = TemplateStore::new(&root);
+
+    assert_eq!(&*templates.load("body.html")?, "First");
+
+    write(&root, "body.html", "Second")?;
+    // The modification time may not change within the resolution of the file system
+    File::options()
+        .write(true)
+        .open(root.join("body.html"))?
+        .set_modified(SystemTime::now() + Duration::from_secs(60))?;
+
+    assert_eq!(&*templates.load("body.html")?, "Second");
+
+    fs::remove_dir_all(root)?;
+
+    Ok(())
+}
@@ -0,0 +1,64 @@
+use std::fs::{self, File};
+use std::time::{Duration, SystemTime};
+
+use color_eyre::eyre::Result;
+use sequoia::client::Client;
+use sequoia::db::DB;
+use sequoia::email::{Email, EmailBuilder, TemplateStore};
+use sequoia::mailer::{Mailer, MemoryTransport};
+
+#[tokio::test]
+async fn revisions_record_the_template_files_sent() -> Result<()> {
+    let root = std::env::temp_dir().join(format!("sequoia-revisions-{}", std::process::id()));
+    fs::create_dir_all(root.join("news"))?;
+    fs::write(root.join("news/body.html"), "<p>First</p>")?;
+
+    let db = DB::connect_to(":memory:").await?;
+    let transport = MemoryTransport::new();
+    let mailer =
+        Mailer::with_transport(transport.clone(), &db)?.templates(TemplateStore::new(&root));
+    let mut client = Client::create("alice@example.com", &db).await?.into();
+
+    let mut email = EmailBuilder::new()
+        .sender_adresse("Bureau <bureau@example.com>")?
+        .subject("News")
+        .template_path("news/body.html")
+        .draft()
+        .create(&db)
+        .await?;
+    email.publish(&db).await?;
+
+    // The file isn't read until the email is sent
+    assert_eq!(email.revisions(&db).await?[0].body(), "");
+
+    mailer.send(&email, &mut client).await?;
+    mailer.send(&email, &mut client).await?;
+
+    // Edited after the revision was sent, so the next send makes a new revision
+    fs::write(root.join("news/body.html"), "<p>Second</p>")?;
+    // The modification time may not change within the resolution of the file system
+    File::options()
+        .write(true)
+        .open(root.join("news/body.html"))?
+        .set_modified(SystemTime::now() + Duration::from_secs(60))?;
+    mailer.send(&email, &mut client).await?;
+
+    let messages = transport.messages();
+    assert_eq!(messages.len(), 3);
+    assert!(messages[1].as_str().contains("<p>First</p>"));
+    assert!(messages[2].as_str().contains("<p>Second</p>"));
+
+    let revisions = email.revisions(&db).await?;
+    let bodies: Vec<_> = revisions.iter().map(|revision| revision.body()).collect();
+    assert_eq!(bodies, ["<p>First</p>", "<p>Second</p>"]);
+    assert!(revisions
+        .iter()
+        .all(|revision| revision.source_path() == Some("news/body.html")));
+
+    let loaded = Email::get_one(email.id(), &db).await?.unwrap();
+    assert_eq!(loaded.revision(), 2);
+
+    fs::remove_dir_all(root)?;
+
+    Ok(())
+}
@@ -0,0 +1,101 @@
+use std::time::Duration;
+
+use chrono::{Datelike, Local, TimeDelta, Timelike};
+use color_eyre::eyre::Result;
+use sequoia::client::{Client, Group};
+use sequoia::db::DB;
+use sequoia::email::EmailBuilder;
+use sequoia::mailer::{AbTest, Mailer, MemoryTransport};
+use sequoia::scheduler::trigger::{DatetimeTrigger, NaiveTime, PartialDate};
+use sequoia::scheduler::Scheduler;
+
+#[tokio::test(start_paused = true)]
+async fn scheduled_emails_expose_the_generation() -> Result<()> {
+    let db: &'static DB = Box::leak(Box::new(DB::connect_to(":memory:").await?));
+    let transport = MemoryTransport::new();
+    let mut scheduler = Scheduler::new(Mailer::with_transport(transport.clone(), db)?);
+
+    let email = EmailBuilder::new()
+        .sender_adresse("Bureau <bureau@example.com>")?
+        .subject("Issue {{ generation }}")
+        .template_body("<p>Hello</p>")
+        .create(db)
+        .await?;
+    let client = Client::create("alice@example.com", db).await?;
+
+    let at = Local::now() + TimeDelta::minutes(1);
+    let trigger = DatetimeTrigger::new(
+        PartialDate::new_y(at.year() as u32),
+        NaiveTime::from_hms_opt(at.hour(), at.minute(), at.second()).unwrap(),
+    );
+    scheduler.schedule_email(trigger.into(), email, client.into());
+
+    // Time is paused, so sleeping skips ahead to the trigger
+    for _ in 0..120 {
+        if !transport.messages().is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+
+    let messages = transport.messages();
+    assert_eq!(messages.len(), 1);
+    assert!(messages[0].as_str().contains("Subject: Issue 0\r\n"));
+
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn ab_test_remainders_are_scheduled() -> Result<()> {
+    let db: &'static DB = Box::leak(Box::new(DB::connect_to(":memory:").await?));
+    let transport = MemoryTransport::new();
+    let mailer = Mailer::with_transport(transport.clone(), db)?;
+
+    let mut group = Group::create("Newsletter".to_owned(), db).await?;
+    let mut client_ids = Vec::new();
+    for i in 0..20 {
+        client_ids.push(
+            Client::create(&format!("client{i}@example.com"), db)
+                .await?
+                .id()
+                .to_owned(),
+        );
+    }
+    group.add_clients(&client_ids, db).await?;
+
+    let mut variants = Vec::new();
+    for subject in ["A", "B"] {
+        variants.push(
+            EmailBuilder::new()
+                .sender_adresse("Bureau <bureau@example.com>")?
+                .subject(subject)
+                .template_body("<p>Hello</p>")
+                .create(db)
+                .await?,
+        );
+    }
+    let winner = variants[1].id().to_owned();
+    let mut ab_test = AbTest::create(group, variants, 42, 50, Some(Duration::ZERO), db).await?;
+    mailer.send_ab_test(&mut ab_test).await?;
+    let sample = transport.messages().len();
+    assert!(sample < client_ids.len());
+
+    let mut scheduler = Scheduler::new(mailer);
+    scheduler.schedule_ab_test_remainder(ab_test, move |_| Some(winner.clone()))?;
+
+    // Time is paused, so sleeping skips ahead to the trigger
+    for _ in 0..10 {
+        if transport.messages().len() > sample {
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+
+    let messages = transport.messages();
+    assert_eq!(messages.len(), client_ids.len());
+    assert!(messages[sample..]
+        .iter()
+        .all(|message| message.as_str().contains("Subject: B\r\n")));
+
+    Ok(())
+}
@@ -0,0 +1,123 @@
+use color_eyre::eyre::Result;
+use sequoia::client::Client;
+use sequoia::db::DB;
+use sequoia::email::{Email, EmailBuilder, TemplateStore};
+use sequoia::sender::{Sender, SmtpLogin};
+
+async fn db() -> Result<DB> {
+    DB::connect_to(":memory:").await
+}
+
+#[tokio::test]
+async fn sender_round_trip() -> Result<()> {
+    let db = db().await?;
+
+    let mut sender = Sender::create(Some("Bureau"), "bureau@example.com", &db).await?;
+    sender
+        .set_reply_to(Some("Tarak <tarak@example.com>"), &db)
+        .await?;
+    sender
+        .set_signature(Some("<p>Le <b>Bureau</b></p>"), &db)
+        .await?;
+    sender
+        .set_smtp_login(
+            Some(SmtpLogin::new("bureau", "SEQUOIA_TEST_BUREAU_PASSWORD")),
+            &db,
+        )
+        .await?;
+
+    let loaded = Sender::get_one(sender.id(), &db)
+        .await?
+        .expect("sender is saved");
+    assert_eq!(loaded.name(), Some("Bureau"));
+    assert_eq!(loaded.adresse(), "bureau@example.com");
+    assert_eq!(loaded.reply_to(), sender.reply_to());
+    assert_eq!(loaded.signature(), Some("<p>Le <b>Bureau</b></p>"));
+    let smtp_login = loaded.smtp_login().expect("SMTP login is saved");
+    assert_eq!(smtp_login.username(), "bureau");
+    assert_eq!(smtp_login.password_env(), "SEQUOIA_TEST_BUREAU_PASSWORD");
+
+    // The password is only read from the environment
+    assert!(loaded.credentials().is_err());
+    std::env::set_var("SEQUOIA_TEST_BUREAU_PASSWORD", "secret");
+    let credentials = loaded.credentials()?.expect("credentials are read");
+    assert_eq!(credentials.username(), "bureau");
+    assert_eq!(credentials.password(), "secret");
+
+    assert_eq!(Sender::list(&db).await?.len(), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn email_sent_as_sender() -> Result<()> {
+    let db = db().await?;
+
+    let mut sender = Sender::create(Some("Bureau"), "bureau@example.com", &db).await?;
+    sender.set_reply_to(Some("tarak@example.com"), &db).await?;
+    sender
+        .set_signature(Some("<p>Le <b>Bureau</b></p>"), &db)
+        .await?;
+
+    let email = EmailBuilder::new()
+        .sender(&sender)?
+        .subject("News")
+        .plain_body("<html><body><p>Hello</p></body></html>")
+        .create(&db)
+        .await?;
+
+    let loaded = Email::get_one(email.id(), &db)
+        .await?
+        .expect("email is saved");
+    assert_eq!(loaded.sender().map(Sender::id), Some(sender.id()));
+    assert_eq!(loaded.sender_adresse(), "bureau@example.com");
+
+    let client = Client::create("client@example.com", &db).await?;
+    let message = loaded.render_for(&client, &TemplateStore::new("templates"))?;
+    let formatted = String::from_utf8(message.formatted())?;
+    assert!(formatted.contains("From: Bureau <bureau@example.com>"));
+    assert!(formatted.contains("Reply-To: tarak@example.com"));
+    assert!(formatted.contains("<p>Le <b>Bureau</b></p>\r\n</body>"));
+    assert!(formatted.contains("\r\n-- \r\nLe **Bureau**"));
+
+    // The email keeps its adresse when the sender is deleted
+    sender.delete(&db).await?;
+    let loaded = Email::get_one(email.id(), &db)
+        .await?
+        .expect("email is kept");
+    assert!(loaded.sender().is_none());
+    assert_eq!(loaded.sender_adresse(), "bureau@example.com");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn rename_sender() -> Result<()> {
+    let db = db().await?;
+
+    let mut sender = Sender::create(Some("Burau"), "burau@example.com", &db).await?;
+    let email = EmailBuilder::new()
+        .sender(&sender)?
+        .subject("News")
+        .plain_body("<p>Hello</p>")
+        .create(&db)
+        .await?;
+
+    sender.set_name(Some("Bureau"), &db).await?;
+    sender.set_adresse("bureau@example.com", &db).await?;
+    assert!(sender.set_adresse("not an adresse", &db).await.is_err());
+
+    let loaded = Sender::get_one(sender.id(), &db)
+        .await?
+        .expect("sender is saved");
+    assert_eq!(loaded.name(), Some("Bureau"));
+    assert_eq!(loaded.adresse(), "bureau@example.com");
+
+    // Its emails are sent from the new adresse
+    let loaded = Email::get_one(email.id(), &db)
+        .await?
+        .expect("email is saved");
+    assert_eq!(loaded.sender_adresse(), "bureau@example.com");
+
+    Ok(())
+}
@@ -0,0 +1,131 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::thread;
+
+use color_eyre::eyre::Result;
+use lettre::Message;
+use sequoia::mailer::{AuthMechanism, MailTransport, SmtpConfig, SmtpRelay, TlsMode};
+
+/// Accept one connection on `listener` and answer it like a relay without TLS nor login,
+/// returning the commands and the first message received.
+fn sink(listener: TcpListener) -> thread::JoinHandle<Vec<String>> {
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        let mut lines = Vec::new();
+        let mut in_data = false;
+
+        writer.write_all(b"220 sink ESMTP\r\n").unwrap();
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap() == 0 {
+                break;
+            }
+            let line = line.trim_end().to_owned();
+            lines.push(line.clone());
+
+            let reply: &[u8] = if in_data {
+                if line != "." {
+                    continue;
+                }
+                // The pool keeps the connection open, so the sink stops at the first message
+                writer.write_all(b"250 queued\r\n").unwrap();
+                break;
+            } else if line.starts_with("EHLO") {
+                b"250-sink\r\n250 8BITMIME\r\n"
+            } else if line == "DATA" {
+                in_data = true;
+                b"354 go ahead\r\n"
+            } else if line == "QUIT" {
+                writer.write_all(b"221 bye\r\n").unwrap();
+                break;
+            } else {
+                b"250 ok\r\n"
+            };
+            writer.write_all(reply).unwrap();
+        }
+
+        lines
+    })
+}
+
+#[test]
+fn settings_are_parsed() -> Result<()> {
+    assert_eq!("implicit".parse::<TlsMode>()?, TlsMode::Implicit);
+    assert_eq!("STARTTLS".parse::<TlsMode>()?, TlsMode::StartTls);
+    assert_eq!("none".parse::<TlsMode>()?, TlsMode::None);
+    assert!("ssl".parse::<TlsMode>().is_err());
+
+    assert_eq!("plain".parse::<AuthMechanism>()?, AuthMechanism::Plain);
+    assert_eq!("Login".parse::<AuthMechanism>()?, AuthMechanism::Login);
+    assert_eq!("xoauth2".parse::<AuthMechanism>()?, AuthMechanism::Xoauth2);
+    assert!("cram-md5".parse::<AuthMechanism>().is_err());
+
+    let config = SmtpConfig::new("relay.example.com");
+    assert_eq!(config.tls_mode(), TlsMode::StartTls);
+    assert_eq!(config.port_or_default(), 587);
+    assert_eq!(config.clone().tls(TlsMode::Implicit).port_or_default(), 465);
+    assert_eq!(config.clone().tls(TlsMode::None).port_or_default(), 25);
+    assert_eq!(config.port(2525).port_or_default(), 2525);
+
+    Ok(())
+}
+
+#[test]
+fn missing_host_falls_back_to_the_deprecated_default() -> Result<()> {
+    // No other test of this binary reads the environment
+    for name in ["SMTP_HOST", "SMTP_PORT", "SMTP_TLS"] {
+        std::env::remove_var(name);
+    }
+
+    let config = SmtpConfig::from_env()?;
+    assert_eq!(config.host(), "smtp.gmail.com");
+    assert_eq!(config.tls_mode(), TlsMode::Implicit);
+    assert_eq!(config.port_or_default(), 465);
+
+    Ok(())
+}
+
+#[test]
+fn messages_are_sent_to_the_configured_relay() -> Result<()> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let port = listener.local_addr()?.port();
+    let sink = sink(listener);
+
+    let relay = SmtpRelay::new(
+        SmtpConfig::new("127.0.0.1")
+            .port(port)
+            .tls(TlsMode::None)
+            .pool_size(1)
+            .helo_name("sequoia.example.com"),
+    )?;
+
+    let message = Message::builder()
+        .from("Bureau <bureau@example.com>".parse()?)
+        .to("client@example.com".parse()?)
+        .subject("News")
+        .body("Hello".to_owned())?;
+    relay.send(&message, None)?;
+
+    let lines = sink.join().unwrap();
+    assert_eq!(lines[0], "EHLO sequoia.example.com");
+    assert!(lines.contains(&"MAIL FROM:<bureau@example.com>".to_owned()));
+    assert!(lines.contains(&"RCPT TO:<client@example.com>".to_owned()));
+    assert!(lines.contains(&"Subject: News".to_owned()));
+    assert!(lines.contains(&"Hello".to_owned()));
+
+    Ok(())
+}
+
+#[test]
+fn unreachable_relay_fails_the_connection_test() -> Result<()> {
+    // The port is free once the listener is dropped
+    let port = TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+
+    let relay = SmtpRelay::new(SmtpConfig::new("127.0.0.1").port(port).tls(TlsMode::None))?;
+
+    assert!(relay.test_connection().is_err());
+
+    Ok(())
+}
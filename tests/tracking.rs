@@ -0,0 +1,265 @@
+use color_eyre::eyre::Result;
+use sequoia::client::Client;
+use sequoia::db::DB;
+use sequoia::email::{Email, EmailBuilder};
+use sequoia::tracking::{self, ClickReport, Delivery, OpenReport};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const BASE_URL: &str = "https://t.example.com/";
+
+async fn db() -> Result<&'static DB> {
+    Ok(Box::leak(Box::new(DB::connect_to(":memory:").await?)))
+}
+
+async fn email(db: &DB) -> Result<Email> {
+    EmailBuilder::new()
+        .sender_adresse("sender@example.com")?
+        .subject("News")
+        .plain_body("Hello")
+        .create(db)
+        .await
+}
+
+/// Serve the tracking endpoints on a free port and return its address.
+async fn serve(db: &'static DB) -> Result<String> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let address = listener.local_addr()?.to_string();
+    tokio::spawn(tracking::serve(listener, db));
+
+    Ok(address)
+}
+
+/// Send a request to the tracking server at `address` and return the response.
+async fn request(address: &str, method: &str, path: &str) -> Result<String> {
+    let mut stream = TcpStream::connect(address).await?;
+    stream
+        .write_all(format!("{method} {path} HTTP/1.1\r\nHost: t.example.com\r\n\r\n").as_bytes())
+        .await?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+
+    Ok(String::from_utf8_lossy(&response).into_owned())
+}
+
+#[tokio::test]
+async fn links_are_rewritten() -> Result<()> {
+    let db = db().await?;
+    let email = email(db).await?;
+    let client = Client::create("client@example.com", db).await?;
+
+    let mut delivery = Delivery::new(&email, &client);
+    let html = delivery.track_links(
+        r##"<p><a href="https://example.com/?a=1&amp;b=2">Shop</a>
+        <a class="x" href='HTTP://example.com/news'>News</a>
+        <a href="mailto:bureau@example.com">Mail</a> <a href="#top">Top</a>
+        <a data-href="https://example.com/old" href="https://example.com/sale">Sale</a></p>"##,
+        BASE_URL,
+    )?;
+
+    let token = delivery.token();
+    assert!(html.contains(&format!(r#"<a href="https://t.example.com/c/{token}/0">"#)));
+    assert!(html.contains(&format!(
+        r#"<a class="x" href='https://t.example.com/c/{token}/1'>"#
+    )));
+    assert!(html.contains(r#"href="mailto:bureau@example.com""#));
+    assert!(html.contains(r##"href="#top""##));
+    // Only the `href` is tracked, not an attribute ending with it
+    assert!(html.contains(&format!(
+        r#"<a data-href="https://example.com/old" href="https://t.example.com/c/{token}/2">"#
+    )));
+    assert_eq!(
+        delivery.links(),
+        [
+            "https://example.com/?a=1&b=2",
+            "HTTP://example.com/news",
+            "https://example.com/sale"
+        ]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn text_links_are_rewritten() -> Result<()> {
+    let db = db().await?;
+    let email = email(db).await?;
+    let client = Client::create("client@example.com", db).await?;
+
+    let mut delivery = Delivery::new(&email, &client);
+    delivery.track_links(r#"<a href="https://example.com/shop">Shop</a>"#, BASE_URL)?;
+    let text = delivery.track_text_links(
+        "Shop [Shop](https://example.com/shop). News: https://example.com/news.\n\
+        Mail bureau@example.com",
+        BASE_URL,
+    );
+
+    // The link of the HTML body keeps its position
+    let token = delivery.token();
+    assert_eq!(
+        text,
+        format!(
+            "Shop [Shop](https://t.example.com/c/{token}/0). News: https://t.example.com/c/{token}/1.\n\
+            Mail bureau@example.com"
+        )
+    );
+    assert_eq!(
+        delivery.links(),
+        ["https://example.com/shop", "https://example.com/news"]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn clicks_are_recorded_and_redirected() -> Result<()> {
+    let db = db().await?;
+    let email = email(db).await?;
+    let alice = Client::create("alice@example.com", db).await?;
+    let bob = Client::create("bob@example.com", db).await?;
+
+    let html = r#"<a href="https://example.com/shop">Shop</a> <a href="https://example.com/news">News</a>"#;
+    let mut tokens = Vec::new();
+    for client in [&alice, &bob] {
+        let mut delivery = Delivery::new(&email, client);
+        delivery.track_links(html, BASE_URL)?;
+        delivery.write(0, db).await?;
+        tokens.push(delivery.token().to_owned());
+    }
+
+    let address = serve(db).await?;
+
+    let response = request(&address, "GET", &format!("/c/{}/0", tokens[0])).await?;
+    assert!(response.starts_with("HTTP/1.1 302 Found\r\n"));
+    assert!(response.contains("\r\nLocation: https://example.com/shop\r\n"));
+
+    request(&address, "GET", &format!("/c/{}/0?utm=x", tokens[0])).await?;
+    request(&address, "GET", &format!("/c/{}/0", tokens[1])).await?;
+    request(&address, "GET", &format!("/c/{}/1", tokens[1])).await?;
+
+    // Checking where a link leads isn't a click
+    let response = request(&address, "HEAD", &format!("/c/{}/1", tokens[0])).await?;
+    assert!(response.contains("\r\nLocation: https://example.com/news\r\n"));
+
+    for path in [
+        format!("/c/{}/2", tokens[0]),
+        "/c/unknown/0".to_owned(),
+        "/".to_owned(),
+    ] {
+        let response = request(&address, "GET", &path).await?;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"), "{path}");
+    }
+
+    let report = ClickReport::for_email(email.id(), db).await?;
+    assert_eq!(report.clicks(), 4);
+    assert_eq!(report.clickers(), 2);
+
+    let links: Vec<_> = report
+        .links()
+        .iter()
+        .map(|link| (link.url(), link.clicks(), link.clickers()))
+        .collect();
+    assert_eq!(
+        links,
+        [
+            ("https://example.com/shop", 3, 2),
+            ("https://example.com/news", 1, 1)
+        ]
+    );
+
+    let reports = ClickReport::list(db).await?;
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].email_id(), email.id());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn opens_are_recorded() -> Result<()> {
+    let db = db().await?;
+    let email = email(db).await?;
+    let alice = Client::create("alice@example.com", db).await?;
+    let bob = Client::create("bob@example.com", db).await?;
+
+    let mut tokens = Vec::new();
+    for client in [&alice, &bob] {
+        let mut delivery = Delivery::new(&email, client);
+        let html = delivery.track_opens("<html><body><p>Hello</p></body></html>", BASE_URL);
+        assert!(html.contains(&format!(
+            r#"<img src="https://t.example.com/o/{}.gif" width="1" height="1" alt="""#,
+            delivery.token()
+        )));
+        assert!(html.ends_with("\n</body></html>"));
+
+        delivery.write(0, db).await?;
+        tokens.push(delivery.token().to_owned());
+    }
+
+    // Without the pixel, the delivery has no opens to record
+    let untracked = Delivery::new(&email, &alice);
+    untracked.write(0, db).await?;
+
+    let address = serve(db).await?;
+
+    for token in [&tokens[0], &tokens[0], &tokens[1]] {
+        let response = request(&address, "GET", &format!("/o/{token}.gif")).await?;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\r\nContent-Type: image/gif\r\n"));
+        assert!(response.ends_with(";"));
+    }
+
+    // Unknown tokens still get the pixel, but nothing is recorded
+    for path in [
+        "/o/unknown.gif".to_owned(),
+        format!("/o/{}.gif", untracked.token()),
+    ] {
+        let response = request(&address, "GET", &path).await?;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{path}");
+    }
+    let response = request(&address, "HEAD", &format!("/o/{}.gif", tokens[1])).await?;
+    assert!(response.ends_with("\r\n\r\n"));
+
+    let report = OpenReport::for_email(email.id(), db).await?;
+    assert_eq!(report.recipients(), 2);
+    assert_eq!(report.opens(), 3);
+    assert_eq!(report.openers(), 2);
+
+    let reports = OpenReport::list(db).await?;
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].email_id(), email.id());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn tracking_preferences_are_saved() -> Result<()> {
+    let db = db().await?;
+
+    let email = EmailBuilder::new()
+        .sender_adresse("sender@example.com")?
+        .subject("News")
+        .plain_body("Hello")
+        .track_opens()
+        .create(db)
+        .await?;
+    let mut loaded = Email::get_one(email.id(), db)
+        .await?
+        .expect("email is saved");
+    assert!(loaded.tracks_opens());
+    loaded.set_track_opens(false, db).await?;
+    assert!(!Email::get_one(email.id(), db)
+        .await?
+        .unwrap()
+        .tracks_opens());
+
+    let mut client = Client::create("client@example.com", db).await?;
+    assert!(!client.do_not_track());
+    client.set_do_not_track(true, db).await?;
+    let loaded = Client::get_one(client.id().to_owned(), db)
+        .await?
+        .expect("client is saved");
+    assert!(loaded.do_not_track());
+
+    Ok(())
+}